@@ -44,6 +44,7 @@ fn main() {
     }
 
     println!("cargo:rerun-if-changed=ts/lib.ts");
+    println!("cargo:rerun-if-changed=ts/protocol.d.ts");
 
     #[cfg(not(target_os = "windows"))]
     let mut tsc_command = Command::new("tsc");
@@ -54,8 +55,9 @@ fn main() {
     tsc_command.args(&["-c", "tsc"]);
 
     let js_needs_update = || -> Result<bool, Box<dyn std::error::Error>> {
-        Ok(Path::new("ts/lib.ts").metadata()?.modified()?
-            > Path::new("www/static/lib.js").metadata()?.modified()?)
+        let js_modified = Path::new("www/static/lib.js").metadata()?.modified()?;
+        Ok(Path::new("ts/lib.ts").metadata()?.modified()? > js_modified
+            || Path::new("ts/protocol.d.ts").metadata()?.modified()? > js_modified)
     }()
     .unwrap_or(true);
 
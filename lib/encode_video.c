@@ -73,6 +73,7 @@ typedef struct VideoContext
 	int try_nvenc;
 	int try_videotoolbox;
 	int try_mediafoundation;
+	int annexb;
 } VideoContext;
 
 // this is a rust function and lives in src/video.rs
@@ -487,10 +488,13 @@ void open_video(VideoContext* ctx, Error* err)
 	const AVCodec* codec;
 	int ret;
 
-	avformat_alloc_output_context2(&ctx->oc, NULL, "mp4", NULL);
+	// raw H.264 in Annex B format is used by transports that packetize the video themselves,
+	// fragmented mp4 otherwise
+	const char* format_name = ctx->annexb ? "h264" : "mp4";
+	avformat_alloc_output_context2(&ctx->oc, NULL, format_name, NULL);
 	if (!ctx->oc)
 	{
-		ERROR(err, 1, "Could not find output format mp4.");
+		ERROR(err, 1, "Could not find output format %s.", format_name);
 	}
 
 	int using_hw = 0;
@@ -785,7 +789,8 @@ void open_video(VideoContext* ctx, Error* err)
 	AVDictionary* opt = NULL;
 
 	// enable writing fragmented mp4
-	av_dict_set(&opt, "movflags", "frag_custom+empty_moov+default_base_moof", 0);
+	if (!ctx->annexb)
+		av_dict_set(&opt, "movflags", "frag_custom+empty_moov+default_base_moof", 0);
 	ret = avformat_write_header(ctx->oc, &opt);
 	if (ret < 0)
 		log_warn("Video: failed to write header!");
@@ -859,8 +864,12 @@ void encode_video_frame(VideoContext* ctx, int millis, Error* err)
 		av_write_frame(ctx->oc, ctx->pkt);
 		av_packet_unref(ctx->pkt);
 
-		// new fragment on every frame for lowest latency
-		av_write_frame(ctx->oc, NULL);
+		if (ctx->annexb)
+			// hand out every access unit as soon as it is encoded
+			avio_flush(ctx->oc->pb);
+		else
+			// new fragment on every frame for lowest latency
+			av_write_frame(ctx->oc, NULL);
 	}
 }
 
@@ -873,7 +882,8 @@ VideoContext* init_video_encoder(
 	int try_vaapi,
 	int try_nvenc,
 	int try_videotoolbox,
-	int try_mediafoundation)
+	int try_mediafoundation,
+	int annexb)
 {
 	VideoContext* ctx = malloc(sizeof(VideoContext));
 	ctx->rust_ctx = rust_ctx;
@@ -888,6 +898,7 @@ VideoContext* init_video_encoder(
 	ctx->try_nvenc = try_nvenc;
 	ctx->try_videotoolbox = try_videotoolbox;
 	ctx->try_mediafoundation = try_mediafoundation;
+	ctx->annexb = annexb;
 	ctx->hw_device_ctx = NULL;
 
 	// make sure all scalers are zero initialized so that destroy can always be called
@@ -1,11 +1,14 @@
 #include <X11/X.h>
+#include <X11/XKBlib.h>
 #include <X11/Xlib.h>
 #include <X11/extensions/XInput.h>
 #include <X11/extensions/XInput2.h>
+#include <X11/extensions/Xfixes.h>
 #include <X11/extensions/Xrandr.h>
 #include <X11/extensions/randr.h>
 #include <stdlib.h>
 #include <string.h>
+#include <sys/select.h>
 
 #include "../error.h"
 #include "../log.h"
@@ -263,6 +266,285 @@ int create_capturables(
 	return i;
 }
 
+typedef struct CapturableWatcher
+{
+	Display* disp;
+	int has_randr;
+	int randr_event_base;
+	Atom net_client_list;
+	Atom win_client_list;
+	Atom net_wm_name;
+} CapturableWatcher;
+
+// window titles are properties of the windows themselves, so every window has to be watched
+void watch_client_windows(CapturableWatcher* w)
+{
+	Error err;
+	unsigned long client_list_size;
+	Window* client_list = get_client_list(w->disp, &client_list_size, &err);
+	if (!client_list)
+		return;
+	for (size_t i = 0; i < client_list_size / sizeof(Window); ++i)
+		XSelectInput(w->disp, client_list[i], PropertyChangeMask);
+	free(client_list);
+}
+
+CapturableWatcher* create_capturable_watcher(Error* err)
+{
+	// use a separate connection, the one used for capturing must not be blocked while waiting for
+	// events
+	Display* disp = XOpenDisplay(NULL);
+	if (!disp)
+	{
+		fill_error(err, 1, "Failed to open display.");
+		return NULL;
+	}
+	CapturableWatcher* w = malloc(sizeof(CapturableWatcher));
+	w->disp = disp;
+	w->net_client_list = XInternAtom(disp, "_NET_CLIENT_LIST", False);
+	w->win_client_list = XInternAtom(disp, "_WIN_CLIENT_LIST", False);
+	w->net_wm_name = XInternAtom(disp, "_NET_WM_NAME", False);
+
+	Window root = DefaultRootWindow(disp);
+	// the client list is a property of the root window, so this catches windows being opened and
+	// closed
+	XSelectInput(disp, root, PropertyChangeMask);
+
+	int error_base, major, minor;
+	w->has_randr = XRRQueryExtension(disp, &w->randr_event_base, &error_base) &&
+				   XRRQueryVersion(disp, &major, &minor);
+	if (w->has_randr)
+		XRRSelectInput(
+			disp,
+			root,
+			RRScreenChangeNotifyMask | RRCrtcChangeNotifyMask | RROutputChangeNotifyMask);
+	else
+		log_debug("Xrandr is unsupported on this X server, monitor changes are not detected.");
+
+	watch_client_windows(w);
+	XFlush(disp);
+	return w;
+}
+
+// Wait at most timeout_ms milliseconds for events to arrive, returns 0 if there are none.
+int wait_for_events(Display* disp, int timeout_ms)
+{
+	if (XPending(disp))
+		return 1;
+	int fd = ConnectionNumber(disp);
+	fd_set fds;
+	FD_ZERO(&fds);
+	FD_SET(fd, &fds);
+	struct timeval timeout = {timeout_ms / 1000, (timeout_ms % 1000) * 1000};
+	return select(fd + 1, &fds, NULL, NULL, &timeout) > 0;
+}
+
+// Wait at most timeout_ms milliseconds for windows being opened, closed, renamed or monitors being
+// changed. Returns 1 if anything changed, 0 otherwise.
+int wait_for_capturable_change(CapturableWatcher* w, int timeout_ms)
+{
+	if (!wait_for_events(w->disp, timeout_ms))
+		return 0;
+
+	int changed = 0;
+	while (XPending(w->disp))
+	{
+		XEvent event;
+		XNextEvent(w->disp, &event);
+		if (event.type == PropertyNotify)
+		{
+			Atom atom = event.xproperty.atom;
+			if (atom == w->net_client_list || atom == w->win_client_list)
+			{
+				changed = 1;
+				watch_client_windows(w);
+			}
+			else if (atom == w->net_wm_name || atom == XA_WM_NAME)
+				changed = 1;
+		}
+		else if (
+			w->has_randr && (event.type == w->randr_event_base + RRScreenChangeNotify ||
+							 event.type == w->randr_event_base + RRNotify))
+			changed = 1;
+	}
+	return changed;
+}
+
+void destroy_capturable_watcher(CapturableWatcher* w)
+{
+	XCloseDisplay(w->disp);
+	free(w);
+}
+
+typedef struct ClipboardWatcher
+{
+	Display* disp;
+	int xfixes_event_base;
+	Atom clipboard;
+} ClipboardWatcher;
+
+ClipboardWatcher* create_clipboard_watcher(Error* err)
+{
+	Display* disp = XOpenDisplay(NULL);
+	if (!disp)
+	{
+		fill_error(err, 1, "Failed to open display.");
+		return NULL;
+	}
+	int event_base, error_base;
+	if (!XFixesQueryExtension(disp, &event_base, &error_base))
+	{
+		XCloseDisplay(disp);
+		fill_error(err, 1, "XFixes is unsupported on this X server, can not watch the clipboard.");
+		return NULL;
+	}
+	ClipboardWatcher* w = malloc(sizeof(ClipboardWatcher));
+	w->disp = disp;
+	w->xfixes_event_base = event_base;
+	w->clipboard = XInternAtom(disp, "CLIPBOARD", False);
+	// every copy makes the copying application the new owner of the selection, even if it already
+	// owned it
+	XFixesSelectSelectionInput(
+		disp, DefaultRootWindow(disp), w->clipboard, XFixesSetSelectionOwnerNotifyMask);
+	XFlush(disp);
+	return w;
+}
+
+// Wait at most timeout_ms milliseconds for something being copied to the clipboard. Returns 1 if
+// the clipboard changed, 0 otherwise.
+int wait_for_clipboard_change(ClipboardWatcher* w, int timeout_ms)
+{
+	if (!wait_for_events(w->disp, timeout_ms))
+		return 0;
+
+	int changed = 0;
+	while (XPending(w->disp))
+	{
+		XEvent event;
+		XNextEvent(w->disp, &event);
+		if (event.type == w->xfixes_event_base + XFixesSelectionNotify &&
+			((XFixesSelectionNotifyEvent*)&event)->selection == w->clipboard)
+			changed = 1;
+	}
+	return changed;
+}
+
+void destroy_clipboard_watcher(ClipboardWatcher* w)
+{
+	XCloseDisplay(w->disp);
+	free(w);
+}
+
+typedef struct CursorWatcher
+{
+	Display* disp;
+	int xfixes_event_base;
+	int shape_changed;
+	// RGBA pixels of the last cursor image
+	unsigned char* image;
+	size_t image_size;
+} CursorWatcher;
+
+CursorWatcher* create_cursor_watcher(Error* err)
+{
+	Display* disp = XOpenDisplay(NULL);
+	if (!disp)
+	{
+		fill_error(err, 1, "Failed to open display.");
+		return NULL;
+	}
+	int event_base, error_base;
+	if (!XFixesQueryExtension(disp, &event_base, &error_base))
+	{
+		XCloseDisplay(disp);
+		fill_error(err, 1, "XFixes is unsupported on this X server, can not watch the cursor.");
+		return NULL;
+	}
+	CursorWatcher* w = malloc(sizeof(CursorWatcher));
+	w->disp = disp;
+	w->xfixes_event_base = event_base;
+	// the client does not know the current shape yet
+	w->shape_changed = 1;
+	w->image = NULL;
+	w->image_size = 0;
+	XFixesSelectCursorInput(disp, DefaultRootWindow(disp), XFixesDisplayCursorNotifyMask);
+	XFlush(disp);
+	return w;
+}
+
+// Returns 1 if the shape of the cursor changed since the last call to get_cursor_image.
+int cursor_shape_changed(CursorWatcher* w)
+{
+	while (XPending(w->disp))
+	{
+		XEvent event;
+		XNextEvent(w->disp, &event);
+		if (event.type == w->xfixes_event_base + XFixesCursorNotify)
+			w->shape_changed = 1;
+	}
+	return w->shape_changed;
+}
+
+// Get the position of the cursor in pixels relative to the root window along with the size of the
+// screen. Returns 0 if the cursor is on another screen.
+int get_cursor_position(CursorWatcher* w, int* x, int* y, int* screen_width, int* screen_height)
+{
+	Window root, child;
+	int win_x, win_y;
+	unsigned int mask;
+	*screen_width = DisplayWidth(w->disp, DefaultScreen(w->disp));
+	*screen_height = DisplayHeight(w->disp, DefaultScreen(w->disp));
+	return XQueryPointer(
+		w->disp, DefaultRootWindow(w->disp), &root, &child, x, y, &win_x, &win_y, &mask);
+}
+
+// Get the current image of the cursor as RGBA pixels. The pixels are owned by the watcher and
+// valid until the next call.
+unsigned char* get_cursor_image(
+	CursorWatcher* w, unsigned int* width, unsigned int* height, int* xhot, int* yhot, Error* err)
+{
+	XFixesCursorImage* cursor_img = XFixesGetCursorImage(w->disp);
+	if (!cursor_img)
+	{
+		fill_error(err, 1, "Failed to obtain cursor image.");
+		return NULL;
+	}
+	size_t size = (size_t)cursor_img->width * cursor_img->height * 4;
+	if (size > w->image_size)
+	{
+		free(w->image);
+		w->image = malloc(size);
+		w->image_size = size;
+	}
+	for (size_t i = 0; i < (size_t)cursor_img->width * cursor_img->height; ++i)
+	{
+		// pixels are stored as premultiplied ARGB in unsigned longs
+		unsigned long pixel = cursor_img->pixels[i];
+		unsigned char a = (pixel >> 24) & 0xff;
+		unsigned char* p = w->image + i * 4;
+		for (int c = 0; c < 3; ++c)
+		{
+			unsigned char v = (pixel >> (16 - 8 * c)) & 0xff;
+			p[c] = a ? (v >= a ? 255 : v * 255 / a) : 0;
+		}
+		p[3] = a;
+	}
+	*width = cursor_img->width;
+	*height = cursor_img->height;
+	*xhot = cursor_img->xhot;
+	*yhot = cursor_img->yhot;
+	XFree(cursor_img);
+	w->shape_changed = 0;
+	return w->image;
+}
+
+void destroy_cursor_watcher(CursorWatcher* w)
+{
+	XCloseDisplay(w->disp);
+	free(w->image);
+	free(w);
+}
+
 void* clone_capturable(Capturable* c)
 {
 	Capturable* c2 = malloc(sizeof(Capturable));
@@ -407,6 +689,24 @@ void capturable_before_input(Capturable* cap, Error* err)
 
 const char* get_capturable_name(Capturable* c) { return c->name; }
 
+// Identifier that stays the same as long as the window or monitor exists, in contrast to the
+// position in the list returned by create_capturables.
+void get_capturable_id(Capturable* c, char* buf, size_t size)
+{
+	switch (c->type)
+	{
+	case WINDOW:
+		if (c->c.winfo.is_regular_window)
+			snprintf(buf, size, "x11:window:%lu", c->c.winfo.win);
+		else
+			snprintf(buf, size, "x11:desktop");
+		break;
+	case RECT:
+		snprintf(buf, size, "x11:%s", c->name);
+		break;
+	}
+}
+
 void map_input_device_to_entire_screen(Display* disp, const char* device_name, int pen, Error* err)
 {
 
@@ -503,3 +803,53 @@ void map_input_device_to_entire_screen(Display* disp, const char* device_name, i
 
 	XFree(data.c);
 }
+
+// Look up the keycode that types keysym, either as is or with shift held. Returns 0 if keysym can
+// not be typed that way with the current keyboard mapping.
+int keysym_to_keycode(Display* disp, unsigned long keysym, int* shift)
+{
+	KeyCode keycode = XKeysymToKeycode(disp, keysym);
+	if (!keycode)
+		return 0;
+	for (int level = 0; level < 2; ++level)
+		if (XkbKeycodeToKeysym(disp, keycode, 0, level) == keysym)
+		{
+			*shift = level;
+			return keycode;
+		}
+	// only reachable via AltGr or another group
+	return 0;
+}
+
+// Write up to size keycodes without any keysyms bound to them to keycodes, returns how many have
+// been found.
+int get_unused_keycodes(Display* disp, int* keycodes, int size)
+{
+	int min_keycode, max_keycode, keysyms_per_keycode;
+	XDisplayKeycodes(disp, &min_keycode, &max_keycode);
+	KeySym* keysyms = XGetKeyboardMapping(
+		disp, min_keycode, max_keycode - min_keycode + 1, &keysyms_per_keycode);
+	if (!keysyms)
+		return 0;
+	int n = 0;
+	// high keycodes are the least likely to be taken by anything else
+	for (int keycode = max_keycode; keycode >= min_keycode && n < size; --keycode)
+	{
+		int unused = 1;
+		for (int i = 0; i < keysyms_per_keycode; ++i)
+			if (keysyms[(keycode - min_keycode) * keysyms_per_keycode + i] != NoSymbol)
+				unused = 0;
+		if (unused)
+			keycodes[n++] = keycode;
+	}
+	XFree(keysyms);
+	return n;
+}
+
+// Bind keysym to keycode with and without shift, NoSymbol removes the binding.
+void bind_keysym(Display* disp, int keycode, unsigned long keysym)
+{
+	KeySym keysyms[2] = {keysym, keysym};
+	XChangeKeyboardMapping(disp, keycode, 2, keysyms, 1);
+	XSync(disp, False);
+}
@@ -0,0 +1,131 @@
+//! Host-side approval of new clients: if enabled, the host has to accept each new connection
+//! before any of its messages are processed.
+
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tracing::{info, warn};
+
+use crate::web::Web2UiMessage;
+use crate::websocket::ApproveClient;
+
+/// Requests the host neither accepts nor rejects within this time are rejected.
+pub const APPROVAL_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Addresses of a server's clients with a request waiting for an answer, each may only have one
+/// at a time so that a client can not flood the host with requests. Clients connected via a Unix
+/// socket share a single one.
+#[derive(Default)]
+pub struct PendingApprovals {
+    pending: Mutex<Vec<Option<IpAddr>>>,
+}
+
+impl PendingApprovals {
+    /// Returns false if `ip` has a request waiting already.
+    fn insert(&self, ip: Option<IpAddr>) -> bool {
+        let mut pending = self.pending.lock().unwrap();
+        if pending.contains(&ip) {
+            return false;
+        }
+        pending.push(ip);
+        true
+    }
+
+    fn remove(&self, ip: Option<IpAddr>) {
+        self.pending
+            .lock()
+            .unwrap()
+            .retain(|pending| *pending != ip);
+    }
+}
+
+/// A client waiting for the host to accept or reject it.
+pub struct ApprovalRequest {
+    pub address: String,
+    pub client_name: Option<String>,
+    answer: mpsc::Sender<bool>,
+    expires: Instant,
+}
+
+impl ApprovalRequest {
+    /// Time left until the request is rejected because nobody answered.
+    pub fn remaining(&self) -> Duration {
+        self.expires.saturating_duration_since(Instant::now())
+    }
+
+    pub fn answer(self, accept: bool) {
+        // fails if the request timed out already
+        if self.answer.send(accept).is_err() {
+            warn!("Client {self} has stopped waiting for approval.");
+        }
+    }
+}
+
+impl fmt::Display for ApprovalRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.client_name {
+            Some(name) => write!(f, "\"{name}\" ({})", self.address),
+            None => write!(f, "{}", self.address),
+        }
+    }
+}
+
+/// Callback for the WeylusClientHandler that asks the host to accept the client at `address`,
+/// `ip` is its IP if it connected via the network.
+pub fn approver(
+    sender_ui: tokio::sync::mpsc::Sender<Web2UiMessage>,
+    pending: Arc<PendingApprovals>,
+    address: String,
+    ip: Option<IpAddr>,
+) -> ApproveClient {
+    Box::new(move |client_name| {
+        let ip = ip.map(|ip| ip.to_canonical());
+        if !pending.insert(ip) {
+            info!(
+                "Rejected client {address}, another client from the same address is waiting for \
+                approval."
+            );
+            return false;
+        }
+        let accepted = request_approval(&sender_ui, address, client_name);
+        pending.remove(ip);
+        accepted
+    })
+}
+
+/// Ask the host to accept the client, blocks until the host answered or the request timed out.
+fn request_approval(
+    sender_ui: &tokio::sync::mpsc::Sender<Web2UiMessage>,
+    address: String,
+    client_name: Option<String>,
+) -> bool {
+    let (answer, receiver) = mpsc::channel();
+    let request = ApprovalRequest {
+        address,
+        client_name,
+        answer,
+        expires: Instant::now() + APPROVAL_TIMEOUT,
+    };
+    let client = request.to_string();
+    info!("Client {client} is waiting for approval.");
+    if let Err(err) = sender_ui.blocking_send(Web2UiMessage::ApprovalRequest(request)) {
+        warn!("Failed to send approval request: {err}.");
+        return false;
+    }
+    match receiver.recv_timeout(APPROVAL_TIMEOUT) {
+        Ok(true) => {
+            info!("Accepted client {client}.");
+            true
+        }
+        Ok(false) => {
+            info!("Rejected client {client}.");
+            false
+        }
+        Err(_) => {
+            info!("Rejected client {client}, nobody answered in time.");
+            false
+        }
+    }
+}
@@ -0,0 +1,80 @@
+//! Capture the host's audio output via GStreamer and encode it as AAC in fragmented MP4, which
+//! browsers can play via Media Source Extensions just like the video.
+//!
+//! By default whatever is played on the default PulseAudio/PipeWire sink is captured, the source
+//! can be replaced by any GStreamer source via `WEYLUS_AUDIO_SOURCE`, for example
+//! `audiotestsrc is-live=true` for testing.
+
+use std::error::Error;
+
+use gstreamer as gst;
+use gstreamer::prelude::*;
+use gstreamer_app::{AppSink, AppSinkCallbacks};
+use tracing::debug;
+
+const DEFAULT_SOURCE: &str = "pulsesrc device=@DEFAULT_MONITOR@";
+
+// in order of preference, availability depends on the installed GStreamer plugins
+const AAC_ENCODERS: [&str; 3] = ["fdkaacenc", "avenc_aac", "voaacenc"];
+
+pub struct AudioStream {
+    pipeline: gst::Pipeline,
+}
+
+impl AudioStream {
+    /// Start capturing audio, `write_data` is called from a GStreamer thread with chunks of the
+    /// MP4 stream, starting with its header.
+    pub fn new<F>(mut write_data: F) -> Result<Self, Box<dyn Error>>
+    where
+        F: FnMut(&[u8]) + Send + 'static,
+    {
+        let source =
+            std::env::var("WEYLUS_AUDIO_SOURCE").unwrap_or_else(|_| DEFAULT_SOURCE.to_string());
+        let encoder = AAC_ENCODERS
+            .iter()
+            .find(|name| gst::ElementFactory::find(name).is_some())
+            .ok_or_else(|| {
+                format!(
+                    "No AAC encoder found, install a GStreamer plugin providing one of: {}.",
+                    AAC_ENCODERS.join(", ")
+                )
+            })?;
+        let description = format!(
+            "{source} ! audioconvert ! audioresample ! audio/x-raw,rate=48000,channels=2 \
+             ! {encoder} ! mp4mux streamable=true fragment-duration=100 \
+             ! appsink name=sink sync=false"
+        );
+        debug!("Audio pipeline: {description}");
+
+        let pipeline = gst::parse::launch(&description)?
+            .downcast::<gst::Pipeline>()
+            .map_err(|_| "Audio pipeline is expected to be a pipeline!")?;
+        let appsink = pipeline
+            .by_name("sink")
+            .and_then(|sink| sink.downcast::<AppSink>().ok())
+            .ok_or("Sink element is expected to be an appsink!")?;
+        appsink.set_callbacks(
+            AppSinkCallbacks::builder()
+                .new_sample(move |appsink| {
+                    let sample = appsink.pull_sample().map_err(|_| gst::FlowError::Eos)?;
+                    let buffer = sample.buffer().ok_or(gst::FlowError::Error)?;
+                    let map = buffer.map_readable().map_err(|_| gst::FlowError::Error)?;
+                    write_data(map.as_slice());
+                    Ok(gst::FlowSuccess::Ok)
+                })
+                .build(),
+        );
+
+        if let Err(err) = pipeline.set_state(gst::State::Playing) {
+            let _ = pipeline.set_state(gst::State::Null);
+            return Err(err.into());
+        }
+        Ok(Self { pipeline })
+    }
+}
+
+impl Drop for AudioStream {
+    fn drop(&mut self) {
+        let _ = self.pipeline.set_state(gst::State::Null);
+    }
+}
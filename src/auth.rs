@@ -0,0 +1,280 @@
+//! Authentication of web clients: clients log in with the access code and are given a session,
+//! which is kept in a cookie by the browser. Clients logging in with the view-only access code may
+//! watch but not control the computer.
+//!
+//! The access code is protected against brute-forcing: codes are compared in constant time and
+//! addresses that repeatedly send wrong codes have to wait exponentially longer before they may
+//! try again, until they are locked out for a while.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+// failed attempts that are not penalized, to allow for typos
+const FREE_ATTEMPTS: u32 = 3;
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+const LOCKOUT_ATTEMPTS: u32 = 10;
+const LOCKOUT_DURATION: Duration = Duration::from_secs(15 * 60);
+// failed attempts are forgotten if there has been none for this long
+const FORGET_AFTER: Duration = Duration::from_secs(60 * 60);
+
+/// Random token of `len` bytes encoded as hex.
+pub fn random_token(len: usize) -> String {
+    let mut bytes = vec![0u8; len];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Compare access codes in constant time, so that the time taken does not reveal how much of the
+/// code is correct.
+pub fn access_code_matches(given: &[u8], expected: &[u8]) -> bool {
+    // comparing hashes hides the length of the access code as well
+    let given = Sha256::digest(given);
+    let expected = Sha256::digest(expected);
+    let diff = given
+        .iter()
+        .zip(expected.iter())
+        .fold(0u8, |diff, (a, b)| diff | (a ^ b));
+    std::hint::black_box(diff) == 0
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    /// Full access including input.
+    Control,
+    /// Receive video only, any input is dropped.
+    View,
+}
+
+/// Permission granted by `code`, `None` if it matches neither the access code nor the view-only
+/// access code.
+pub fn check_access_code(
+    code: &[u8],
+    access_code: Option<&str>,
+    view_access_code: Option<&str>,
+) -> Option<Permission> {
+    if access_code.is_some_and(|c| access_code_matches(code, c.as_bytes())) {
+        Some(Permission::Control)
+    } else if view_access_code.is_some_and(|c| access_code_matches(code, c.as_bytes())) {
+        Some(Permission::View)
+    } else {
+        None
+    }
+}
+
+struct FailedAttempts {
+    count: u32,
+    last: Instant,
+}
+
+impl FailedAttempts {
+    fn blocked_until(&self) -> Instant {
+        let wait = if self.count >= LOCKOUT_ATTEMPTS {
+            LOCKOUT_DURATION
+        } else if self.count > FREE_ATTEMPTS {
+            let exponent = (self.count - FREE_ATTEMPTS - 1).min(16);
+            (Duration::from_secs(1) * 2u32.pow(exponent)).min(MAX_BACKOFF)
+        } else {
+            Duration::ZERO
+        };
+        self.last + wait
+    }
+}
+
+/// Keeps track of failed attempts to authenticate per remote address.
+#[derive(Default)]
+pub struct AuthLimiter {
+    failed: Mutex<HashMap<IpAddr, FailedAttempts>>,
+}
+
+impl AuthLimiter {
+    /// Start an attempt of `ip` to authenticate. The attempt counts as failed right away, so
+    /// concurrent attempts can not get around the limit, until `record_success` is called.
+    ///
+    /// Returns the number of failed attempts including this one or, if `ip` may not try right
+    /// now, how long it has to wait.
+    pub fn attempt(&self, ip: IpAddr) -> Result<u32, Duration> {
+        let now = Instant::now();
+        let mut failed = self.failed.lock().unwrap();
+        failed.retain(|_, attempts| now.duration_since(attempts.last) < FORGET_AFTER);
+        let attempts = failed.entry(key(ip)).or_insert(FailedAttempts {
+            count: 0,
+            last: now,
+        });
+        let wait = attempts.blocked_until().saturating_duration_since(now);
+        if !wait.is_zero() {
+            return Err(wait);
+        }
+        attempts.count += 1;
+        attempts.last = now;
+        Ok(attempts.count)
+    }
+
+    /// The attempt of `ip` succeeded, forget its failed attempts.
+    pub fn record_success(&self, ip: IpAddr) {
+        self.failed.lock().unwrap().remove(&key(ip));
+    }
+}
+
+/// Sessions of clients that logged in with the access code.
+pub struct LoginSessions {
+    lifetime: Duration,
+    sessions: Mutex<HashMap<String, (Instant, Permission)>>,
+}
+
+impl LoginSessions {
+    pub fn new(lifetime: Duration) -> Self {
+        Self {
+            lifetime,
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn lifetime(&self) -> Duration {
+        self.lifetime
+    }
+
+    /// Start a new session, returns its token.
+    pub fn create(&self, permission: Permission) -> String {
+        let token = random_token(32);
+        self.sessions
+            .lock()
+            .unwrap()
+            .insert(token.clone(), (Instant::now(), permission));
+        token
+    }
+
+    /// Permission of the session, `None` if there is no such session or it expired.
+    pub fn permission(&self, token: &str) -> Option<Permission> {
+        self.sessions
+            .lock()
+            .unwrap()
+            .get(token)
+            .filter(|(created, _)| created.elapsed() < self.lifetime)
+            .map(|(_, permission)| *permission)
+    }
+
+    /// Forget expired sessions, returns their tokens.
+    pub fn remove_expired(&self) -> Vec<String> {
+        let mut expired = Vec::new();
+        self.sessions.lock().unwrap().retain(|token, (created, _)| {
+            let valid = created.elapsed() < self.lifetime;
+            if !valid {
+                expired.push(token.clone());
+            }
+            valid
+        });
+        expired
+    }
+
+    pub fn remove(&self, token: &str) {
+        self.sessions.lock().unwrap().remove(token);
+    }
+}
+
+/// IPv6 hosts usually get a whole /64 subnet, so it is treated as one address, otherwise
+/// attackers could simply switch to a new address.
+fn key(ip: IpAddr) -> IpAddr {
+    match ip.to_canonical() {
+        IpAddr::V6(ip) => IpAddr::V6((u128::from(ip) & !(u64::MAX as u128)).into()),
+        ip => ip,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Barrier;
+    use std::thread;
+
+    fn wait_after(count: u32) -> Duration {
+        let last = Instant::now();
+        FailedAttempts { count, last }.blocked_until() - last
+    }
+
+    #[test]
+    fn blocked_until() {
+        for count in 0..=FREE_ATTEMPTS {
+            assert_eq!(wait_after(count), Duration::ZERO);
+        }
+        assert_eq!(wait_after(FREE_ATTEMPTS + 1), Duration::from_secs(1));
+        assert_eq!(wait_after(FREE_ATTEMPTS + 2), Duration::from_secs(2));
+        assert_eq!(wait_after(FREE_ATTEMPTS + 3), Duration::from_secs(4));
+        for count in FREE_ATTEMPTS + 1..LOCKOUT_ATTEMPTS {
+            assert!(wait_after(count) <= MAX_BACKOFF);
+            assert!(wait_after(count) < wait_after(count + 1));
+        }
+        assert_eq!(wait_after(LOCKOUT_ATTEMPTS), LOCKOUT_DURATION);
+        assert_eq!(wait_after(u32::MAX), LOCKOUT_DURATION);
+    }
+
+    #[test]
+    fn limiter() {
+        let limiter = AuthLimiter::default();
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+        let other: IpAddr = "192.0.2.2".parse().unwrap();
+        for attempts in 1..=FREE_ATTEMPTS + 1 {
+            assert_eq!(limiter.attempt(ip), Ok(attempts));
+        }
+        assert!(limiter
+            .attempt(ip)
+            .is_err_and(|wait| wait <= Duration::from_secs(1)));
+        assert_eq!(limiter.attempt(other), Ok(1));
+        limiter.record_success(ip);
+        assert_eq!(limiter.attempt(ip), Ok(1));
+        limiter.record_success(ip);
+        assert_eq!(limiter.attempt(ip), Ok(1));
+    }
+
+    #[test]
+    fn concurrent_attempts() {
+        let limiter = AuthLimiter::default();
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+        let barrier = Barrier::new(4 * LOCKOUT_ATTEMPTS as usize);
+        // all attempts start before any of them fails
+        let allowed = thread::scope(|s| {
+            let threads: Vec<_> = (0..4 * LOCKOUT_ATTEMPTS)
+                .map(|_| {
+                    s.spawn(|| {
+                        barrier.wait();
+                        limiter.attempt(ip).is_ok()
+                    })
+                })
+                .collect();
+            threads
+                .into_iter()
+                .map(|thread| thread.join().unwrap())
+                .filter(|&allowed| allowed)
+                .count()
+        });
+        assert_eq!(allowed, FREE_ATTEMPTS as usize + 1);
+    }
+
+    #[test]
+    fn ipv6_subnet_shares_attempts() {
+        let limiter = AuthLimiter::default();
+        let ip: IpAddr = "2001:db8:1:2::1".parse().unwrap();
+        for _ in 0..=FREE_ATTEMPTS {
+            limiter.attempt(ip).unwrap();
+        }
+        let same_subnet: IpAddr = "2001:db8:1:2:ffff:ffff:ffff:ffff".parse().unwrap();
+        let other_subnet: IpAddr = "2001:db8:1:3::1".parse().unwrap();
+        assert!(limiter.attempt(same_subnet).is_err());
+        assert_eq!(limiter.attempt(other_subnet), Ok(1));
+    }
+
+    #[test]
+    fn key_canonicalizes_mapped_ipv4() {
+        let mapped: IpAddr = "::ffff:192.0.2.1".parse().unwrap();
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+        assert_eq!(key(mapped), ip);
+        assert_eq!(
+            key("2001:db8::1".parse().unwrap()),
+            key("2001:db8::2".parse().unwrap())
+        );
+    }
+}
@@ -29,6 +29,9 @@ impl Capturable for CaptrsCapturable {
     fn name(&self) -> String {
         format!("Desktop {} (captrs)", self.name).into()
     }
+    fn id(&self) -> String {
+        format!("captrs:{}", self.name)
+    }
     fn before_input(&mut self) -> Result<(), Box<dyn Error>> {
         Ok(())
     }
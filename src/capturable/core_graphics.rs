@@ -52,6 +52,9 @@ impl Capturable for CGDisplayCapturable {
             self.display.pixels_high()
         )
     }
+    fn id(&self) -> String {
+        format!("cg:display:{}", self.display.id)
+    }
     fn geometry(&self) -> Result<Geometry, Box<dyn Error>> {
         let bounds = self.display.bounds();
         let (x0, y0, w, h) = screen_coordsys()?;
@@ -175,6 +178,9 @@ impl Capturable for CGWindowCapturable {
     fn name(&self) -> String {
         self.name.clone()
     }
+    fn id(&self) -> String {
+        format!("cg:window:{}", self.id)
+    }
     fn geometry(&self) -> Result<Geometry, Box<dyn Error>> {
         let (x, y, w, h) = self.geometry_relative;
         Ok(Geometry::Relative(x, y, w, h))
@@ -1,5 +1,6 @@
 use std::boxed::Box;
 use std::error::Error;
+use std::time::Duration;
 use tracing::warn;
 
 #[cfg(target_os = "macos")]
@@ -46,6 +47,10 @@ pub trait Capturable: Send + BoxCloneCapturable {
     /// Name of the Capturable, for example the window title, if it is a window.
     fn name(&self) -> String;
 
+    /// Identifier that stays the same while the Capturable exists, even if the list of
+    /// capturables is refreshed and reordered, for example the X11 window ID.
+    fn id(&self) -> String;
+
     /// Return Geometry of the Capturable.
     fn geometry(&self) -> Result<Geometry, Box<dyn Error>>;
 
@@ -63,28 +68,46 @@ impl Clone for Box<dyn Capturable> {
     }
 }
 
-pub fn get_capturables(
-    #[cfg(target_os = "linux")] wayland_support: bool,
-    #[cfg(target_os = "linux")] capture_cursor: bool,
-) -> Vec<Box<dyn Capturable>> {
-    let mut capturables: Vec<Box<dyn Capturable>> = vec![];
+/// Watches for changes of the capturables returned by get_capturables, like windows being opened,
+/// closed or renamed and monitors being plugged in or removed.
+pub trait CapturableWatcher: Send {
+    /// Block for at most timeout and return true if the capturables changed in the meantime.
+    fn wait_for_change(&mut self, timeout: Duration) -> bool;
+}
+
+/// Return a watcher for the current platform if there is one.
+pub fn capturable_watcher() -> Option<Box<dyn CapturableWatcher>> {
     #[cfg(target_os = "linux")]
     {
-        if wayland_support {
-            use crate::capturable::pipewire::get_capturables as get_capturables_pw;
-            match get_capturables_pw(capture_cursor) {
-                Ok(captrs) => {
-                    for c in captrs {
-                        capturables.push(Box::new(c));
-                    }
-                }
-                Err(err) => warn!(
-                    "Failed to get list of capturables via dbus/pipewire: {}",
-                    err
-                ),
-            }
+        match x11::X11CapturableWatcher::new() {
+            Ok(watcher) => return Some(Box::new(watcher)),
+            Err(err) => warn!(
+                "Failed to watch for changes of windows and monitors: {}",
+                err
+            ),
         }
+    }
+    None
+}
 
+/// Request screen casts via the xdg-desktop-portal. This may show a dialog to the user, so only call
+/// it if the user asked for it.
+#[cfg(target_os = "linux")]
+pub fn get_screen_casts(capture_cursor: bool) -> Result<Vec<Box<dyn Capturable>>, Box<dyn Error>> {
+    Ok(pipewire::get_capturables(capture_cursor)?
+        .into_iter()
+        .map(|c| Box::new(c) as Box<dyn Capturable>)
+        .collect())
+}
+
+/// Returns all capturables that could be found and the errors that occurred while searching for
+/// them. Screen casts via PipeWire are not included, see get_screen_casts.
+pub fn get_capturables() -> (Vec<Box<dyn Capturable>>, Vec<Box<dyn Error>>) {
+    let mut capturables: Vec<Box<dyn Capturable>> = vec![];
+    #[allow(unused_mut)]
+    let mut errors: Vec<Box<dyn Error>> = vec![];
+    #[cfg(target_os = "linux")]
+    {
         use crate::capturable::x11::X11Context;
         let x11ctx = X11Context::new();
         if let Some(mut x11ctx) = x11ctx {
@@ -94,7 +117,10 @@ pub fn get_capturables(
                         capturables.push(Box::new(c));
                     }
                 }
-                Err(err) => warn!("Failed to get list of capturables via X11: {}", err),
+                Err(err) => {
+                    warn!("Failed to get list of capturables via X11: {}", err);
+                    errors.push(Box::new(err));
+                }
             }
         };
     }
@@ -109,7 +135,10 @@ pub fn get_capturables(
                     capturables.push(Box::new(c));
                 }
             }
-            Err(err) => warn!("Failed to get list of displays via CoreGraphics: {}", err),
+            Err(err) => {
+                warn!("Failed to get list of displays via CoreGraphics: {}", err);
+                errors.push(err);
+            }
         }
 
         match get_windows_cg() {
@@ -119,7 +148,10 @@ pub fn get_capturables(
                     capturables.push(Box::new(c));
                 }
             }
-            Err(err) => warn!("Failed to get list of windows via CoreGraphics: {}", err),
+            Err(err) => {
+                warn!("Failed to get list of windows via CoreGraphics: {}", err);
+                errors.push(err);
+            }
         }
     }
 
@@ -161,5 +193,5 @@ pub fn get_capturables(
         }
     }
 
-    capturables
+    (capturables, errors)
 }
@@ -54,6 +54,18 @@ impl std::fmt::Display for GStreamerError {
 
 impl Error for GStreamerError {}
 
+/// The user declined the request to share the screen.
+#[derive(Debug)]
+pub struct ScreenCastDenied;
+
+impl std::fmt::Display for ScreenCastDenied {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "User cancelled the screen cast request.")
+    }
+}
+
+impl Error for ScreenCastDenied {}
+
 #[derive(Clone)]
 pub struct PipeWireCapturable {
     // connection needs to be kept alive for recording
@@ -97,6 +109,10 @@ impl Capturable for PipeWireCapturable {
         format!("Pipewire {}, path: {}", type_str, self.path)
     }
 
+    fn id(&self) -> String {
+        format!("pipewire:{}:{}", self.source_type, self.path)
+    }
+
     fn geometry(&self) -> Result<Geometry, Box<dyn Error>> {
         Ok(Geometry::Relative(0.0, 0.0, 1.0, 1.0))
     }
@@ -287,7 +303,9 @@ where
             match r.response {
                 0 => {}
                 1 => {
-                    context.lock().unwrap().failure = true;
+                    let mut context = context.lock().unwrap();
+                    context.failure = true;
+                    context.denied = true;
                     warn!("DBus response: User cancelled interaction.");
                     return true;
                 }
@@ -363,6 +381,7 @@ struct CallBackContext {
     restore_token: Option<String>,
     has_remote_desktop: bool,
     failure: bool,
+    denied: bool,
 }
 
 fn on_create_session_response(
@@ -555,6 +574,7 @@ fn request_remote_desktop(
         restore_token: None,
         has_remote_desktop,
         failure: false,
+        denied: false,
     };
     let context = Arc::new(Mutex::new(context));
 
@@ -592,6 +612,8 @@ fn request_remote_desktop(
     let context = context.lock().unwrap();
     if context.fd.is_some() && !context.streams.is_empty() {
         Ok((conn, context.fd.clone().unwrap(), context.streams.clone()))
+    } else if context.denied {
+        Err(Box::new(ScreenCastDenied))
     } else {
         Err(Box::new(DBusError(
             "Failed to obtain screen capture.".into(),
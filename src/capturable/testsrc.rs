@@ -68,6 +68,12 @@ impl Capturable for TestCapturable {
             self.width, self.height, self.pixel_format
         )
     }
+    fn id(&self) -> String {
+        format!(
+            "testsrc:{}x{}:{:?}",
+            self.width, self.height, self.pixel_format
+        )
+    }
     fn geometry(&self) -> Result<Geometry, Box<dyn Error>> {
         Ok(Geometry::Relative(0.0, 0.0, 1.0, 1.0))
     }
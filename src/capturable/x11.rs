@@ -1,10 +1,11 @@
-use crate::capturable::{Capturable, Geometry, Recorder};
+use crate::capturable::{Capturable, CapturableWatcher, Geometry, Recorder};
 use crate::cerror::CError;
 use crate::video::PixelProvider;
 use std::ffi::{CStr, CString};
-use std::os::raw::{c_char, c_float, c_int, c_uint, c_void};
+use std::os::raw::{c_char, c_float, c_int, c_uint, c_ulong, c_void};
 use std::slice::from_raw_parts;
 use std::sync::Arc;
+use std::time::Duration;
 use std::{error::Error, fmt};
 
 use tracing::debug;
@@ -29,6 +30,7 @@ extern "C" {
     fn clone_capturable(handle: *const c_void) -> *mut c_void;
     fn destroy_capturable(handle: *mut c_void);
     fn get_capturable_name(handle: *const c_void) -> *const c_char;
+    fn get_capturable_id(handle: *const c_void, buf: *mut c_char, size: usize);
     fn capturable_before_input(handle: *mut c_void, err: *mut CError);
     fn get_geometry_relative(
         handle: *const c_void,
@@ -45,6 +47,9 @@ extern "C" {
         libinput: c_int,
         err: *mut CError,
     );
+    fn keysym_to_keycode(disp: *mut c_void, keysym: c_ulong, shift: *mut c_int) -> c_int;
+    fn get_unused_keycodes(disp: *mut c_void, keycodes: *mut c_int, size: c_int) -> c_int;
+    fn bind_keysym(disp: *mut c_void, keycode: c_int, keysym: c_ulong);
     fn start_capture(handle: *const c_void, ctx: *mut c_void, err: *mut CError) -> *mut c_void;
     fn capture_screen(
         handle: *mut c_void,
@@ -53,6 +58,10 @@ extern "C" {
         err: *mut CError,
     );
     fn stop_capture(handle: *mut c_void, err: *mut CError);
+
+    fn create_capturable_watcher(err: *mut CError) -> *mut c_void;
+    fn wait_for_capturable_change(handle: *mut c_void, timeout_ms: c_int) -> c_int;
+    fn destroy_capturable_watcher(handle: *mut c_void);
 }
 
 pub fn x11_init() {
@@ -95,6 +104,14 @@ impl Capturable for X11Capturable {
         }
     }
 
+    fn id(&self) -> String {
+        let mut buf = [0 as c_char; 256];
+        unsafe {
+            get_capturable_id(self.handle, buf.as_mut_ptr(), buf.len());
+            CStr::from_ptr(buf.as_ptr()).to_string_lossy().into()
+        }
+    }
+
     fn geometry(&self) -> Result<Geometry, Box<dyn Error>> {
         let mut x: c_float = 0.0;
         let mut y: c_float = 0.0;
@@ -188,6 +205,36 @@ impl Drop for XDisplay {
     }
 }
 
+/// Watches the X server for windows being opened, closed or renamed and monitors being changed.
+pub struct X11CapturableWatcher {
+    handle: *mut c_void,
+}
+
+unsafe impl Send for X11CapturableWatcher {}
+
+impl X11CapturableWatcher {
+    pub fn new() -> Result<Self, CError> {
+        let mut err = CError::new();
+        let handle = unsafe { create_capturable_watcher(&mut err) };
+        if err.is_err() {
+            return Err(err);
+        }
+        Ok(Self { handle })
+    }
+}
+
+impl CapturableWatcher for X11CapturableWatcher {
+    fn wait_for_change(&mut self, timeout: Duration) -> bool {
+        unsafe { wait_for_capturable_change(self.handle, timeout.as_millis() as c_int) != 0 }
+    }
+}
+
+impl Drop for X11CapturableWatcher {
+    fn drop(&mut self) {
+        unsafe { destroy_capturable_watcher(self.handle) }
+    }
+}
+
 pub struct X11Context {
     disp: Arc<XDisplay>,
 }
@@ -254,6 +301,41 @@ impl X11Context {
         }
         err
     }
+
+    /// X keycode typing `keysym` with the current keyboard mapping and whether shift has to be
+    /// held to do so.
+    pub fn keysym_to_keycode(&mut self, keysym: u32) -> Option<(c_int, bool)> {
+        let mut shift: c_int = 0;
+        self.disp.lock();
+        let keycode = unsafe { keysym_to_keycode(self.disp.handle, keysym as c_ulong, &mut shift) };
+        self.disp.unlock();
+        if keycode == 0 {
+            return None;
+        }
+        Some((keycode, shift != 0))
+    }
+
+    /// X keycodes without any keysyms bound to them.
+    pub fn unused_keycodes(&mut self) -> Vec<c_int> {
+        let mut keycodes = [0 as c_int; 256];
+        self.disp.lock();
+        let n = unsafe {
+            get_unused_keycodes(
+                self.disp.handle,
+                keycodes.as_mut_ptr(),
+                keycodes.len() as c_int,
+            )
+        };
+        self.disp.unlock();
+        keycodes[0..n as usize].to_vec()
+    }
+
+    /// Bind `keysym` to `keycode`, 0 (NoSymbol) removes the binding.
+    pub fn bind_keysym(&mut self, keycode: c_int, keysym: u32) {
+        self.disp.lock();
+        unsafe { bind_keysym(self.disp.handle, keycode, keysym as c_ulong) };
+        self.disp.unlock();
+    }
 }
 
 #[repr(C)]
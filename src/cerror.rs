@@ -32,6 +32,12 @@ impl CError {
         self.code as i32
     }
 
+    pub fn message(&self) -> String {
+        unsafe { CStr::from_ptr(self.error_str.as_ptr()) }
+            .to_string_lossy()
+            .into_owned()
+    }
+
     pub fn to_enum(&self) -> CErrorCode {
         match self.code {
             0 => CErrorCode::NoError,
@@ -0,0 +1,150 @@
+//! Access to the host's clipboard, so far only supported on X11.
+//!
+//! Reading and writing is done via arboard, changes are detected by watching the owner of the
+//! CLIPBOARD selection with XFixes. Images are exchanged as PNG.
+
+use std::borrow::Cow;
+use std::error::Error;
+use std::fmt;
+use std::io::Cursor;
+use std::os::raw::{c_int, c_void};
+use std::time::Duration;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use image::{ImageFormat, RgbaImage};
+
+use crate::cerror::CError;
+use crate::protocol::ClipboardContent;
+
+extern "C" {
+    fn create_clipboard_watcher(err: *mut CError) -> *mut c_void;
+    fn wait_for_clipboard_change(handle: *mut c_void, timeout_ms: c_int) -> c_int;
+    fn destroy_clipboard_watcher(handle: *mut c_void);
+}
+
+#[derive(Debug)]
+pub enum ClipboardError {
+    Clipboard(arboard::Error),
+    Image(image::ImageError),
+    Base64(base64::DecodeError),
+    InvalidImage,
+}
+
+impl fmt::Display for ClipboardError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClipboardError::Clipboard(err) => write!(f, "Clipboard error: {err}"),
+            ClipboardError::Image(err) => write!(f, "Failed to convert image: {err}"),
+            ClipboardError::Base64(err) => write!(f, "Invalid base64: {err}"),
+            ClipboardError::InvalidImage => write!(f, "Image size does not match its data."),
+        }
+    }
+}
+
+impl Error for ClipboardError {}
+
+impl From<arboard::Error> for ClipboardError {
+    fn from(err: arboard::Error) -> Self {
+        ClipboardError::Clipboard(err)
+    }
+}
+
+impl From<image::ImageError> for ClipboardError {
+    fn from(err: image::ImageError) -> Self {
+        ClipboardError::Image(err)
+    }
+}
+
+impl From<base64::DecodeError> for ClipboardError {
+    fn from(err: base64::DecodeError) -> Self {
+        ClipboardError::Base64(err)
+    }
+}
+
+pub struct Clipboard {
+    clipboard: arboard::Clipboard,
+}
+
+impl Clipboard {
+    pub fn new() -> Result<Self, ClipboardError> {
+        Ok(Self {
+            clipboard: arboard::Clipboard::new()?,
+        })
+    }
+
+    /// Current content of the clipboard, `None` if it is empty or holds neither text nor an image.
+    pub fn get(&mut self) -> Result<Option<ClipboardContent>, ClipboardError> {
+        // text first: applications often offer a rendered image along with copied text, but
+        // rarely text along with a copied image
+        match self.clipboard.get_text() {
+            Ok(text) => return Ok(Some(ClipboardContent::Text(text))),
+            Err(arboard::Error::ContentNotAvailable) => (),
+            Err(err) => return Err(err.into()),
+        }
+        match self.clipboard.get_image() {
+            Ok(image) => Ok(Some(ClipboardContent::Png(encode_png(image)?))),
+            Err(arboard::Error::ContentNotAvailable) => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn set(&mut self, content: &ClipboardContent) -> Result<(), ClipboardError> {
+        match content {
+            ClipboardContent::Text(text) => self.clipboard.set_text(text)?,
+            ClipboardContent::Png(png) => self.clipboard.set_image(decode_png(png)?)?,
+        }
+        Ok(())
+    }
+}
+
+fn encode_png(image: arboard::ImageData) -> Result<String, ClipboardError> {
+    let image = RgbaImage::from_raw(
+        image.width as u32,
+        image.height as u32,
+        image.bytes.into_owned(),
+    )
+    .ok_or(ClipboardError::InvalidImage)?;
+    let mut png = Vec::new();
+    image.write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+    Ok(BASE64.encode(png))
+}
+
+fn decode_png(png: &str) -> Result<arboard::ImageData<'static>, ClipboardError> {
+    let png = BASE64.decode(png)?;
+    let image = image::load_from_memory_with_format(&png, ImageFormat::Png)?.into_rgba8();
+    Ok(arboard::ImageData {
+        width: image.width() as usize,
+        height: image.height() as usize,
+        bytes: Cow::Owned(image.into_raw()),
+    })
+}
+
+/// Watches the X server for something being copied to the clipboard.
+pub struct ClipboardWatcher {
+    handle: *mut c_void,
+}
+
+unsafe impl Send for ClipboardWatcher {}
+
+impl ClipboardWatcher {
+    pub fn new() -> Result<Self, CError> {
+        let mut err = CError::new();
+        let handle = unsafe { create_clipboard_watcher(&mut err) };
+        if err.is_err() {
+            return Err(err);
+        }
+        Ok(Self { handle })
+    }
+
+    /// Wait at most `timeout` for the clipboard to change, returns true if it changed.
+    pub fn wait_for_change(&mut self, timeout: Duration) -> bool {
+        unsafe { wait_for_clipboard_change(self.handle, timeout.as_millis() as c_int) != 0 }
+    }
+}
+
+impl Drop for ClipboardWatcher {
+    fn drop(&mut self) {
+        unsafe { destroy_clipboard_watcher(self.handle) }
+    }
+}
@@ -5,6 +5,8 @@ use clap::Parser;
 use serde::{Deserialize, Serialize};
 use tracing::{debug, warn};
 
+use crate::ip_filter::Cidr;
+
 #[derive(clap::ValueEnum, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ThemeType {
     Aero,
@@ -73,10 +75,42 @@ impl ThemeType {
 pub struct Config {
     #[arg(long, help = "Access code")]
     pub access_code: Option<String>,
+    #[arg(
+        long,
+        help = "Access code for clients that may watch but not control the computer, they \
+        receive video but their input is ignored"
+    )]
+    pub view_access_code: Option<String>,
     #[arg(long, default_value = "0.0.0.0", help = "Bind address")]
     pub bind_address: IpAddr,
     #[arg(long, default_value = "1701", help = "Web port")]
     pub web_port: u16,
+    #[arg(
+        long,
+        value_delimiter = ',',
+        help = "Only accept connections from these addresses or subnets, for example \
+                192.168.1.0/24,10.0.0.5. All addresses are allowed if not set."
+    )]
+    #[serde(default)]
+    pub allow: Vec<Cidr>,
+    #[arg(
+        long,
+        value_delimiter = ',',
+        help = "Refuse connections from these addresses or subnets, takes precedence over --allow."
+    )]
+    #[serde(default)]
+    pub deny: Vec<Cidr>,
+    #[arg(
+        long,
+        help = "Port to listen on for native clients connecting via plain TCP, disabled if not set."
+    )]
+    pub socket_port: Option<u16>,
+    #[cfg(unix)]
+    #[arg(
+        long,
+        help = "Path of a Unix domain socket to listen on for native clients, disabled if not set."
+    )]
+    pub socket_path: Option<PathBuf>,
     #[cfg(target_os = "linux")]
     #[arg(
         long,
@@ -101,6 +135,55 @@ pub struct Config {
     )]
     #[serde(default)]
     pub try_mediafoundation: bool,
+    #[arg(
+        long,
+        default_value = "30",
+        help = "Seconds after which unresponsive web clients are disconnected, 0 disables the timeout."
+    )]
+    #[serde(default = "default_idle_timeout")]
+    pub idle_timeout: u64,
+    #[arg(
+        long,
+        default_value = "24",
+        help = "Hours after which web clients that logged in with the access code have to log in \
+                again."
+    )]
+    #[serde(default = "default_session_lifetime")]
+    pub session_lifetime: u64,
+    #[arg(
+        long,
+        help = "Directory to store files uploaded by clients in, uploads are disabled if not set."
+    )]
+    pub upload_dir: Option<PathBuf>,
+    #[arg(
+        long,
+        default_value = "100",
+        help = "Maximum size of uploaded files in MiB."
+    )]
+    #[serde(default = "default_max_upload_size")]
+    pub max_upload_size: u64,
+    #[arg(
+        long,
+        help = "PEM file with the TLS certificate (chain) used with --tls, a self-signed \
+                certificate is generated if neither it nor --tls-key are set."
+    )]
+    pub tls_cert: Option<PathBuf>,
+    #[arg(long, help = "PEM file with the private key of the TLS certificate.")]
+    pub tls_key: Option<PathBuf>,
+    #[arg(
+        long,
+        help = "Serve HTTPS instead of plain HTTP, not needed if encryption is handled by a \
+                reverse proxy."
+    )]
+    #[serde(default)]
+    pub tls: bool,
+    #[arg(
+        long,
+        help = "Ask on the host whether to accept each new client before any of its input is \
+                processed."
+    )]
+    #[serde(default)]
+    pub require_approval: bool,
     #[arg(long, help = "Start Weylus server immediately on program start.")]
     #[serde(default)]
     pub auto_start: bool,
@@ -143,14 +226,41 @@ pub struct Config {
     #[serde(skip)]
     pub custom_lib_js: Option<PathBuf>,
 
+    #[arg(long, help = "List devices paired via QR code.")]
+    #[serde(skip)]
+    pub list_devices: bool,
+    #[arg(
+        long,
+        value_name = "ID",
+        help = "Revoke the credential of a paired device, \"all\" revokes all of them."
+    )]
+    #[serde(skip)]
+    pub revoke_device: Option<String>,
+
     #[arg(long, help = "Print shell completions for given shell.")]
     #[serde(skip)]
     pub completions: Option<clap_complete::Shell>,
 }
 
+fn default_idle_timeout() -> u64 {
+    30
+}
+
+fn default_session_lifetime() -> u64 {
+    24
+}
+
+fn default_max_upload_size() -> u64 {
+    100
+}
+
+/// Directory Weylus stores its configuration and other persistent state in.
+pub fn config_dir() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("weylus"))
+}
+
 pub fn read_config() -> Option<Config> {
-    if let Some(mut config_path) = dirs::config_dir() {
-        config_path.push("weylus");
+    if let Some(mut config_path) = config_dir() {
         config_path.push("weylus.toml");
         match fs::read_to_string(&config_path) {
             Ok(s) => match toml::from_str(&s) {
@@ -176,9 +286,8 @@ pub fn read_config() -> Option<Config> {
 }
 
 pub fn write_config(conf: &Config) {
-    match dirs::config_dir() {
+    match config_dir() {
         Some(mut config_path) => {
-            config_path.push("weylus");
             if !config_path.exists() {
                 if let Err(err) = fs::create_dir_all(&config_path) {
                     warn!("Failed create directory for configuration: {}", err);
@@ -0,0 +1,129 @@
+//! Watch the cursor on X11 so that it can be sent to clients separately from the video.
+//!
+//! Changes of the cursor's shape are reported by XFixes, its position is polled.
+
+use std::collections::hash_map::DefaultHasher;
+use std::error::Error;
+use std::hash::{Hash, Hasher};
+use std::io::Cursor;
+use std::os::raw::{c_int, c_uint, c_void};
+use std::slice;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use image::{ImageFormat, RgbaImage};
+
+use crate::cerror::CError;
+use crate::protocol::CursorShape;
+
+extern "C" {
+    fn create_cursor_watcher(err: *mut CError) -> *mut c_void;
+    fn cursor_shape_changed(handle: *mut c_void) -> c_int;
+    fn get_cursor_position(
+        handle: *mut c_void,
+        x: *mut c_int,
+        y: *mut c_int,
+        screen_width: *mut c_int,
+        screen_height: *mut c_int,
+    ) -> c_int;
+    fn get_cursor_image(
+        handle: *mut c_void,
+        width: *mut c_uint,
+        height: *mut c_uint,
+        xhot: *mut c_int,
+        yhot: *mut c_int,
+        err: *mut CError,
+    ) -> *const u8;
+    fn destroy_cursor_watcher(handle: *mut c_void);
+}
+
+pub struct CursorWatcher {
+    handle: *mut c_void,
+}
+
+unsafe impl Send for CursorWatcher {}
+
+impl CursorWatcher {
+    pub fn new() -> Result<Self, CError> {
+        let mut err = CError::new();
+        let handle = unsafe { create_cursor_watcher(&mut err) };
+        if err.is_err() {
+            return Err(err);
+        }
+        Ok(Self { handle })
+    }
+
+    /// True if the shape changed since it has last been retrieved via `shape`, this is always the
+    /// case for a new watcher.
+    pub fn shape_changed(&mut self) -> bool {
+        unsafe { cursor_shape_changed(self.handle) != 0 }
+    }
+
+    /// Position of the cursor relative to the size of the screen, `None` if it is on another
+    /// screen.
+    pub fn position(&mut self) -> Option<(f64, f64)> {
+        let mut x = 0;
+        let mut y = 0;
+        let mut width = 0;
+        let mut height = 0;
+        let on_screen = unsafe {
+            get_cursor_position(self.handle, &mut x, &mut y, &mut width, &mut height) != 0
+        };
+        if on_screen && width > 0 && height > 0 {
+            Some((x as f64 / width as f64, y as f64 / height as f64))
+        } else {
+            None
+        }
+    }
+
+    /// Current shape of the cursor including its image.
+    pub fn shape(&mut self) -> Result<CursorShape, Box<dyn Error>> {
+        let mut width = 0;
+        let mut height = 0;
+        let mut hotspot_x = 0;
+        let mut hotspot_y = 0;
+        let mut err = CError::new();
+        let data = unsafe {
+            get_cursor_image(
+                self.handle,
+                &mut width,
+                &mut height,
+                &mut hotspot_x,
+                &mut hotspot_y,
+                &mut err,
+            )
+        };
+        if err.is_err() {
+            return Err(err.into());
+        }
+        let data = if data.is_null() {
+            &[]
+        } else {
+            unsafe { slice::from_raw_parts(data, (width * height * 4) as usize) }
+        };
+
+        let mut hasher = DefaultHasher::new();
+        (width, height, hotspot_x, hotspot_y).hash(&mut hasher);
+        data.hash(&mut hasher);
+
+        let image =
+            RgbaImage::from_raw(width, height, data.to_vec()).ok_or("Invalid cursor image.")?;
+        let mut png = Vec::new();
+        image.write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+
+        Ok(CursorShape {
+            hash: format!("{:016x}", hasher.finish()),
+            width,
+            height,
+            hotspot_x: hotspot_x.max(0) as u32,
+            hotspot_y: hotspot_y.max(0) as u32,
+            png: Some(BASE64.encode(png)),
+        })
+    }
+}
+
+impl Drop for CursorWatcher {
+    fn drop(&mut self) {
+        unsafe { destroy_cursor_watcher(self.handle) }
+    }
+}
@@ -1,11 +1,13 @@
+use std::cell::RefCell;
 use std::cmp::min;
 use std::io::Cursor;
 use std::iter::Iterator;
 use std::net::{IpAddr, SocketAddr};
+use std::rc::Rc;
 use std::sync::atomic::AtomicBool;
 
 use fltk::app;
-use fltk::enums::{FrameType, LabelType};
+use fltk::enums::{Align, FrameType, LabelType};
 use fltk::image::PngImage;
 use fltk::menu::Choice;
 use std::sync::{mpsc, Arc, Mutex};
@@ -22,12 +24,73 @@ use fltk::{
     window::Window,
 };
 
-#[cfg(not(target_os = "windows"))]
-use pnet_datalink as datalink;
-
+use crate::approval;
 use crate::config::{write_config, Config, ThemeType};
 use crate::protocol::{CustomInputAreas, Rect};
-use crate::web::Web2UiMessage::UInputInaccessible;
+use crate::web::Web2UiMessage::{ApprovalRequest, UInputInaccessible};
+
+#[cfg(not(target_os = "windows"))]
+fn set_qr_code(qr_frame: &mut Frame, url: &str, w: i32, h: i32) {
+    use image::Luma;
+    use qrcode::QrCode;
+
+    let code = QrCode::new(url).unwrap();
+    let img_buf = code.render::<Luma<u8>>().build();
+    let image = image::DynamicImage::ImageLuma8(img_buf);
+    let dims = min(w, h) as u32;
+    let image = image.resize_exact(dims, dims, image::imageops::FilterType::Nearest);
+    let mut buf = vec![];
+    let mut cursor = Cursor::new(&mut buf);
+    image
+        .write_to(&mut cursor, image::ImageFormat::Png)
+        .unwrap();
+    let png = PngImage::from_data(&buf).unwrap();
+    qr_frame.set_image(Some(png));
+}
+
+/// Ask whether to accept the client, the window closes once the request times out.
+fn show_approval_request(request: approval::ApprovalRequest) {
+    let w = 400;
+    let h = 120;
+    let mut window = Window::default()
+        .with_size(w, h)
+        .center_screen()
+        .with_label("Weylus - Approve Client");
+    window.set_xclass("weylus");
+    let mut text = Frame::new(10, 10, w - 20, h - 60, None);
+    text.set_label(&format!("Client {request} wants to connect."));
+    text.set_align(Align::Wrap | Align::Inside);
+    let mut button_reject = Button::new(w - 200, h - 40, 90, 30, "Reject");
+    let mut button_accept = Button::new(w - 100, h - 40, 90, 30, "Accept");
+    window.end();
+
+    let timeout = request.remaining().as_secs_f64();
+    let request = Rc::new(RefCell::new(Some(request)));
+    // None if the request timed out and the client has been rejected already
+    let mut finish = {
+        let mut window = window.clone();
+        move |accept: Option<bool>| {
+            if let (Some(request), Some(accept)) = (request.borrow_mut().take(), accept) {
+                request.answer(accept);
+            }
+            window.hide();
+        }
+    };
+    {
+        let mut finish = finish.clone();
+        button_reject.set_callback(move |_| finish(Some(false)));
+    }
+    {
+        let mut finish = finish.clone();
+        button_accept.set_callback(move |_| finish(Some(true)));
+    }
+    {
+        let mut finish = finish.clone();
+        window.set_callback(move |_| finish(Some(false)));
+    }
+    app::add_timeout3(timeout, move |_| finish(None));
+    window.show();
+}
 
 pub fn run(config: &Config, log_receiver: mpsc::Receiver<String>) {
     let width = 200;
@@ -37,7 +100,7 @@ pub fn run(config: &Config, log_receiver: mpsc::Receiver<String>) {
     let app = App::default().with_scheme(fltk::app::AppScheme::Gtk);
     config.gui_theme.map(|th| th.apply());
     let mut wind = Window::default()
-        .with_size(660, 600)
+        .with_size(660, 640)
         .center_screen()
         .with_label(&format!("Weylus - {}", env!("CARGO_PKG_VERSION")));
     wind.set_xclass("weylus");
@@ -49,9 +112,9 @@ pub fn run(config: &Config, log_receiver: mpsc::Receiver<String>) {
         .with_label("Access code");
     input_access_code.set_tooltip(
         "Restrict who can control your computer with an access code. Note that this does NOT do \
-        any kind of encryption and it is advised to only run Weylus inside trusted networks! Do \
-        NOT reuse any of your passwords! If left blank, no code is required to access Weylus \
-        remotely.",
+        any kind of encryption unless Weylus is started with --tls and it is advised to only run \
+        Weylus inside trusted networks! Do NOT reuse any of your passwords! If left blank, no \
+        code is required to access Weylus remotely.",
     );
     if let Some(code) = config.access_code.as_ref() {
         input_access_code.set_value(code);
@@ -154,10 +217,21 @@ pub fn run(config: &Config, log_receiver: mpsc::Receiver<String>) {
         .with_label("Connect your\ntablet to:");
     output_server_addr.hide();
 
+    let mut output_fingerprint = Output::default()
+        .with_size(500, height)
+        .below_of(&output_server_addr, padding)
+        .with_label("Certificate\nfingerprint:");
+    output_fingerprint.set_text_size(10);
+    output_fingerprint.set_tooltip(
+        "SHA-256 fingerprint of the TLS certificate, compare it with the one shown by the browser \
+        before trusting the certificate.",
+    );
+    output_fingerprint.hide();
+
     let output_buf = TextBuffer::default();
     let mut output = TextDisplay::default().with_size(600, 6 * height).with_pos(
         30,
-        output_server_addr.y() + output_server_addr.height() + padding,
+        output_fingerprint.y() + output_fingerprint.height() + padding,
     );
     output.set_buffer(output_buf);
     let output_buf = output.buffer().unwrap();
@@ -192,6 +266,7 @@ pub fn run(config: &Config, log_receiver: mpsc::Receiver<String>) {
 
     let mut weylus = crate::weylus::Weylus::new();
     let mut is_server_running = false;
+    let mut qr_timeout: Option<app::TimeoutHandle> = None;
     let auto_start = config.auto_start;
     let config = Arc::new(Mutex::new(config.clone()));
 
@@ -264,6 +339,14 @@ pub fn run(config: &Config, log_receiver: mpsc::Receiver<String>) {
                         pop_up.make_modal(true);
                         pop_up.show();
                     }),
+                    ApprovalRequest(request) => {
+                        let mut request = Some(request);
+                        awake_callback(move || {
+                            if let Some(request) = request.take() {
+                                show_approval_request(request);
+                            }
+                        })
+                    }
                 }) {
                     return Ok(());
                 }
@@ -271,27 +354,13 @@ pub fn run(config: &Config, log_receiver: mpsc::Receiver<String>) {
 
                 write_config(&config);
 
+                let scheme = if config.tls { "https" } else { "http" };
                 let mut web_sock = SocketAddr::new(config.bind_address, config.web_port);
 
                 #[cfg(not(target_os = "windows"))]
                 {
                     if web_sock.ip().is_unspecified() {
-                        // try to guess an ip
-                        let mut ips = Vec::<IpAddr>::new();
-                        for iface in datalink::interfaces()
-                            .iter()
-                            .filter(|iface| iface.is_up() && !iface.is_loopback())
-                        {
-                            for ipnetw in &iface.ips {
-                                if (ipnetw.is_ipv4() && web_sock.ip().is_ipv4())
-                                    || (ipnetw.is_ipv6() && web_sock.ip().is_ipv6())
-                                {
-                                    // filtering ipv6 unicast requires nightly or more fiddling,
-                                    // lets wait for nightlies to stabilize...
-                                    ips.push(ipnetw.ip())
-                                }
-                            }
-                        }
+                        let ips = crate::weylus::guess_ips(&web_sock);
                         if !ips.is_empty() {
                             web_sock.set_ip(ips[0]);
                         }
@@ -299,7 +368,7 @@ pub fn run(config: &Config, log_receiver: mpsc::Receiver<String>) {
                             info!("Found more than one IP address for browsers to connect to,");
                             info!("other urls are:");
                             for ip in &ips[1..] {
-                                info!("http://{}", SocketAddr::new(*ip, config.web_port));
+                                info!("{scheme}://{}", SocketAddr::new(*ip, config.web_port));
                             }
                         }
                     }
@@ -307,60 +376,67 @@ pub fn run(config: &Config, log_receiver: mpsc::Receiver<String>) {
 
                 #[cfg(not(target_os = "windows"))]
                 {
-                    use image::Luma;
-                    use qrcode::QrCode;
-                    let addr_string = format!("http://{}", web_sock);
+                    let addr_string = format!("{scheme}://{}", web_sock);
                     output_server_addr.set_value(&addr_string);
-                    let mut url_string = addr_string;
-                    if let Some(access_code) = &config.access_code {
-                        url_string.push_str("?access_code=");
-                        url_string.push_str(
-                            &percent_encoding::utf8_percent_encode(
-                                access_code,
-                                percent_encoding::NON_ALPHANUMERIC,
-                            )
-                            .to_string(),
-                        );
-                    }
-
-                    let cb = move |qr_frame: &mut Frame, _, _, w, h| {
-                        let code = QrCode::new(&url_string).unwrap();
-                        let img_buf = code.render::<Luma<u8>>().build();
-                        let image = image::DynamicImage::ImageLuma8(img_buf);
-                        let dims = min(w, h) as u32;
-                        let image =
-                            image.resize_exact(dims, dims, image::imageops::FilterType::Nearest);
-                        let mut buf = vec![];
-                        let mut cursor = Cursor::new(&mut buf);
-                        image
-                            .write_to(&mut cursor, image::ImageFormat::Png)
-                            .unwrap();
-                        let png = PngImage::from_data(&buf).unwrap();
-                        qr_frame.set_image(Some(png));
+                    // with an access code set the QR code contains a one-time token to pair
+                    // devices with, which is replaced once it is redeemed or expires
+                    let pairing = config.access_code.is_some().then(|| weylus.pairing());
+                    let qr_url = move || match &pairing {
+                        Some(pairing) => {
+                            format!("{addr_string}/pair?token={}", pairing.current_token())
+                        }
+                        None => addr_string.clone(),
                     };
 
-                    let x = qr_frame.x();
-                    let y = qr_frame.y();
+                    let mut shown_url = qr_url();
                     let w = qr_frame.width();
                     let h = qr_frame.height();
-                    cb(&mut qr_frame, x, y, w, h);
-                    qr_frame.resize_callback(cb);
+                    set_qr_code(&mut qr_frame, &shown_url, w, h);
+                    {
+                        let qr_url = qr_url.clone();
+                        qr_frame.resize_callback(move |qr_frame, _, _, w, h| {
+                            set_qr_code(qr_frame, &qr_url(), w, h)
+                        });
+                    }
+                    if config.access_code.is_some() {
+                        let mut qr_frame = qr_frame.clone();
+                        qr_timeout = Some(app::add_timeout3(1.0, move |handle| {
+                            let url = qr_url();
+                            if url != shown_url {
+                                let w = qr_frame.width();
+                                let h = qr_frame.height();
+                                set_qr_code(&mut qr_frame, &url, w, h);
+                                qr_frame.redraw();
+                                shown_url = url;
+                            }
+                            app::repeat_timeout3(1.0, handle);
+                        }));
+                    }
                     qr_frame.show();
                 }
                 #[cfg(target_os = "windows")]
                 {
                     if web_sock.ip().is_unspecified() {
-                        output_server_addr.set_value("http://<your ip address>");
+                        output_server_addr.set_value(&format!("{scheme}://<your ip address>"));
                     } else {
-                        output_server_addr.set_value(&format!("http://{}", web_sock.to_string()));
+                        output_server_addr
+                            .set_value(&format!("{scheme}://{}", web_sock.to_string()));
                     }
                 }
                 output_server_addr.show();
+                if let Some(fingerprint) = weylus.tls_fingerprint() {
+                    output_fingerprint.set_value(fingerprint);
+                    output_fingerprint.show();
+                }
                 but.set_label("Stop");
             } else {
                 weylus.stop();
                 but.set_label("Start");
                 output_server_addr.hide();
+                output_fingerprint.hide();
+                if let Some(handle) = qr_timeout.take() {
+                    app::remove_timeout3(handle);
+                }
                 qr_frame.resize_callback(|_, _, _, _, _| {});
                 qr_frame.hide();
                 is_server_running = false;
@@ -157,6 +157,15 @@ impl InputDevice for AutoPilotDevice {
         }
     }
 
+    fn send_text(&mut self, text: &str) {
+        use autopilot::key::Character;
+
+        for c in text.chars() {
+            autopilot::key::toggle(&Character(c), true, &[], 0);
+            autopilot::key::toggle(&Character(c), false, &[], 0);
+        }
+    }
+
     fn set_capturable(&mut self, capturable: Box<dyn Capturable>) {
         self.capturable = capturable;
     }
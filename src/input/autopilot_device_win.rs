@@ -217,6 +217,10 @@ impl InputDevice for WindowsInput {
         self.autopilot_device.send_keyboard_event(event);
     }
 
+    fn send_text(&mut self, text: &str) {
+        self.autopilot_device.send_text(text);
+    }
+
     fn set_capturable(&mut self, capturable: Box<dyn Capturable>) {
         self.capturable = capturable;
     }
@@ -1,7 +1,10 @@
+use serde::{Deserialize, Serialize};
+
 use crate::capturable::Capturable;
 use crate::protocol::{KeyboardEvent, PointerEvent, WheelEvent};
 
-#[derive(PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(test, derive(schemars::JsonSchema))]
 pub enum InputDeviceType {
     AutoPilotDevice,
     UInputDevice,
@@ -12,7 +15,27 @@ pub enum InputDeviceType {
 pub trait InputDevice {
     fn send_wheel_event(&mut self, event: &WheelEvent);
     fn send_pointer_event(&mut self, event: &PointerEvent);
+    /// Send a batch of pointer events, by default they are simply sent one after another.
+    fn send_pointer_events(&mut self, events: &[PointerEvent]) {
+        for event in events {
+            self.send_pointer_event(event);
+        }
+    }
     fn send_keyboard_event(&mut self, event: &KeyboardEvent);
+    /// Type text independent of the keys required to do so.
+    fn send_text(&mut self, text: &str);
     fn set_capturable(&mut self, capturable: Box<dyn Capturable>);
     fn device_type(&self) -> InputDeviceType;
 }
+
+/// Input backends that can be requested by clients on the current platform.
+pub fn available_input_device_types() -> Vec<InputDeviceType> {
+    vec![
+        #[cfg(target_os = "linux")]
+        InputDeviceType::UInputDevice,
+        #[cfg(not(target_os = "windows"))]
+        InputDeviceType::AutoPilotDevice,
+        #[cfg(target_os = "windows")]
+        InputDeviceType::WindowsInput,
+    ]
+}
@@ -1,4 +1,5 @@
 use std::cmp::Ordering;
+use std::collections::VecDeque;
 use std::ffi::CString;
 use std::os::raw::{c_char, c_int};
 use std::time::{Duration, Instant};
@@ -37,6 +38,10 @@ pub struct UInputDevice {
     tool_pen_active: bool,
     pen_touching: bool,
     last_pen_event: Instant,
+    // while set, touch events do not end their report but only mark it as pending, see
+    // send_pointer_events
+    defer_touch_sync: bool,
+    touch_sync_pending: bool,
     capturable: Box<dyn Capturable>,
     geometry: Rect,
     name_mouse_device: String,
@@ -46,6 +51,10 @@ pub struct UInputDevice {
     num_stylus_mapping_tries: usize,
     num_touch_mapping_tries: usize,
     x11ctx: Option<X11Context>,
+    // keycodes without keysyms, characters missing from the keyboard mapping are bound to these
+    unused_keycodes: Option<Vec<c_int>>,
+    // keycodes currently bound to a character, oldest first
+    bound_keycodes: VecDeque<c_int>,
 }
 
 impl UInputDevice {
@@ -102,6 +111,8 @@ impl UInputDevice {
             tool_pen_active: false,
             pen_touching: false,
             last_pen_event: Instant::now(),
+            defer_touch_sync: false,
+            touch_sync_pending: false,
             capturable,
             geometry: Rect::default(),
             name_mouse_device: name_mouse,
@@ -111,6 +122,8 @@ impl UInputDevice {
             num_stylus_mapping_tries: 0,
             num_touch_mapping_tries: 0,
             x11ctx: X11Context::new(),
+            unused_keycodes: None,
+            bound_keycodes: VecDeque::new(),
         })
     }
 
@@ -148,6 +161,13 @@ impl UInputDevice {
             })
     }
 
+    fn flush_touch_report(&mut self) {
+        if self.touch_sync_pending {
+            self.send(self.touch_fd, ET_SYNC, EC_SYNC_REPORT, 0);
+            self.touch_sync_pending = false;
+        }
+    }
+
     fn send(&self, fd: c_int, typ: c_int, code: c_int, value: c_int) {
         let mut err = CError::new();
         unsafe {
@@ -157,10 +177,102 @@ impl UInputDevice {
             warn!("{}", err);
         }
     }
+
+    fn tap_key(&self, key_code: c_int, shift: bool) {
+        use crate::input::uinput_keys::KEY_LEFTSHIFT;
+        if shift {
+            self.send(self.keyboard_fd, ET_KEY, KEY_LEFTSHIFT, 1);
+            self.send(self.keyboard_fd, ET_SYNC, EC_SYNC_REPORT, 0);
+        }
+        self.send(self.keyboard_fd, ET_KEY, key_code, 1);
+        self.send(self.keyboard_fd, ET_SYNC, EC_SYNC_REPORT, 0);
+        self.send(self.keyboard_fd, ET_KEY, key_code, 0);
+        self.send(self.keyboard_fd, ET_SYNC, EC_SYNC_REPORT, 0);
+        if shift {
+            self.send(self.keyboard_fd, ET_KEY, KEY_LEFTSHIFT, 0);
+            self.send(self.keyboard_fd, ET_SYNC, EC_SYNC_REPORT, 0);
+        }
+    }
+
+    /// Type `c` with the keys the current keyboard mapping provides for it. Characters missing
+    /// from the mapping are bound to an unused keycode first. Without X11 the keyboard layout is
+    /// unknown, in that case a US layout is assumed and anything else is typed via ctrl + shift +
+    /// u + code point, which is understood by GTK and IBus.
+    fn type_char(&mut self, c: char) {
+        let keysym = match char_to_keysym(c) {
+            Some(keysym) => keysym,
+            None => {
+                debug!("Can not type control character {:?}, ignoring it.", c);
+                return;
+            }
+        };
+        let key = match &mut self.x11ctx {
+            Some(x11ctx) => x11ctx.keysym_to_keycode(keysym),
+            None => {
+                match us_layout_key(c) {
+                    Some((key_code, shift)) => self.tap_key(key_code, shift),
+                    None => self.send_unicode(c),
+                }
+                return;
+            }
+        };
+        match key.or_else(|| self.bind_keysym(keysym).map(|keycode| (keycode, false))) {
+            // X keycodes are evdev keycodes offset by 8
+            Some((keycode, shift)) => self.tap_key(keycode - 8, shift),
+            None => self.send_unicode(c),
+        }
+    }
+
+    /// Bind `keysym` to an unused keycode and return the keycode. The binding is kept until the
+    /// keycode is needed for another keysym as the X server may process the key event only after
+    /// the binding has been removed otherwise.
+    fn bind_keysym(&mut self, keysym: u32) -> Option<c_int> {
+        let x11ctx = self.x11ctx.as_mut()?;
+        let unused_keycodes = self.unused_keycodes.get_or_insert_with(|| {
+            // X keycode 8 is evdev's KEY_RESERVED
+            let mut keycodes = x11ctx.unused_keycodes();
+            keycodes.retain(|keycode| *keycode > 8);
+            keycodes
+        });
+        let keycode = match unused_keycodes.pop() {
+            Some(keycode) => keycode,
+            // reuse the keycode that has been bound the longest time ago
+            None => self.bound_keycodes.pop_front()?,
+        };
+        x11ctx.bind_keysym(keycode, keysym);
+        self.bound_keycodes.push_back(keycode);
+        Some(keycode)
+    }
+
+    fn send_unicode(&self, c: char) {
+        use crate::input::uinput_keys::*;
+        self.send(self.keyboard_fd, ET_KEY, KEY_LEFTCTRL, 1);
+        self.send(self.keyboard_fd, ET_KEY, KEY_LEFTSHIFT, 1);
+        self.send(self.keyboard_fd, ET_KEY, KEY_U, 1);
+        self.send(self.keyboard_fd, ET_SYNC, EC_SYNC_REPORT, 0);
+        for digit in format!("{:X}", c as u32).chars() {
+            let code = if digit.is_ascii_digit() {
+                format!("Digit{}", digit)
+            } else {
+                format!("Key{}", digit)
+            };
+            self.tap_key(map_key(&code, &KeyboardLocation::STANDARD), false);
+        }
+        self.send(self.keyboard_fd, ET_KEY, KEY_LEFTCTRL, 0);
+        self.send(self.keyboard_fd, ET_KEY, KEY_LEFTSHIFT, 0);
+        self.send(self.keyboard_fd, ET_KEY, KEY_U, 0);
+        self.send(self.keyboard_fd, ET_SYNC, EC_SYNC_REPORT, 0);
+    }
 }
 
 impl Drop for UInputDevice {
     fn drop(&mut self) {
+        // restore the keyboard mapping
+        if let Some(x11ctx) = &mut self.x11ctx {
+            for keycode in self.bound_keycodes.drain(..) {
+                x11ctx.bind_keysym(keycode, 0);
+            }
+        }
         unsafe {
             destroy_uinput_device(self.keyboard_fd);
             destroy_uinput_device(self.stylus_fd);
@@ -170,6 +282,256 @@ impl Drop for UInputDevice {
     }
 }
 
+/// X keysym of `c`, see keysymdef.h.
+fn char_to_keysym(c: char) -> Option<u32> {
+    match c {
+        '\n' | '\r' => Some(0xff0d), // Return
+        '\t' => Some(0xff09),        // Tab
+        '\u{8}' => Some(0xff08),     // BackSpace
+        c if c.is_control() => None,
+        // keysyms of Latin-1 characters are their code points
+        ' '..='~' | '\u{a0}'..='\u{ff}' => Some(c as u32),
+        c => Some(0x0100_0000 | c as u32),
+    }
+}
+
+/// Key typing `c` on a US keyboard layout and whether shift has to be held to do so.
+fn us_layout_key(c: char) -> Option<(c_int, bool)> {
+    let (code, shift) = match c {
+        'a'..='z' => (format!("Key{}", c.to_ascii_uppercase()), false),
+        'A'..='Z' => (format!("Key{}", c), true),
+        '0'..='9' => (format!("Digit{}", c), false),
+        _ => {
+            let (code, shift) = match c {
+                ' ' => ("Space", false),
+                '\n' | '\r' => ("Enter", false),
+                '\t' => ("Tab", false),
+                '\u{8}' => ("Backspace", false),
+                '-' => ("Minus", false),
+                '_' => ("Minus", true),
+                '=' => ("Equal", false),
+                '+' => ("Equal", true),
+                '[' => ("BracketLeft", false),
+                '{' => ("BracketLeft", true),
+                ']' => ("BracketRight", false),
+                '}' => ("BracketRight", true),
+                ';' => ("Semicolon", false),
+                ':' => ("Semicolon", true),
+                '\'' => ("Quote", false),
+                '"' => ("Quote", true),
+                '`' => ("Backquote", false),
+                '~' => ("Backquote", true),
+                '\\' => ("Backslash", false),
+                '|' => ("Backslash", true),
+                ',' => ("Comma", false),
+                '<' => ("Comma", true),
+                '.' => ("Period", false),
+                '>' => ("Period", true),
+                '/' => ("Slash", false),
+                '?' => ("Slash", true),
+                '!' => ("Digit1", true),
+                '@' => ("Digit2", true),
+                '#' => ("Digit3", true),
+                '$' => ("Digit4", true),
+                '%' => ("Digit5", true),
+                '^' => ("Digit6", true),
+                '&' => ("Digit7", true),
+                '*' => ("Digit8", true),
+                '(' => ("Digit9", true),
+                ')' => ("Digit0", true),
+                _ => return None,
+            };
+            (code.to_string(), shift)
+        }
+    };
+    Some((map_key(&code, &KeyboardLocation::STANDARD), shift))
+}
+
+fn map_key(code: &str, location: &KeyboardLocation) -> c_int {
+    use crate::input::uinput_keys::*;
+    match (code, location) {
+        ("Escape", _) => KEY_ESC,
+        ("Digit0", KeyboardLocation::NUMPAD) => KEY_KP0,
+        ("Digit1", KeyboardLocation::NUMPAD) => KEY_KP1,
+        ("Digit2", KeyboardLocation::NUMPAD) => KEY_KP2,
+        ("Digit3", KeyboardLocation::NUMPAD) => KEY_KP3,
+        ("Digit4", KeyboardLocation::NUMPAD) => KEY_KP4,
+        ("Digit5", KeyboardLocation::NUMPAD) => KEY_KP5,
+        ("Digit6", KeyboardLocation::NUMPAD) => KEY_KP6,
+        ("Digit7", KeyboardLocation::NUMPAD) => KEY_KP7,
+        ("Digit8", KeyboardLocation::NUMPAD) => KEY_KP8,
+        ("Digit9", KeyboardLocation::NUMPAD) => KEY_KP9,
+        ("Minus", KeyboardLocation::NUMPAD) => KEY_KPMINUS,
+        ("Equal", KeyboardLocation::NUMPAD) => KEY_KPEQUAL,
+        ("Enter", KeyboardLocation::NUMPAD) => KEY_KPENTER,
+        ("Digit0", _) => KEY_0,
+        ("Digit1", _) => KEY_1,
+        ("Digit2", _) => KEY_2,
+        ("Digit3", _) => KEY_3,
+        ("Digit4", _) => KEY_4,
+        ("Digit5", _) => KEY_5,
+        ("Digit6", _) => KEY_6,
+        ("Digit7", _) => KEY_7,
+        ("Digit8", _) => KEY_8,
+        ("Digit9", _) => KEY_9,
+        ("Minus", _) => KEY_MINUS,
+        ("Equal", _) => KEY_EQUAL,
+        ("Enter", _) => KEY_ENTER,
+        ("Backspace", _) => KEY_BACKSPACE,
+        ("Tab", _) => KEY_TAB,
+        ("KeyA", _) => KEY_A,
+        ("KeyB", _) => KEY_B,
+        ("KeyC", _) => KEY_C,
+        ("KeyD", _) => KEY_D,
+        ("KeyE", _) => KEY_E,
+        ("KeyF", _) => KEY_F,
+        ("KeyG", _) => KEY_G,
+        ("KeyH", _) => KEY_H,
+        ("KeyI", _) => KEY_I,
+        ("KeyJ", _) => KEY_J,
+        ("KeyK", _) => KEY_K,
+        ("KeyL", _) => KEY_L,
+        ("KeyM", _) => KEY_M,
+        ("KeyN", _) => KEY_N,
+        ("KeyO", _) => KEY_O,
+        ("KeyP", _) => KEY_P,
+        ("KeyQ", _) => KEY_Q,
+        ("KeyR", _) => KEY_R,
+        ("KeyS", _) => KEY_S,
+        ("KeyT", _) => KEY_T,
+        ("KeyU", _) => KEY_U,
+        ("KeyV", _) => KEY_V,
+        ("KeyW", _) => KEY_W,
+        ("KeyX", _) => KEY_X,
+        ("KeyY", _) => KEY_Y,
+        ("KeyZ", _) => KEY_Z,
+        ("BracketLeft", _) => KEY_LEFTBRACE,
+        ("BracketRight", _) => KEY_RIGHTBRACE,
+        ("Semicolon", _) => KEY_SEMICOLON,
+        ("Quote", _) => KEY_APOSTROPHE,
+        ("Backquote", _) => KEY_GRAVE,
+        ("Backslash", _) => KEY_BACKSLASH,
+        ("Comma", _) => KEY_COMMA,
+        ("Period", _) => KEY_DOT,
+        ("Slash", _) => KEY_SLASH,
+        ("Space", _) => KEY_SPACE,
+        ("CapsLock", _) => KEY_CAPSLOCK,
+        ("NumpadMultiply", _) => KEY_KPASTERISK,
+        ("F1", _) => KEY_F1,
+        ("F2", _) => KEY_F2,
+        ("F3", _) => KEY_F3,
+        ("F4", _) => KEY_F4,
+        ("F5", _) => KEY_F5,
+        ("F6", _) => KEY_F6,
+        ("F7", _) => KEY_F7,
+        ("F8", _) => KEY_F8,
+        ("F9", _) => KEY_F9,
+        ("F10", _) => KEY_F10,
+        ("F11", _) => KEY_F11,
+        ("F12", _) => KEY_F12,
+        ("F13", _) => KEY_F13,
+        ("F14", _) => KEY_F14,
+        ("F15", _) => KEY_F15,
+        ("F16", _) => KEY_F16,
+        ("F17", _) => KEY_F17,
+        ("F18", _) => KEY_F18,
+        ("F19", _) => KEY_F19,
+        ("F20", _) => KEY_F20,
+        ("F21", _) => KEY_F21,
+        ("F22", _) => KEY_F22,
+        ("F23", _) => KEY_F23,
+        ("F24", _) => KEY_F24,
+        ("NumLock", _) => KEY_NUMLOCK,
+        ("ScrollLock", _) => KEY_SCROLLLOCK,
+        ("Numpad0", _) => KEY_KP0,
+        ("Numpad1", _) => KEY_KP1,
+        ("Numpad2", _) => KEY_KP2,
+        ("Numpad3", _) => KEY_KP3,
+        ("Numpad4", _) => KEY_KP4,
+        ("Numpad5", _) => KEY_KP5,
+        ("Numpad6", _) => KEY_KP6,
+        ("Numpad7", _) => KEY_KP7,
+        ("Numpad8", _) => KEY_KP8,
+        ("Numpad9", _) => KEY_KP9,
+        ("NumpadSubtract", _) => KEY_KPMINUS,
+        ("NumpadAdd", _) => KEY_KPPLUS,
+        // ("NumpadDecimal", _) => ?,
+        ("IntlBackslash", _) => KEY_102ND,
+        ("IntlRo", _) => KEY_RO,
+        ("NumpadEnter", _) => KEY_KPENTER,
+        ("NumpadDivide", _) => KEY_KPSLASH,
+        ("NumpadEqual", _) => KEY_KPEQUAL,
+        ("NumpadComma", _) => KEY_KPCOMMA,
+        ("NumpadParenLeft", _) => KEY_KPLEFTPAREN,
+        ("NumpadParenRight", _) => KEY_KPRIGHTPAREN,
+        // ("NumpadChangeSign", _) => ?,
+        // ("Convert", _) => ?,
+        ("KanaMode", _) => KEY_KATAKANA,
+        // ("NonConvert", _) => ?,
+        ("PrintScreen", _) => KEY_SYSRQ,
+        ("Home", _) => KEY_HOME,
+        ("ArrowUp", _) => KEY_UP,
+        ("PageUp", _) => KEY_PAGEUP,
+        ("ArrowLeft", _) => KEY_LEFT,
+        ("ArrowRight", _) => KEY_RIGHT,
+        ("End", _) => KEY_END,
+        ("ArrowDown", _) => KEY_DOWN,
+        ("PageDown", _) => KEY_PAGEDOWN,
+        ("Insert", _) => KEY_INSERT,
+        ("Delete", _) => KEY_DELETE,
+        ("VolumeMute", _) | ("AudioVolumeMute", _) => KEY_MUTE,
+        ("VolumeDown", _) | ("AudioVolumeDown", _) => KEY_VOLUMEDOWN,
+        ("VolumeUp", _) | ("AudioVolumeUp", _) => KEY_VOLUMEUP,
+        ("Pause", _) => KEY_PAUSE,
+
+        ("Lang1", _) => KEY_HANGUEL,
+        ("Lang2", _) => KEY_HANJA,
+        ("IntlYen", _) => KEY_YEN,
+        ("OSLeft", _) => KEY_LEFTMETA,
+        ("OSRight", _) => KEY_RIGHTMETA,
+        ("ContextMenu", _) => KEY_MENU,
+        // ("BrowserStop", _) => ?,
+        ("Cancel", _) => KEY_CANCEL,
+        ("Again", _) => KEY_AGAIN,
+        ("Props", _) => KEY_PROPS,
+        ("Undo", _) => KEY_UNDO,
+        // ("Select", _) => ?,
+        ("Copy", _) => KEY_COPY,
+        ("Open", _) => KEY_OPEN,
+        ("Paste", _) => KEY_PASTE,
+        ("Find", _) => KEY_FIND,
+        ("Cut", _) => KEY_CUT,
+        ("Help", _) => KEY_HELP,
+        // ("LaunchApp2", _) => ?,
+        // ("LaunchApp1", _) => ,
+        ("LaunchMail", _) => KEY_MAIL,
+        // ("BrowserFavorites", _) => ?,
+        // ("BrowserBack", _) => ?,
+        // ("BrowserForward", _) => ?,
+        ("Eject", _) => KEY_EJECTCD,
+        ("MediaTrackNext", _) => KEY_NEXTSONG,
+        ("MediaPlayPause", _) => KEY_PLAYPAUSE,
+        ("MediaTrackPrevious", _) => KEY_PREVIOUSSONG,
+        ("MediaStop", _) => KEY_STOPCD,
+        ("MediaSelect", _) | ("LaunchMediaPlayer", _) => KEY_MEDIA,
+        // ("BrowserHome", _) => ?,
+        // ("BrowserRefresh", _) => ?,
+        // ("BrowserSearch", _) => ?,
+        ("Power", _) => KEY_POWER,
+        ("Sleep", _) => KEY_SLEEP,
+        ("WakeUp", _) => KEY_WAKEUP,
+        ("ControlLeft", _) => KEY_LEFTCTRL,
+        ("ControlRight", _) => KEY_RIGHTCTRL,
+        ("AltLeft", _) => KEY_LEFTALT,
+        ("AltRight", _) => KEY_RIGHTALT,
+        ("MetaLeft", _) => KEY_LEFTMETA,
+        ("MetaRight", _) => KEY_RIGHTMETA,
+        ("ShiftLeft", _) => KEY_LEFTSHIFT,
+        ("ShiftRight", _) => KEY_RIGHTSHIFT,
+        _ => KEY_UNKNOWN,
+    }
+}
+
 // Event Types
 const ET_SYNC: c_int = 0x00;
 const ET_KEY: c_int = 0x01;
@@ -439,7 +801,11 @@ impl InputDevice for UInputDevice {
                             EC_MSC_TIMESTAMP,
                             (event.timestamp % (i32::MAX as u64 + 1)) as i32,
                         );
-                        self.send(self.touch_fd, ET_SYNC, EC_SYNC_REPORT, 0);
+                        if self.defer_touch_sync {
+                            self.touch_sync_pending = true;
+                        } else {
+                            self.send(self.touch_fd, ET_SYNC, EC_SYNC_REPORT, 0);
+                        }
                     }
                     PointerEventType::CANCEL
                     | PointerEventType::UP
@@ -640,196 +1006,34 @@ impl InputDevice for UInputDevice {
         }
     }
 
+    fn send_pointer_events(&mut self, events: &[PointerEvent]) {
+        // Moving touch points belong to the same multitouch frame, so moves of distinct touches
+        // are combined into a single report. Everything else is sent as one report per event,
+        // otherwise intermediate positions of coalesced events would be lost.
+        let mut pointers_in_report = Vec::new();
+        for event in events {
+            let mergeable = matches!(event.pointer_type, PointerType::Touch)
+                && matches!(event.event_type, PointerEventType::MOVE);
+            if !mergeable || pointers_in_report.contains(&event.pointer_id) {
+                self.flush_touch_report();
+                pointers_in_report.clear();
+            }
+            self.defer_touch_sync = mergeable;
+            self.send_pointer_event(event);
+            if mergeable {
+                pointers_in_report.push(event.pointer_id);
+            }
+        }
+        self.defer_touch_sync = false;
+        self.flush_touch_report();
+    }
+
     fn send_keyboard_event(&mut self, event: &KeyboardEvent) {
         use crate::input::uinput_keys::*;
         if let Err(err) = self.capturable.before_input() {
             warn!("Failed to activate window, sending no input ({})", err);
             return;
         }
-        fn map_key(code: &str, location: &KeyboardLocation) -> c_int {
-            match (code, location) {
-                ("Escape", _) => KEY_ESC,
-                ("Digit0", KeyboardLocation::NUMPAD) => KEY_KP0,
-                ("Digit1", KeyboardLocation::NUMPAD) => KEY_KP1,
-                ("Digit2", KeyboardLocation::NUMPAD) => KEY_KP2,
-                ("Digit3", KeyboardLocation::NUMPAD) => KEY_KP3,
-                ("Digit4", KeyboardLocation::NUMPAD) => KEY_KP4,
-                ("Digit5", KeyboardLocation::NUMPAD) => KEY_KP5,
-                ("Digit6", KeyboardLocation::NUMPAD) => KEY_KP6,
-                ("Digit7", KeyboardLocation::NUMPAD) => KEY_KP7,
-                ("Digit8", KeyboardLocation::NUMPAD) => KEY_KP8,
-                ("Digit9", KeyboardLocation::NUMPAD) => KEY_KP9,
-                ("Minus", KeyboardLocation::NUMPAD) => KEY_KPMINUS,
-                ("Equal", KeyboardLocation::NUMPAD) => KEY_KPEQUAL,
-                ("Enter", KeyboardLocation::NUMPAD) => KEY_KPENTER,
-                ("Digit0", _) => KEY_0,
-                ("Digit1", _) => KEY_1,
-                ("Digit2", _) => KEY_2,
-                ("Digit3", _) => KEY_3,
-                ("Digit4", _) => KEY_4,
-                ("Digit5", _) => KEY_5,
-                ("Digit6", _) => KEY_6,
-                ("Digit7", _) => KEY_7,
-                ("Digit8", _) => KEY_8,
-                ("Digit9", _) => KEY_9,
-                ("Minus", _) => KEY_MINUS,
-                ("Equal", _) => KEY_EQUAL,
-                ("Enter", _) => KEY_ENTER,
-                ("Backspace", _) => KEY_BACKSPACE,
-                ("Tab", _) => KEY_TAB,
-                ("KeyA", _) => KEY_A,
-                ("KeyB", _) => KEY_B,
-                ("KeyC", _) => KEY_C,
-                ("KeyD", _) => KEY_D,
-                ("KeyE", _) => KEY_E,
-                ("KeyF", _) => KEY_F,
-                ("KeyG", _) => KEY_G,
-                ("KeyH", _) => KEY_H,
-                ("KeyI", _) => KEY_I,
-                ("KeyJ", _) => KEY_J,
-                ("KeyK", _) => KEY_K,
-                ("KeyL", _) => KEY_L,
-                ("KeyM", _) => KEY_M,
-                ("KeyN", _) => KEY_N,
-                ("KeyO", _) => KEY_O,
-                ("KeyP", _) => KEY_P,
-                ("KeyQ", _) => KEY_Q,
-                ("KeyR", _) => KEY_R,
-                ("KeyS", _) => KEY_S,
-                ("KeyT", _) => KEY_T,
-                ("KeyU", _) => KEY_U,
-                ("KeyV", _) => KEY_V,
-                ("KeyW", _) => KEY_W,
-                ("KeyX", _) => KEY_X,
-                ("KeyY", _) => KEY_Y,
-                ("KeyZ", _) => KEY_Z,
-                ("BracketLeft", _) => KEY_LEFTBRACE,
-                ("BracketRight", _) => KEY_RIGHTBRACE,
-                ("Semicolon", _) => KEY_SEMICOLON,
-                ("Quote", _) => KEY_APOSTROPHE,
-                ("Backquote", _) => KEY_GRAVE,
-                ("Backslash", _) => KEY_BACKSLASH,
-                ("Comma", _) => KEY_COMMA,
-                ("Period", _) => KEY_DOT,
-                ("Slash", _) => KEY_SLASH,
-                ("Space", _) => KEY_SPACE,
-                ("CapsLock", _) => KEY_CAPSLOCK,
-                ("NumpadMultiply", _) => KEY_KPASTERISK,
-                ("F1", _) => KEY_F1,
-                ("F2", _) => KEY_F2,
-                ("F3", _) => KEY_F3,
-                ("F4", _) => KEY_F4,
-                ("F5", _) => KEY_F5,
-                ("F6", _) => KEY_F6,
-                ("F7", _) => KEY_F7,
-                ("F8", _) => KEY_F8,
-                ("F9", _) => KEY_F9,
-                ("F10", _) => KEY_F10,
-                ("F11", _) => KEY_F11,
-                ("F12", _) => KEY_F12,
-                ("F13", _) => KEY_F13,
-                ("F14", _) => KEY_F14,
-                ("F15", _) => KEY_F15,
-                ("F16", _) => KEY_F16,
-                ("F17", _) => KEY_F17,
-                ("F18", _) => KEY_F18,
-                ("F19", _) => KEY_F19,
-                ("F20", _) => KEY_F20,
-                ("F21", _) => KEY_F21,
-                ("F22", _) => KEY_F22,
-                ("F23", _) => KEY_F23,
-                ("F24", _) => KEY_F24,
-                ("NumLock", _) => KEY_NUMLOCK,
-                ("ScrollLock", _) => KEY_SCROLLLOCK,
-                ("Numpad0", _) => KEY_KP0,
-                ("Numpad1", _) => KEY_KP1,
-                ("Numpad2", _) => KEY_KP2,
-                ("Numpad3", _) => KEY_KP3,
-                ("Numpad4", _) => KEY_KP4,
-                ("Numpad5", _) => KEY_KP5,
-                ("Numpad6", _) => KEY_KP6,
-                ("Numpad7", _) => KEY_KP7,
-                ("Numpad8", _) => KEY_KP8,
-                ("Numpad9", _) => KEY_KP9,
-                ("NumpadSubtract", _) => KEY_KPMINUS,
-                ("NumpadAdd", _) => KEY_KPPLUS,
-                // ("NumpadDecimal", _) => ?,
-                ("IntlBackslash", _) => KEY_102ND,
-                ("IntlRo", _) => KEY_RO,
-                ("NumpadEnter", _) => KEY_KPENTER,
-                ("NumpadDivide", _) => KEY_KPSLASH,
-                ("NumpadEqual", _) => KEY_KPEQUAL,
-                ("NumpadComma", _) => KEY_KPCOMMA,
-                ("NumpadParenLeft", _) => KEY_KPLEFTPAREN,
-                ("NumpadParenRight", _) => KEY_KPRIGHTPAREN,
-                // ("NumpadChangeSign", _) => ?,
-                // ("Convert", _) => ?,
-                ("KanaMode", _) => KEY_KATAKANA,
-                // ("NonConvert", _) => ?,
-                ("PrintScreen", _) => KEY_SYSRQ,
-                ("Home", _) => KEY_HOME,
-                ("ArrowUp", _) => KEY_UP,
-                ("PageUp", _) => KEY_PAGEUP,
-                ("ArrowLeft", _) => KEY_LEFT,
-                ("ArrowRight", _) => KEY_RIGHT,
-                ("End", _) => KEY_END,
-                ("ArrowDown", _) => KEY_DOWN,
-                ("PageDown", _) => KEY_PAGEDOWN,
-                ("Insert", _) => KEY_INSERT,
-                ("Delete", _) => KEY_DELETE,
-                ("VolumeMute", _) | ("AudioVolumeMute", _) => KEY_MUTE,
-                ("VolumeDown", _) | ("AudioVolumeDown", _) => KEY_VOLUMEDOWN,
-                ("VolumeUp", _) | ("AudioVolumeUp", _) => KEY_VOLUMEUP,
-                ("Pause", _) => KEY_PAUSE,
-
-                ("Lang1", _) => KEY_HANGUEL,
-                ("Lang2", _) => KEY_HANJA,
-                ("IntlYen", _) => KEY_YEN,
-                ("OSLeft", _) => KEY_LEFTMETA,
-                ("OSRight", _) => KEY_RIGHTMETA,
-                ("ContextMenu", _) => KEY_MENU,
-                // ("BrowserStop", _) => ?,
-                ("Cancel", _) => KEY_CANCEL,
-                ("Again", _) => KEY_AGAIN,
-                ("Props", _) => KEY_PROPS,
-                ("Undo", _) => KEY_UNDO,
-                // ("Select", _) => ?,
-                ("Copy", _) => KEY_COPY,
-                ("Open", _) => KEY_OPEN,
-                ("Paste", _) => KEY_PASTE,
-                ("Find", _) => KEY_FIND,
-                ("Cut", _) => KEY_CUT,
-                ("Help", _) => KEY_HELP,
-                // ("LaunchApp2", _) => ?,
-                // ("LaunchApp1", _) => ,
-                ("LaunchMail", _) => KEY_MAIL,
-                // ("BrowserFavorites", _) => ?,
-                // ("BrowserBack", _) => ?,
-                // ("BrowserForward", _) => ?,
-                ("Eject", _) => KEY_EJECTCD,
-                ("MediaTrackNext", _) => KEY_NEXTSONG,
-                ("MediaPlayPause", _) => KEY_PLAYPAUSE,
-                ("MediaTrackPrevious", _) => KEY_PREVIOUSSONG,
-                ("MediaStop", _) => KEY_STOPCD,
-                ("MediaSelect", _) | ("LaunchMediaPlayer", _) => KEY_MEDIA,
-                // ("BrowserHome", _) => ?,
-                // ("BrowserRefresh", _) => ?,
-                // ("BrowserSearch", _) => ?,
-                ("Power", _) => KEY_POWER,
-                ("Sleep", _) => KEY_SLEEP,
-                ("WakeUp", _) => KEY_WAKEUP,
-                ("ControlLeft", _) => KEY_LEFTCTRL,
-                ("ControlRight", _) => KEY_RIGHTCTRL,
-                ("AltLeft", _) => KEY_LEFTALT,
-                ("AltRight", _) => KEY_RIGHTALT,
-                ("MetaLeft", _) => KEY_LEFTMETA,
-                ("MetaRight", _) => KEY_RIGHTMETA,
-                ("ShiftLeft", _) => KEY_LEFTSHIFT,
-                ("ShiftRight", _) => KEY_RIGHTSHIFT,
-                _ => KEY_UNKNOWN,
-            }
-        }
-
         let key_code: c_int = map_key(&event.code, &event.location);
         let state: c_int = match event.event_type {
             KeyboardEventType::UP => 0,
@@ -839,42 +1043,17 @@ impl InputDevice for UInputDevice {
 
         if key_code == KEY_UNKNOWN {
             if let KeyboardEventType::DOWN = event.event_type {
-                if !event.key.is_empty() {
-                    // If the key is unknow try inserting the unicode character directly
-                    // to do so use CTRL + SHIFT + U + UTF16 HEX of the unicode point.
-                    let unicode_keys = event
-                        .key
-                        .encode_utf16()
-                        .map(|b| format!("{:X}", b))
-                        .collect::<Vec<String>>()
-                        .concat();
-
+                // named keys like "Dead" or "Unidentified" can not be typed
+                let named_key = event.key.len() > 1 && event.key.is_ascii();
+                if !event.key.is_empty() && !named_key {
+                    // If the key is unknown try typing the character(s) it produces instead.
                     debug!(
-                        "Got unknown key: {} code: {}, trying to insert unicode using ctrl + \
-                        shift + u + {}!",
-                        event.code, event.key, unicode_keys
+                        "Got unknown key: {} code: {}, trying to type it as text!",
+                        event.code, event.key
                     );
-
-                    self.send(self.keyboard_fd, ET_KEY, KEY_LEFTCTRL, 1);
-                    self.send(self.keyboard_fd, ET_KEY, KEY_LEFTSHIFT, 1);
-                    self.send(self.keyboard_fd, ET_KEY, KEY_U, 1);
-                    self.send(self.keyboard_fd, ET_SYNC, EC_SYNC_REPORT, 0);
-                    for c in unicode_keys.chars() {
-                        let key_code = if c.is_alphabetic() {
-                            map_key(&format!("Key{}", c), &KeyboardLocation::STANDARD)
-                        } else {
-                            map_key(&format!("Digit{}", c), &KeyboardLocation::STANDARD)
-                        };
-
-                        self.send(self.keyboard_fd, ET_KEY, key_code, 1);
-                        self.send(self.keyboard_fd, ET_SYNC, EC_SYNC_REPORT, 0);
-                        self.send(self.keyboard_fd, ET_KEY, key_code, 0);
-                        self.send(self.keyboard_fd, ET_SYNC, EC_SYNC_REPORT, 0);
+                    for c in event.key.chars() {
+                        self.type_char(c);
                     }
-                    self.send(self.keyboard_fd, ET_KEY, KEY_LEFTCTRL, 0);
-                    self.send(self.keyboard_fd, ET_KEY, KEY_LEFTSHIFT, 0);
-                    self.send(self.keyboard_fd, ET_KEY, KEY_U, 0);
-                    self.send(self.keyboard_fd, ET_SYNC, EC_SYNC_REPORT, 0);
                 }
             } else {
                 debug!(
@@ -906,6 +1085,16 @@ impl InputDevice for UInputDevice {
         self.send(self.keyboard_fd, ET_SYNC, EC_SYNC_REPORT, 0);
     }
 
+    fn send_text(&mut self, text: &str) {
+        if let Err(err) = self.capturable.before_input() {
+            warn!("Failed to activate window, sending no input ({})", err);
+            return;
+        }
+        for c in text.chars() {
+            self.type_char(c);
+        }
+    }
+
     fn set_capturable(&mut self, capturable: Box<dyn Capturable>) {
         self.capturable = capturable;
     }
@@ -0,0 +1,180 @@
+//! Restrict which addresses may connect to Weylus via lists of allowed and denied subnets.
+
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+/// IP subnet in CIDR notation, for example `192.168.1.0/24`. A plain address is a subnet
+/// containing only this address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        // IPv4 clients connecting to a socket bound to an IPv6 address show up as IPv4-mapped
+        // IPv6 addresses
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix_len) = match s.split_once('/') {
+            Some((addr, prefix_len)) => (addr, Some(prefix_len)),
+            None => (s, None),
+        };
+        let addr = addr
+            .parse::<IpAddr>()
+            .map_err(|err| format!("Invalid address {addr}: {err}"))?;
+        let max_prefix_len = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(prefix_len) => prefix_len
+                .parse::<u8>()
+                .ok()
+                .filter(|len| *len <= max_prefix_len)
+                .ok_or_else(|| format!("Invalid prefix length: {prefix_len}"))?,
+            None => max_prefix_len,
+        };
+        // clients are matched by their canonical address, so IPv4-mapped subnets are stored as
+        // plain IPv4 subnets
+        match addr.to_canonical() {
+            IpAddr::V4(v4) if addr.is_ipv6() => {
+                let prefix_len = prefix_len.checked_sub(96).ok_or_else(|| {
+                    format!(
+                        "Invalid prefix length for IPv4-mapped subnet: {prefix_len}, it has to \
+                         be between 96 and 128"
+                    )
+                })?;
+                Ok(Self {
+                    addr: IpAddr::V4(v4),
+                    prefix_len,
+                })
+            }
+            addr => Ok(Self { addr, prefix_len }),
+        }
+    }
+}
+
+impl TryFrom<String> for Cidr {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Cidr> for String {
+    fn from(cidr: Cidr) -> Self {
+        cidr.to_string()
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct IpFilter {
+    pub allow: Vec<Cidr>,
+    pub deny: Vec<Cidr>,
+}
+
+impl IpFilter {
+    /// Denied subnets take precedence, if no subnets are allowed explicitly all other addresses
+    /// are allowed.
+    pub fn is_allowed(&self, ip: IpAddr) -> bool {
+        !self.deny.iter().any(|cidr| cidr.contains(ip))
+            && (self.allow.is_empty() || self.allow.iter().any(|cidr| cidr.contains(ip)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cidr(s: &str) -> Cidr {
+        s.parse().unwrap()
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn prefix_lengths() {
+        assert!(cidr("0.0.0.0/0").contains(ip("203.0.113.7")));
+        assert!(!cidr("0.0.0.0/0").contains(ip("2001:db8::1")));
+        assert!(cidr("::/0").contains(ip("2001:db8::1")));
+        assert!(cidr("192.168.1.0/24").contains(ip("192.168.1.255")));
+        assert!(!cidr("192.168.1.0/24").contains(ip("192.168.2.1")));
+        assert!(cidr("192.168.1.7/32").contains(ip("192.168.1.7")));
+        assert!(!cidr("192.168.1.7/32").contains(ip("192.168.1.8")));
+        assert!(cidr("2001:db8::1/128").contains(ip("2001:db8::1")));
+        assert!(!cidr("2001:db8::1/128").contains(ip("2001:db8::2")));
+        assert_eq!(cidr("192.168.1.7"), cidr("192.168.1.7/32"));
+        assert_eq!(cidr("2001:db8::1"), cidr("2001:db8::1/128"));
+        assert!("192.168.1.0/33".parse::<Cidr>().is_err());
+        assert!("2001:db8::/129".parse::<Cidr>().is_err());
+        assert!("192.168.1.0/-1".parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn ipv4_mapped_clients() {
+        let filter = IpFilter {
+            allow: vec![cidr("192.168.1.0/24")],
+            deny: vec![],
+        };
+        assert!(filter.is_allowed(ip("::ffff:192.168.1.7")));
+        assert!(!filter.is_allowed(ip("::ffff:192.168.2.7")));
+    }
+
+    #[test]
+    fn ipv4_mapped_subnets() {
+        assert_eq!(cidr("::ffff:192.168.1.0/120"), cidr("192.168.1.0/24"));
+        assert_eq!(cidr("::ffff:192.168.1.7"), cidr("192.168.1.7/32"));
+        assert_eq!(cidr("::ffff:0.0.0.0/96"), cidr("0.0.0.0/0"));
+        let err = "::ffff:192.168.1.0/24".parse::<Cidr>().unwrap_err();
+        assert!(err.contains("IPv4-mapped"), "{err}");
+    }
+
+    #[test]
+    fn deny_takes_precedence() {
+        let filter = IpFilter {
+            allow: vec![cidr("192.168.0.0/16")],
+            deny: vec![cidr("192.168.1.0/24")],
+        };
+        assert!(filter.is_allowed(ip("192.168.2.1")));
+        assert!(!filter.is_allowed(ip("192.168.1.1")));
+        assert!(!filter.is_allowed(ip("10.0.0.1")));
+        let filter = IpFilter {
+            allow: vec![],
+            deny: vec![cidr("192.168.1.0/24")],
+        };
+        assert!(filter.is_allowed(ip("10.0.0.1")));
+        assert!(!filter.is_allowed(ip("::ffff:192.168.1.1")));
+    }
+}
@@ -13,22 +13,146 @@ use signal_hook::iterator::Signals;
 use signal_hook::{consts::TERM_SIGNALS, low_level::signal_name};
 use tracing::{error, info, warn};
 
-use std::sync::mpsc;
+use std::collections::VecDeque;
+use std::io::IsTerminal;
+use std::net::SocketAddr;
+use std::sync::{mpsc, Arc, Mutex};
 
+use approval::ApprovalRequest;
 use config::{get_config, Config};
+use pairing::{Pairing, PAIRING_TOKEN_LIFETIME};
 
+mod approval;
+#[cfg(target_os = "linux")]
+mod audio;
+mod auth;
 mod capturable;
 mod cerror;
+#[cfg(target_os = "linux")]
+mod clipboard;
 mod config;
+#[cfg(target_os = "linux")]
+mod cursor;
 mod gui;
 mod input;
+mod ip_filter;
 mod log;
+mod pairing;
 mod protocol;
+#[cfg(all(test, target_os = "linux"))]
+mod protocol_ts;
+mod socket;
+mod tls;
+mod upload;
 mod video;
 mod web;
+#[cfg(feature = "webrtc")]
+mod webrtc;
 mod websocket;
 mod weylus;
 
+/// Returns a function printing a QR code to pair devices with to the terminal, `None` if there is
+/// no address to put into the QR code.
+fn pairing_qr_code_printer(conf: &Config, pairing: Arc<Pairing>) -> Option<impl Fn() + Send> {
+    use qrcode::render::unicode;
+    use qrcode::QrCode;
+
+    let scheme = if conf.tls { "https" } else { "http" };
+    let web_sock = SocketAddr::new(conf.bind_address, conf.web_port);
+    #[cfg(not(target_os = "windows"))]
+    let web_sock = if web_sock.ip().is_unspecified() {
+        weylus::guess_ips(&web_sock)
+            .first()
+            .map_or(web_sock, |ip| SocketAddr::new(*ip, web_sock.port()))
+    } else {
+        web_sock
+    };
+    if web_sock.ip().is_unspecified() {
+        info!("Set --bind-address to get a QR code for pairing devices.");
+        return None;
+    }
+
+    let interactive = std::io::stdin().is_terminal();
+    Some(move || {
+        let url = format!(
+            "{scheme}://{web_sock}/pair?token={}",
+            pairing.current_token()
+        );
+        let code = match QrCode::new(&url) {
+            Ok(code) => code,
+            Err(err) => {
+                error!("Failed to create QR code: {err}");
+                return;
+            }
+        };
+        let image = code
+            .render::<unicode::Dense1x2>()
+            .dark_color(unicode::Dense1x2::Light)
+            .light_color(unicode::Dense1x2::Dark)
+            .build();
+        println!("{image}");
+        println!(
+            "Scan the QR code to pair a device, it is valid for {} minutes: {url}",
+            PAIRING_TOKEN_LIFETIME.as_secs() / 60
+        );
+        if interactive {
+            println!("Press enter to show a new code.");
+        }
+    })
+}
+
+/// Read answers to approval requests from stdin, which allows scripts to answer them too. If no
+/// request is pending, a new QR code for pairing is printed instead.
+fn read_stdin(
+    approvals: Arc<Mutex<VecDeque<ApprovalRequest>>>,
+    print_qr_code: Option<impl Fn() + Send + 'static>,
+) {
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => break,
+            };
+            let mut approvals = approvals.lock().unwrap();
+            // the answer was meant for a request that timed out meanwhile, ask about the next one
+            if remove_expired(&mut approvals) {
+                match approvals.front() {
+                    Some(next) => print_approval_prompt(next),
+                    None => println!("The request timed out before it was answered."),
+                }
+                continue;
+            }
+            match approvals.pop_front() {
+                Some(request) => {
+                    let answer = line.trim().to_lowercase();
+                    request.answer(answer == "y" || answer == "yes");
+                    if let Some(next) = approvals.front() {
+                        print_approval_prompt(next);
+                    }
+                }
+                None => {
+                    if let Some(print_qr_code) = &print_qr_code {
+                        print_qr_code();
+                    }
+                }
+            }
+        }
+    });
+}
+
+/// Drop requests nobody answered in time, returns whether the one asked about was among them.
+fn remove_expired(approvals: &mut VecDeque<ApprovalRequest>) -> bool {
+    let front_expired = approvals
+        .front()
+        .is_some_and(|request| request.remaining().is_zero());
+    approvals.retain(|request| !request.remaining().is_zero());
+    front_expired
+}
+
+fn print_approval_prompt(request: &ApprovalRequest) {
+    println!("Client {request} wants to connect, accept? [y/N]");
+}
+
 fn main() {
     let (sender, receiver) = mpsc::sync_channel::<String>(100);
 
@@ -63,6 +187,26 @@ fn main() {
         return;
     }
 
+    if conf.list_devices {
+        match pairing::list_devices() {
+            Ok(devices) => {
+                for device in devices {
+                    println!("{}\t{}\t{}", device.id, device.paired_at, device.name);
+                }
+            }
+            Err(err) => error!("Failed to read paired devices: {err}"),
+        }
+        return;
+    }
+    if let Some(id) = &conf.revoke_device {
+        match pairing::revoke_device(id) {
+            Ok(0) => warn!("No paired device with id {id} found."),
+            Ok(n) => info!("Revoked {n} device(s)."),
+            Err(err) => error!("Failed to revoke device: {err}"),
+        }
+        return;
+    }
+
     #[cfg(target_os = "linux")]
     {
         // make sure XInitThreads is called before any threading is done
@@ -79,11 +223,39 @@ fn main() {
 
     if conf.no_gui {
         let mut weylus = crate::weylus::Weylus::new();
-        weylus.start(&conf, |msg| match msg {
-            web::Web2UiMessage::UInputInaccessible => {
-                warn!(std::include_str!("strings/uinput_error.txt"))
+        let approvals = Arc::new(Mutex::new(VecDeque::new()));
+        let started = weylus.start(&conf, {
+            let approvals = approvals.clone();
+            move |msg| match msg {
+                web::Web2UiMessage::UInputInaccessible => {
+                    warn!(std::include_str!("strings/uinput_error.txt"))
+                }
+                web::Web2UiMessage::ApprovalRequest(request) => {
+                    let mut approvals = approvals.lock().unwrap();
+                    if remove_expired(&mut approvals) {
+                        if let Some(next) = approvals.front() {
+                            print_approval_prompt(next);
+                        }
+                    }
+                    // requests are answered in order, later ones are shown once it is their turn
+                    if approvals.is_empty() {
+                        print_approval_prompt(&request);
+                    }
+                    approvals.push_back(request);
+                }
             }
         });
+        let print_qr_code = if started && conf.access_code.is_some() {
+            pairing_qr_code_printer(&conf, weylus.pairing())
+        } else {
+            None
+        };
+        if let Some(print_qr_code) = &print_qr_code {
+            print_qr_code();
+        }
+        if conf.require_approval || (print_qr_code.is_some() && std::io::stdin().is_terminal()) {
+            read_stdin(approvals, print_qr_code);
+        }
         #[cfg(unix)]
         {
             let mut signals = Signals::new(TERM_SIGNALS).unwrap();
@@ -149,8 +321,16 @@ mod tests {
             try_videotoolbox: false,
             try_mediafoundation: false,
         };
-        let mut encoder =
-            video::VideoEncoder::new(width, height, width, height, |_| {}, opts).unwrap();
+        let mut encoder = video::VideoEncoder::new(
+            width,
+            height,
+            width,
+            height,
+            |_| {},
+            opts,
+            video::VideoFormat::FragmentedMp4,
+        )
+        .unwrap();
         b.iter(|| encoder.encode(r.capture().unwrap()));
     }
 
@@ -184,8 +364,16 @@ mod tests {
             try_videotoolbox: false,
             try_mediafoundation: false,
         };
-        let mut encoder =
-            video::VideoEncoder::new(width, height, width, height, |_| {}, opts).unwrap();
+        let mut encoder = video::VideoEncoder::new(
+            width,
+            height,
+            width,
+            height,
+            |_| {},
+            opts,
+            video::VideoFormat::FragmentedMp4,
+        )
+        .unwrap();
         b.iter(|| encoder.encode(r.capture().unwrap()));
     }
 
@@ -208,8 +396,16 @@ mod tests {
             try_videotoolbox: false,
             try_mediafoundation: false,
         };
-        let mut encoder =
-            video::VideoEncoder::new(WIDTH, HEIGHT, WIDTH, HEIGHT, |_| {}, opts).unwrap();
+        let mut encoder = video::VideoEncoder::new(
+            WIDTH,
+            HEIGHT,
+            WIDTH,
+            HEIGHT,
+            |_| {},
+            opts,
+            video::VideoFormat::FragmentedMp4,
+        )
+        .unwrap();
         const SIZE: usize = WIDTH * HEIGHT * 4;
         let mut i = 0;
         b.iter(|| {
@@ -237,8 +433,16 @@ mod tests {
             try_videotoolbox: false,
             try_mediafoundation: false,
         };
-        let mut encoder =
-            video::VideoEncoder::new(WIDTH, HEIGHT, WIDTH, HEIGHT, |_| {}, opts).unwrap();
+        let mut encoder = video::VideoEncoder::new(
+            WIDTH,
+            HEIGHT,
+            WIDTH,
+            HEIGHT,
+            |_| {},
+            opts,
+            video::VideoFormat::FragmentedMp4,
+        )
+        .unwrap();
         const SIZE: usize = WIDTH * HEIGHT * 4;
         let mut i = 0;
         b.iter(|| {
@@ -266,8 +470,16 @@ mod tests {
             try_videotoolbox: false,
             try_mediafoundation: false,
         };
-        let mut encoder =
-            video::VideoEncoder::new(WIDTH, HEIGHT, WIDTH, HEIGHT, |_| {}, opts).unwrap();
+        let mut encoder = video::VideoEncoder::new(
+            WIDTH,
+            HEIGHT,
+            WIDTH,
+            HEIGHT,
+            |_| {},
+            opts,
+            video::VideoFormat::FragmentedMp4,
+        )
+        .unwrap();
         const SIZE: usize = WIDTH * HEIGHT * 4;
         let mut i = 0;
         b.iter(|| {
@@ -0,0 +1,189 @@
+//! Pairing of devices: instead of entering the access code, a device scans a QR code with a URL
+//! containing a short-lived one-time token. Redeeming the token issues the device a credential,
+//! which is kept as a cookie by the browser and stays valid until the device is revoked.
+//!
+//! Paired devices are stored in `devices.toml` in the config directory, only hashes of their
+//! credentials are kept there.
+
+use std::error::Error;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::warn;
+
+use crate::auth::{access_code_matches, random_token};
+
+pub const PAIRING_TOKEN_LIFETIME: Duration = Duration::from_secs(5 * 60);
+const DEVICES_FILE: &str = "devices.toml";
+// device names are taken from the user agent, which can be rather long
+const MAX_DEVICE_NAME_LENGTH: usize = 120;
+
+// serializes modifications of the devices file
+static DEVICES_LOCK: Mutex<()> = Mutex::new(());
+// devices file as read last by `verify_credential`, along with its modification time and size
+static DEVICES_CACHE: Mutex<Option<(SystemTime, u64, Devices)>> = Mutex::new(None);
+
+fn hash(credential: &str) -> String {
+    Sha256::digest(credential.as_bytes())
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// Hands out the one-time token required to pair a device, there is only one valid token at a
+/// time.
+#[derive(Default)]
+pub struct Pairing {
+    token: Mutex<Option<(String, Instant)>>,
+}
+
+impl Pairing {
+    /// The currently valid token, a new one is created if it has been redeemed or expired.
+    pub fn current_token(&self) -> String {
+        let mut token = self.token.lock().unwrap();
+        match token.as_ref() {
+            Some((token, created)) if created.elapsed() < PAIRING_TOKEN_LIFETIME => token.clone(),
+            _ => {
+                let new_token = random_token(16);
+                *token = Some((new_token.clone(), Instant::now()));
+                new_token
+            }
+        }
+    }
+
+    /// Returns true and invalidates the token if `token` is valid.
+    pub fn redeem(&self, token: &str) -> bool {
+        let mut current = self.token.lock().unwrap();
+        let valid = current.as_ref().is_some_and(|(current, created)| {
+            created.elapsed() < PAIRING_TOKEN_LIFETIME
+                && access_code_matches(token.as_bytes(), current.as_bytes())
+        });
+        if valid {
+            *current = None;
+        }
+        valid
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Device {
+    pub id: String,
+    pub name: String,
+    /// Seconds since the Unix epoch.
+    pub paired_at: u64,
+    credential_hash: String,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct Devices {
+    #[serde(default, rename = "device")]
+    devices: Vec<Device>,
+}
+
+fn devices_path() -> Result<PathBuf, Box<dyn Error>> {
+    Ok(crate::config::config_dir()
+        .ok_or("Failed to find configuration directory!")?
+        .join(DEVICES_FILE))
+}
+
+fn read_devices() -> Result<Devices, Box<dyn Error>> {
+    match fs::read_to_string(devices_path()?) {
+        Ok(s) => Ok(toml::from_str(&s)?),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(Devices::default()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+fn write_devices(devices: &Devices) -> Result<(), Box<dyn Error>> {
+    let path = devices_path()?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, toml::to_string_pretty(devices)?)?;
+    Ok(())
+}
+
+pub fn list_devices() -> Result<Vec<Device>, Box<dyn Error>> {
+    Ok(read_devices()?.devices)
+}
+
+/// Store a newly paired device, returns the credential it has to present from now on.
+pub fn add_device(name: &str) -> Result<String, Box<dyn Error>> {
+    let _lock = DEVICES_LOCK.lock().unwrap();
+    let mut devices = read_devices()?;
+    let credential = random_token(32);
+    devices.devices.push(Device {
+        id: random_token(4),
+        name: name.chars().take(MAX_DEVICE_NAME_LENGTH).collect(),
+        paired_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs()),
+        credential_hash: hash(&credential),
+    });
+    write_devices(&devices)?;
+    Ok(credential)
+}
+
+/// Revoke the device with the given id, "all" revokes all devices. Returns the number of revoked
+/// devices.
+pub fn revoke_device(id: &str) -> Result<usize, Box<dyn Error>> {
+    remove_devices(|device| id == "all" || device.id == id)
+}
+
+/// Revoke the device `credential` belongs to, returns false if there is no such device.
+pub fn revoke_credential(credential: &str) -> Result<bool, Box<dyn Error>> {
+    let hash = hash(credential);
+    Ok(remove_devices(|device| device.credential_hash == hash)? > 0)
+}
+
+fn remove_devices(remove: impl Fn(&Device) -> bool) -> Result<usize, Box<dyn Error>> {
+    let _lock = DEVICES_LOCK.lock().unwrap();
+    let mut devices = read_devices()?;
+    let num_devices = devices.devices.len();
+    devices.devices.retain(|device| !remove(device));
+    let revoked = num_devices - devices.devices.len();
+    if revoked > 0 {
+        write_devices(&devices)?;
+    }
+    Ok(revoked)
+}
+
+/// The paired device `credential` belongs to, `None` if it is invalid or has been revoked.
+pub fn verify_credential(credential: &str) -> Option<Device> {
+    // the file is checked for modifications every time so that revoking devices takes effect
+    // immediately, even if it is done from another instance of Weylus
+    let metadata = devices_path().and_then(|path| Ok(fs::metadata(path)?));
+    let version = metadata
+        .ok()
+        .and_then(|metadata| Some((metadata.modified().ok()?, metadata.len())));
+    let mut cache = DEVICES_CACHE.lock().unwrap();
+    let cached = cache
+        .as_ref()
+        .filter(|(modified, len, _)| version == Some((*modified, *len)));
+    let devices = match cached {
+        Some((_, _, devices)) => devices,
+        None => {
+            let devices = match read_devices() {
+                Ok(devices) => devices,
+                Err(err) => {
+                    warn!("Failed to read paired devices: {err}");
+                    return None;
+                }
+            };
+            // never matches if there is no version, so a missing file is read again next time
+            let (modified, len) = version.unwrap_or((UNIX_EPOCH, 0));
+            &cache.insert((modified, len, devices)).2
+        }
+    };
+    let hash = hash(credential);
+    devices
+        .devices
+        .iter()
+        .find(|device| device.credential_hash == hash)
+        .cloned()
+}
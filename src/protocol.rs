@@ -1,41 +1,288 @@
 use serde::{Deserialize, Deserializer, Serialize};
 
+use crate::cerror::{CError, CErrorCode};
+use crate::input::device::InputDeviceType;
+use crate::video::VideoFormat;
+
+/// Version of the protocol spoken over the websocket, bump this whenever messages change in an
+/// incompatible way.
+pub const PROTOCOL_VERSION: u32 = 2;
+
 #[derive(Serialize, Deserialize, Debug)]
+#[cfg_attr(test, derive(schemars::JsonSchema))]
 pub struct ClientConfiguration {
     #[cfg(target_os = "linux")]
     pub uinput_support: bool,
-    pub capturable_id: usize,
+    /// Stable identifier of the capturable as sent in `CapturableList`.
+    pub capturable_id: String,
     pub capture_cursor: bool,
+    /// Send the cursor as `CursorPosition` and `CursorShape` instead of drawing it into the video.
+    pub stream_cursor: bool,
+    /// Stream the host's audio via `NewAudio` and `AudioData`.
+    pub audio: bool,
     pub max_width: usize,
     pub max_height: usize,
     pub client_name: Option<String>,
     pub frame_rate: f64,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(test, derive(schemars::JsonSchema))]
+pub struct CapturableInfo {
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[cfg_attr(test, derive(schemars::JsonSchema))]
+pub struct ClientHello {
+    pub protocol_version: u32,
+    /// Shown to the host if it has to approve new clients.
+    #[serde(default)]
+    pub client_name: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(test, derive(schemars::JsonSchema))]
+pub struct ServerInfo {
+    pub protocol_version: u32,
+    pub version: String,
+    pub platform: String,
+    pub input_backends: Vec<InputDeviceType>,
+    pub encoders: Vec<String>,
+    pub features: Vec<String>,
+    /// Input sent by view-only clients is ignored.
+    pub view_only: bool,
+    /// Only set if the connection supports resuming the session after reconnecting.
+    pub session: Option<SessionInfo>,
+}
+
+/// A session can be resumed within a grace period after the connection is lost by reconnecting
+/// with its token, input devices and screen capture are kept alive in the meantime.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(test, derive(schemars::JsonSchema))]
+pub struct SessionInfo {
+    pub token: String,
+    /// True if this connection resumed an existing session.
+    pub resumed: bool,
+    /// Id of the capturable currently in use by the resumed session.
+    pub capturable_id: Option<String>,
+}
+
+/// Signalling used to negotiate a WebRTC session, relayed over the regular connection.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(test, derive(schemars::JsonSchema))]
+pub enum WebRtcSignal {
+    Offer(String),
+    Answer(String),
+    IceCandidate(IceCandidate),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(test, derive(schemars::JsonSchema))]
+pub struct IceCandidate {
+    pub candidate: String,
+    pub sdp_mid: Option<String>,
+    pub sdp_mline_index: Option<u16>,
+}
+
+/// Timestamps are milliseconds since the UNIX epoch on the clock of the side that took them.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[cfg_attr(test, derive(schemars::JsonSchema))]
+pub struct Ping {
+    pub id: u32,
+    pub timestamp: f64,
+}
+
+/// Answer to a Ping, `timestamp` is copied from the Ping, `peer_timestamp` is taken by the side
+/// answering and allows to estimate the offset between both clocks.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[cfg_attr(test, derive(schemars::JsonSchema))]
+pub struct Pong {
+    pub id: u32,
+    pub timestamp: f64,
+    pub peer_timestamp: f64,
+}
+
+/// Sent right before the video data of a frame.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[cfg_attr(test, derive(schemars::JsonSchema))]
+pub struct FrameInfo {
+    pub sequence: u64,
+    /// Time the frame was captured in milliseconds since the UNIX epoch.
+    pub capture_timestamp: f64,
+    /// Number of frames not sent so far because the connection was congested.
+    pub skipped_frames: u64,
+}
+
+/// Latencies measured by the client in milliseconds.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[cfg_attr(test, derive(schemars::JsonSchema))]
+pub struct LatencyReport {
+    pub rtt: f64,
+    /// Time between capturing a frame and displaying it.
+    pub video_latency: f64,
+}
+
+/// Position of the cursor relative to the capturable, values outside of [0, 1] mean the cursor is
+/// outside of the capturable.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(test, derive(schemars::JsonSchema))]
+pub struct CursorPosition {
+    pub x: f64,
+    pub y: f64,
+}
+
+/// Image of the cursor, sent whenever the cursor's shape changes.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(test, derive(schemars::JsonSchema))]
+pub struct CursorShape {
+    /// Hash of the image, identifies images the client has already received.
+    pub hash: String,
+    pub width: u32,
+    pub height: u32,
+    pub hotspot_x: u32,
+    pub hotspot_y: u32,
+    /// Base64 encoded PNG image, omitted if an image with the same hash has been sent before.
+    pub png: Option<String>,
+}
+
+/// Content of a clipboard, only text and images are synchronized.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(test, derive(schemars::JsonSchema))]
+pub enum ClipboardContent {
+    Text(String),
+    /// Base64 encoded PNG image.
+    Png(String),
+}
+
 #[derive(Serialize, Deserialize, Debug)]
+#[cfg_attr(test, derive(schemars::JsonSchema))]
 pub enum MessageInbound {
+    Hello(ClientHello),
     PointerEvent(PointerEvent),
+    PointerEvents(Vec<PointerEvent>),
     WheelEvent(WheelEvent),
     KeyboardEvent(KeyboardEvent),
+    /// Text to type as is, regardless of the keyboard layout, for example the result of an IME
+    /// composition or dictation.
+    TextInput(String),
     GetCapturableList,
     Config(ClientConfiguration),
     PauseVideo,
     ResumeVideo,
     RestartVideo,
     ChooseCustomInputAreas,
+    WebRtcSignal(WebRtcSignal),
+    Ping(Ping),
+    Pong(Pong),
+    LatencyReport(LatencyReport),
+    /// Replace the content of the host's clipboard.
+    ClipboardSet(ClipboardContent),
+    /// Enable or disable sending `ClipboardChanged` whenever the host's clipboard changes.
+    SyncClipboard(bool),
 }
 
 #[derive(Serialize, Deserialize, Debug)]
+#[cfg_attr(test, derive(schemars::JsonSchema))]
 pub enum MessageOutbound {
-    CapturableList(Vec<String>),
+    ServerInfo(ServerInfo),
+    CapturableList(Vec<CapturableInfo>),
     NewVideo,
     ConfigOk,
     CustomInputAreas(CustomInputAreas),
-    ConfigError(String),
-    Error(String),
+    ConfigError(WeylusError),
+    Error(WeylusError),
+    WebRtcSignal(WebRtcSignal),
+    Ping(Ping),
+    Pong(Pong),
+    FrameInfo(FrameInfo),
+    ClipboardChanged(ClipboardContent),
+    CursorPosition(CursorPosition),
+    CursorShape(CursorShape),
+    /// Start of a new audio stream, the following audio data begins with a new MP4 header.
+    NewAudio,
+    /// The next binary message is a chunk of fragmented MP4 containing AAC audio instead of
+    /// video.
+    AudioData,
+}
+
+/// Stable error codes that allow clients to react to errors, for example by showing instructions
+/// on how to fix them. Clients match on the variant names, so do not rename them.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(test, derive(schemars::JsonSchema))]
+pub enum ErrorCode {
+    Generic,
+    InvalidMessage,
+    ProtocolVersionMismatch,
+    UInputNotAccessible,
+    InputDeviceFailed,
+    CapturableGone,
+    ScreenCastDenied,
+    ScreenCastFailed,
+    EncoderInitFailed,
+    WebRtcNotSupported,
+    WebRtcFailed,
+    ClipboardFailed,
+    CursorStreamFailed,
+    AudioFailed,
+    ConnectionRejected,
+}
+
+impl From<CErrorCode> for ErrorCode {
+    fn from(code: CErrorCode) -> Self {
+        match code {
+            CErrorCode::UInputNotAccessible => ErrorCode::UInputNotAccessible,
+            CErrorCode::NoError | CErrorCode::GenericError => ErrorCode::Generic,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(test, derive(schemars::JsonSchema))]
+pub struct WeylusError {
+    pub code: ErrorCode,
+    /// Human readable description of the error.
+    pub detail: Option<String>,
+}
+
+impl WeylusError {
+    pub fn new(code: ErrorCode, detail: impl Into<String>) -> Self {
+        Self {
+            code,
+            detail: Some(detail.into()),
+        }
+    }
+
+    /// Map an error from the C code, errors without a more specific code are reported as
+    /// `fallback`.
+    pub fn from_cerror(err: &CError, fallback: ErrorCode) -> Self {
+        match ErrorCode::from(err.to_enum()) {
+            // include instructions on how to fix this
+            ErrorCode::UInputNotAccessible => Self::new(
+                ErrorCode::UInputNotAccessible,
+                include_str!("strings/uinput_error.txt"),
+            ),
+            ErrorCode::Generic => Self::new(fallback, err.message()),
+            code => Self::new(code, err.message()),
+        }
+    }
+
+    /// Map an error that occurred while starting a screen capture.
+    pub fn from_capture_error(err: &(dyn std::error::Error + 'static)) -> Self {
+        #[cfg(target_os = "linux")]
+        if err
+            .downcast_ref::<crate::capturable::pipewire::ScreenCastDenied>()
+            .is_some()
+        {
+            return Self::new(ErrorCode::ScreenCastDenied, err.to_string());
+        }
+        Self::new(ErrorCode::ScreenCastFailed, err.to_string())
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(test, derive(schemars::JsonSchema))]
 pub struct Rect {
     pub x: f64,
     pub y: f64,
@@ -55,6 +302,7 @@ impl Default for Rect {
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(test, derive(schemars::JsonSchema))]
 pub struct CustomInputAreas {
     pub mouse: Option<Rect>,
     pub touch: Option<Rect>,
@@ -62,6 +310,7 @@ pub struct CustomInputAreas {
 }
 
 #[derive(Serialize, Deserialize, Debug)]
+#[cfg_attr(test, derive(schemars::JsonSchema))]
 pub enum PointerType {
     #[serde(rename = "")]
     Unknown,
@@ -74,6 +323,7 @@ pub enum PointerType {
 }
 
 #[derive(Serialize, Deserialize, Debug)]
+#[cfg_attr(test, derive(schemars::JsonSchema))]
 pub enum PointerEventType {
     #[serde(rename = "pointerdown")]
     DOWN,
@@ -94,6 +344,7 @@ pub enum PointerEventType {
 }
 
 #[derive(Serialize, Deserialize, Debug)]
+#[cfg_attr(test, derive(schemars::JsonSchema))]
 pub enum KeyboardEventType {
     #[serde(rename = "down")]
     DOWN,
@@ -146,11 +397,13 @@ fn button_from<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Button, D::
 }
 
 #[derive(Serialize, Deserialize, Debug)]
+#[cfg_attr(test, derive(schemars::JsonSchema))]
 pub struct KeyboardEvent {
     pub event_type: KeyboardEventType,
     pub code: String,
     pub key: String,
     #[serde(deserialize_with = "location_from")]
+    #[cfg_attr(test, schemars(with = "u8"))]
     pub location: KeyboardLocation,
     pub alt: bool,
     pub ctrl: bool,
@@ -159,6 +412,7 @@ pub struct KeyboardEvent {
 }
 
 #[derive(Serialize, Deserialize, Debug)]
+#[cfg_attr(test, derive(schemars::JsonSchema))]
 pub struct PointerEvent {
     pub event_type: PointerEventType,
     pub pointer_id: i64,
@@ -166,8 +420,10 @@ pub struct PointerEvent {
     pub is_primary: bool,
     pub pointer_type: PointerType,
     #[serde(deserialize_with = "button_from")]
+    #[cfg_attr(test, schemars(with = "u8"))]
     pub button: Button,
     #[serde(deserialize_with = "button_from")]
+    #[cfg_attr(test, schemars(with = "u8"))]
     pub buttons: Button,
     pub x: f64,
     pub y: f64,
@@ -182,16 +438,67 @@ pub struct PointerEvent {
 }
 
 #[derive(Serialize, Deserialize, Debug)]
+#[cfg_attr(test, derive(schemars::JsonSchema))]
 pub struct WheelEvent {
     pub dx: i32,
     pub dy: i32,
     pub timestamp: u64,
 }
 
+/// Encoding used for inbound messages sent as binary websocket frames.
+///
+/// Text frames are always parsed as JSON, so clients that do not negotiate a wire format keep
+/// working unchanged.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WireFormat {
+    #[default]
+    #[serde(rename = "json")]
+    Json,
+    #[serde(rename = "msgpack")]
+    MessagePack,
+}
+
+impl WireFormat {
+    pub fn from_query(s: &str) -> Option<Self> {
+        match s {
+            "json" => Some(Self::Json),
+            "msgpack" => Some(Self::MessagePack),
+            _ => None,
+        }
+    }
+}
+
 pub trait WeylusSender {
     type Error: std::error::Error;
     fn send_message(&mut self, message: MessageOutbound) -> Result<(), Self::Error>;
     fn send_video(&mut self, bytes: &[u8]) -> Result<(), Self::Error>;
+    /// Send a chunk of the audio stream announced by `NewAudio`, it is queued along with the
+    /// video.
+    fn send_audio(&mut self, bytes: &[u8]) -> Result<(), Self::Error>;
+
+    /// Send a message that describes the video, like `NewVideo` or `FrameInfo`. Unlike messages
+    /// sent via `send_message` it must not overtake video that has been sent before.
+    fn send_video_message(&mut self, message: MessageOutbound) -> Result<(), Self::Error> {
+        self.send_message(message)
+    }
+
+    /// Format video passed to `send_video` has to be encoded in. This is queried before every
+    /// frame and all video sent afterwards must be in the returned format until the next query.
+    fn video_format(&self) -> VideoFormat {
+        VideoFormat::FragmentedMp4
+    }
+
+    /// Returns true if video is piling up because the connection can not keep up. No new frames
+    /// should be encoded until this returns false again.
+    fn is_congested(&self) -> bool {
+        false
+    }
+
+    /// Handle WebRTC signalling sent by the client, returns false if WebRTC is not supported by
+    /// this transport.
+    fn handle_webrtc_signal(&mut self, _signal: WebRtcSignal) -> bool {
+        false
+    }
 }
 
 pub trait WeylusReceiver: Iterator<Item = Result<MessageInbound, Self::Error>> {
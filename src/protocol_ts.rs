@@ -0,0 +1,184 @@
+//! TypeScript declarations of the messages exchanged with the web client.
+//!
+//! protocol.rs is the single source of truth: the declarations are generated from the JSON schema
+//! of its types and checked in as ts/protocol.d.ts, where ts/lib.ts picks them up. The test fails
+//! if the checked in declarations are out of date, regenerate them with:
+//!
+//!     WEYLUS_UPDATE_PROTOCOL_TS=1 cargo test protocol_ts
+//!
+//! PROTOCOL_VERSION is declared with its value as type, so the TypeScript compiler rejects
+//! ts/lib.ts if its copy of the version differs.
+//!
+//! Some fields only exist on certain platforms, the declarations are generated on Linux.
+
+use schemars::SchemaGenerator;
+use serde_json::{Map, Value};
+
+use crate::protocol::{MessageInbound, MessageOutbound, PROTOCOL_VERSION};
+
+const PROTOCOL_TS: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/ts/protocol.d.ts");
+const INDENT: &str = "    ";
+
+fn protocol_ts() -> String {
+    let mut generator = SchemaGenerator::default();
+    // everything else is reachable from the messages
+    generator.subschema_for::<MessageInbound>();
+    generator.subschema_for::<MessageOutbound>();
+
+    let mut ts = String::from(
+        "// Generated from src/protocol.rs, do not edit! See src/protocol_ts.rs on how to update.\n\n\
+         declare namespace Protocol {\n",
+    );
+    for (i, (name, schema)) in generator.definitions().iter().enumerate() {
+        if i > 0 {
+            ts.push('\n');
+        }
+        ts.push_str(&doc_comment(schema, INDENT));
+        let variants: Vec<String> = match (schema.get("oneOf"), schema.get("enum")) {
+            (Some(Value::Array(variants)), _) => variants
+                .iter()
+                .flat_map(|v| match v.get("enum") {
+                    Some(Value::Array(values)) => values.iter().map(ts_literal).collect(),
+                    _ => vec![ts_type(v, INDENT)],
+                })
+                .collect(),
+            (_, Some(Value::Array(values))) => values.iter().map(ts_literal).collect(),
+            _ => vec![],
+        };
+        let ty = if variants.len() > 1 {
+            // put every variant on its own line
+            variants
+                .iter()
+                .map(|v| format!("\n{INDENT}{INDENT}| {v}"))
+                .collect()
+        } else {
+            format!(" {}", ts_type(schema, INDENT))
+        };
+        ts.push_str(&format!("{INDENT}export type {name} ={ty};\n"));
+    }
+    // declared as literal type, so that tsc rejects a client speaking another version
+    ts.push_str(&format!(
+        "\n{INDENT}/** Version of the protocol spoken by the server. */\n\
+         {INDENT}export const PROTOCOL_VERSION: {PROTOCOL_VERSION};\n"
+    ));
+    ts.push_str("}\n");
+    ts
+}
+
+fn doc_comment(schema: &Value, indent: &str) -> String {
+    match schema.get("description").and_then(Value::as_str) {
+        Some(description) if description.contains('\n') => {
+            let lines: String = description
+                .lines()
+                .map(|line| format!("{indent} * {line}\n"))
+                .collect();
+            format!("{indent}/**\n{lines}{indent} */\n")
+        }
+        Some(description) => format!("{indent}/** {description} */\n"),
+        None => String::new(),
+    }
+}
+
+fn ts_literal(value: &Value) -> String {
+    // JSON strings, numbers and booleans are valid TypeScript literals
+    value.to_string()
+}
+
+fn ts_union(types: impl Iterator<Item = String>) -> String {
+    types.collect::<Vec<_>>().join(" | ")
+}
+
+/// TypeScript type of `schema`, `indent` is the indentation of the line the type starts on.
+fn ts_type(schema: &Value, indent: &str) -> String {
+    if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+        return reference.trim_start_matches("#/$defs/").to_string();
+    }
+    if let Some(Value::Array(variants)) = schema.get("oneOf").or_else(|| schema.get("anyOf")) {
+        return ts_union(variants.iter().map(|v| ts_type(v, indent)));
+    }
+    if let Some(Value::Array(values)) = schema.get("enum") {
+        return ts_union(values.iter().map(ts_literal));
+    }
+    if let Some(value) = schema.get("const") {
+        return ts_literal(value);
+    }
+    match schema.get("type") {
+        Some(Value::String(ty)) => ts_primitive(ty, schema, indent),
+        Some(Value::Array(types)) => ts_union(
+            types
+                .iter()
+                .map(|ty| ts_primitive(ty.as_str().unwrap(), schema, indent)),
+        ),
+        _ => "unknown".to_string(),
+    }
+}
+
+fn ts_primitive(ty: &str, schema: &Value, indent: &str) -> String {
+    match ty {
+        "string" => "string".to_string(),
+        "integer" | "number" => "number".to_string(),
+        "boolean" => "boolean".to_string(),
+        "null" => "null".to_string(),
+        "array" => format!("Array<{}>", ts_type(&schema["items"], indent)),
+        "object" => ts_object(schema, indent),
+        _ => panic!("Unsupported JSON schema type: {ty}"),
+    }
+}
+
+fn ts_object(schema: &Value, indent: &str) -> String {
+    let empty = Map::new();
+    let properties = schema
+        .get("properties")
+        .and_then(Value::as_object)
+        .unwrap_or(&empty);
+    let required: Vec<&str> = schema
+        .get("required")
+        .and_then(Value::as_array)
+        .map(|r| r.iter().filter_map(Value::as_str).collect())
+        .unwrap_or_default();
+    let field = |(name, property): (&String, &Value), indent: &str| {
+        // missing fields are deserialized as None
+        let optional = if required.contains(&name.as_str()) {
+            ""
+        } else {
+            "?"
+        };
+        format!("{name}{optional}: {}", ts_type(property, indent))
+    };
+    // variants of externally tagged enums fit on a single line
+    if properties.len() == 1 && schema.get("additionalProperties") == Some(&Value::Bool(false)) {
+        return format!("{{ {} }}", field(properties.iter().next().unwrap(), indent));
+    }
+    let inner = format!("{indent}{INDENT}");
+    let fields: String = properties
+        .iter()
+        .map(|property| {
+            format!(
+                "{}{inner}{};\n",
+                doc_comment(property.1, &inner),
+                field(property, &inner)
+            )
+        })
+        .collect();
+    format!("{{\n{fields}{indent}}}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn protocol_ts_is_up_to_date() {
+        let generated = protocol_ts();
+        if std::env::var_os("WEYLUS_UPDATE_PROTOCOL_TS").is_some() {
+            std::fs::write(PROTOCOL_TS, generated).unwrap();
+            return;
+        }
+        let checked_in = std::fs::read_to_string(PROTOCOL_TS).unwrap_or_default();
+        assert!(
+            checked_in == generated,
+            "ts/protocol.d.ts does not match src/protocol.rs, regenerate it with: \
+             WEYLUS_UPDATE_PROTOCOL_TS=1 cargo test protocol_ts"
+        );
+    }
+}
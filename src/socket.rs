@@ -0,0 +1,435 @@
+//! Transport for native clients that do not run inside a browser.
+//!
+//! Clients connect via plain TCP or a Unix domain socket and exchange length-prefixed frames:
+//! a 4 byte big-endian length of the payload, followed by a single byte describing the kind of
+//! frame and the payload itself. The first frame a client sends has to be an auth frame
+//! containing the access code (empty if none is configured), which the server answers with an
+//! auth frame holding a single byte: 1 if the client has been accepted, 0 otherwise. After that
+//! the client sends MessageInbound as JSON or MessagePack frames and receives MessageOutbound as
+//! JSON frames, the video as video frames and the audio as audio frames.
+
+use std::io::{Read, Write};
+use std::net::{IpAddr, Shutdown, SocketAddr, TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{spawn, JoinHandle};
+use std::time::Duration;
+use std::{error::Error, fmt};
+
+use tracing::{debug, info, warn};
+
+use crate::approval::{approver, PendingApprovals};
+use crate::auth::{check_access_code, AuthLimiter, Permission};
+use crate::ip_filter::IpFilter;
+use crate::protocol::{MessageInbound, MessageOutbound, WeylusReceiver, WeylusSender};
+use crate::web::Web2UiMessage;
+use crate::websocket::{WeylusClientConfig, WeylusClientHandler};
+
+const FRAME_JSON: u8 = 0;
+const FRAME_MSGPACK: u8 = 1;
+const FRAME_VIDEO: u8 = 2;
+const FRAME_AUTH: u8 = 3;
+const FRAME_AUDIO: u8 = 4;
+
+// inbound frames are small, anything larger than this is considered garbage
+const MAX_INBOUND_FRAME_SIZE: usize = 1 << 20;
+const AUTH_TIMEOUT: Duration = Duration::from_secs(5);
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+trait SocketStream: Read + Write + Send + Sized + 'static {
+    fn try_clone(&self) -> std::io::Result<Self>;
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()>;
+    fn shutdown(&self) -> std::io::Result<()>;
+}
+
+impl SocketStream for TcpStream {
+    fn try_clone(&self) -> std::io::Result<Self> {
+        TcpStream::try_clone(self)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+
+    fn shutdown(&self) -> std::io::Result<()> {
+        TcpStream::shutdown(self, Shutdown::Both)
+    }
+}
+
+#[cfg(unix)]
+impl SocketStream for UnixStream {
+    fn try_clone(&self) -> std::io::Result<Self> {
+        UnixStream::try_clone(self)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        UnixStream::set_read_timeout(self, timeout)
+    }
+
+    fn shutdown(&self) -> std::io::Result<()> {
+        UnixStream::shutdown(self, Shutdown::Both)
+    }
+}
+
+#[derive(Debug)]
+pub enum SocketError {
+    Json(serde_json::Error),
+    MessagePack(rmp_serde::decode::Error),
+    UnexpectedFrame(u8),
+}
+
+impl fmt::Display for SocketError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SocketError::Json(err) => write!(f, "Failed to parse JSON message: {err}"),
+            SocketError::MessagePack(err) => {
+                write!(f, "Failed to parse MessagePack message: {err}")
+            }
+            SocketError::UnexpectedFrame(kind) => write!(f, "Unexpected frame of kind {kind}"),
+        }
+    }
+}
+
+impl Error for SocketError {}
+
+fn read_frame(reader: &mut impl Read) -> std::io::Result<(u8, Vec<u8>)> {
+    let mut header = [0u8; 5];
+    reader.read_exact(&mut header)?;
+    let len = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+    if len > MAX_INBOUND_FRAME_SIZE {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("Frame of {len} bytes exceeds maximum size of {MAX_INBOUND_FRAME_SIZE} bytes"),
+        ));
+    }
+    let mut payload = vec![0u8; len];
+    reader.read_exact(&mut payload)?;
+    Ok((header[4], payload))
+}
+
+fn write_frame(writer: &mut impl Write, kind: u8, payload: &[u8]) -> std::io::Result<()> {
+    let len: u32 = payload
+        .len()
+        .try_into()
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Frame too large"))?;
+    let mut header = [0u8; 5];
+    header[..4].copy_from_slice(&len.to_be_bytes());
+    header[4] = kind;
+    writer.write_all(&header)?;
+    writer.write_all(payload)?;
+    writer.flush()
+}
+
+pub struct SocketWeylusSender<W> {
+    writer: Arc<Mutex<W>>,
+}
+
+impl<W> Clone for SocketWeylusSender<W> {
+    fn clone(&self) -> Self {
+        Self {
+            writer: self.writer.clone(),
+        }
+    }
+}
+
+impl<W: Write> WeylusSender for SocketWeylusSender<W> {
+    type Error = std::io::Error;
+
+    fn send_message(&mut self, message: MessageOutbound) -> Result<(), Self::Error> {
+        let data = serde_json::to_vec(&message).unwrap();
+        write_frame(&mut *self.writer.lock().unwrap(), FRAME_JSON, &data)
+    }
+
+    fn send_video(&mut self, bytes: &[u8]) -> Result<(), Self::Error> {
+        write_frame(&mut *self.writer.lock().unwrap(), FRAME_VIDEO, bytes)
+    }
+
+    fn send_audio(&mut self, bytes: &[u8]) -> Result<(), Self::Error> {
+        write_frame(&mut *self.writer.lock().unwrap(), FRAME_AUDIO, bytes)
+    }
+}
+
+pub struct SocketWeylusReceiver<R> {
+    reader: R,
+}
+
+impl<R: Read> Iterator for SocketWeylusReceiver<R> {
+    type Item = Result<MessageInbound, SocketError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (kind, payload) = match read_frame(&mut self.reader) {
+            Ok(frame) => frame,
+            Err(err) => {
+                if err.kind() != std::io::ErrorKind::UnexpectedEof {
+                    warn!("Failed to read frame from socket: {err}.");
+                }
+                return None;
+            }
+        };
+        Some(match kind {
+            FRAME_JSON => serde_json::from_slice(&payload).map_err(SocketError::Json),
+            FRAME_MSGPACK => rmp_serde::from_slice(&payload).map_err(SocketError::MessagePack),
+            kind => Err(SocketError::UnexpectedFrame(kind)),
+        })
+    }
+}
+
+impl<R: Read> WeylusReceiver for SocketWeylusReceiver<R> {
+    type Error = SocketError;
+}
+
+/// Returns the permission granted to the client, `None` if it failed to authenticate.
+fn authenticate<S: SocketStream>(
+    stream: &mut S,
+    access_code: Option<&str>,
+    view_access_code: Option<&str>,
+) -> Option<Permission> {
+    if let Err(err) = stream.set_read_timeout(Some(AUTH_TIMEOUT)) {
+        warn!("Failed to set timeout on socket: {err}.");
+        return None;
+    }
+    let permission = match read_frame(stream) {
+        Ok((FRAME_AUTH, _)) if access_code.is_none() && view_access_code.is_none() => {
+            Some(Permission::Control)
+        }
+        Ok((FRAME_AUTH, code)) => check_access_code(&code, access_code, view_access_code),
+        Ok((kind, _)) => {
+            warn!("Expected auth frame, got frame of kind {kind}.");
+            None
+        }
+        Err(err) => {
+            warn!("Failed to read auth frame: {err}.");
+            None
+        }
+    };
+    if let Err(err) = write_frame(stream, FRAME_AUTH, &[permission.is_some().into()]) {
+        warn!("Failed to answer auth frame: {err}.");
+        return None;
+    }
+    if let Err(err) = stream.set_read_timeout(None) {
+        warn!("Failed to reset timeout on socket: {err}.");
+        return None;
+    }
+    permission
+}
+
+#[derive(Clone)]
+pub struct SocketServerConfig {
+    pub bind_addr: Option<SocketAddr>,
+    #[cfg(unix)]
+    pub path: Option<PathBuf>,
+    pub access_code: Option<String>,
+    pub view_access_code: Option<String>,
+    /// Only applies to clients connecting via TCP.
+    pub ip_filter: IpFilter,
+    /// Shared with the web server, only applies to clients connecting via TCP.
+    pub auth_limiter: Arc<AuthLimiter>,
+    pub require_approval: bool,
+}
+
+pub struct SocketServer {
+    shutdown: Arc<AtomicBool>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl SocketServer {
+    pub fn stop(&mut self) {
+        self.shutdown.store(true, Ordering::Relaxed);
+        for t in self.threads.drain(..) {
+            if t.join().is_err() {
+                warn!("Socket server thread panicked.");
+            }
+        }
+    }
+}
+
+impl Drop for SocketServer {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+trait SocketListener: Send + 'static {
+    type Stream: SocketStream;
+    /// Returns the stream, a description of the remote address and its IP, if there is one.
+    fn accept_stream(&self) -> std::io::Result<(Self::Stream, String, Option<IpAddr>)>;
+}
+
+impl SocketListener for TcpListener {
+    type Stream = TcpStream;
+
+    fn accept_stream(&self) -> std::io::Result<(Self::Stream, String, Option<IpAddr>)> {
+        let (stream, addr) = self.accept()?;
+        // the listener is non-blocking, but the connections should not be
+        stream.set_nonblocking(false)?;
+        stream.set_nodelay(true)?;
+        Ok((stream, addr.to_string(), Some(addr.ip())))
+    }
+}
+
+#[cfg(unix)]
+impl SocketListener for UnixListener {
+    type Stream = UnixStream;
+
+    fn accept_stream(&self) -> std::io::Result<(Self::Stream, String, Option<IpAddr>)> {
+        let (stream, addr) = self.accept()?;
+        stream.set_nonblocking(false)?;
+        Ok((stream, format!("{addr:?}"), None))
+    }
+}
+
+fn run_listener<L: SocketListener>(
+    listener: L,
+    shutdown: Arc<AtomicBool>,
+    config: SocketServerConfig,
+    sender_ui: tokio::sync::mpsc::Sender<Web2UiMessage>,
+    weylus_client_config: WeylusClientConfig,
+) {
+    let mut clients: Vec<(L::Stream, JoinHandle<()>)> = Vec::new();
+    let pending_approvals = Arc::new(PendingApprovals::default());
+    while !shutdown.load(Ordering::Relaxed) {
+        clients.retain(|(_, t)| !t.is_finished());
+        let (mut stream, remote_address, ip) = match listener.accept_stream() {
+            Ok(conn) => conn,
+            Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => {
+                std::thread::sleep(ACCEPT_POLL_INTERVAL);
+                continue;
+            }
+            Err(err) => {
+                warn!("Connection failed: {err}.");
+                continue;
+            }
+        };
+        if ip.is_some_and(|ip| !config.ip_filter.is_allowed(ip)) {
+            info!(
+                address = remote_address,
+                "Refused connection from address that is not allowed."
+            );
+            continue;
+        }
+        let Ok(attempts) = ip.map(|ip| config.auth_limiter.attempt(ip)).transpose() else {
+            info!(
+                address = remote_address,
+                "Refused native client, too many failed attempts."
+            );
+            continue;
+        };
+        debug!(address = remote_address, "Native client connected.");
+        let (stream_shutdown, reader) = match (stream.try_clone(), stream.try_clone()) {
+            (Ok(s), Ok(r)) => (s, r),
+            (Err(err), _) | (_, Err(err)) => {
+                warn!("Failed to clone socket: {err}.");
+                continue;
+            }
+        };
+        let access_code = config.access_code.clone();
+        let view_access_code = config.view_access_code.clone();
+        let approve = config.require_approval.then(|| {
+            approver(
+                sender_ui.clone(),
+                pending_approvals.clone(),
+                remote_address.clone(),
+                ip,
+            )
+        });
+        let auth_limiter = config.auth_limiter.clone();
+        let sender_ui = sender_ui.clone();
+        let thread = spawn(move || {
+            let permission = authenticate(
+                &mut stream,
+                access_code.as_deref(),
+                view_access_code.as_deref(),
+            );
+            let Some(permission) = permission else {
+                info!(
+                    address = remote_address,
+                    attempts, "Rejected native client."
+                );
+                return;
+            };
+            if let Some(ip) = ip {
+                auth_limiter.record_success(ip);
+            }
+            debug!(
+                address = remote_address,
+                ?permission,
+                "Native client authenticated."
+            );
+            let client = WeylusClientHandler::new(
+                SocketWeylusSender {
+                    writer: Arc::new(Mutex::new(stream)),
+                },
+                SocketWeylusReceiver { reader },
+                || {
+                    if let Err(err) = sender_ui.blocking_send(Web2UiMessage::UInputInaccessible) {
+                        warn!("Failed to send message 'UInputInaccessible': {err}.");
+                    }
+                },
+                WeylusClientConfig {
+                    view_only: permission == Permission::View,
+                    ..weylus_client_config
+                },
+                approve,
+                None,
+                None,
+            );
+            client.run();
+            debug!(address = remote_address, "Native client disconnected.");
+        });
+        clients.push((stream_shutdown, thread));
+    }
+    for (stream, thread) in clients {
+        stream.shutdown().ok();
+        if thread.join().is_err() {
+            warn!("Native client thread panicked.");
+        }
+    }
+}
+
+pub fn run(
+    config: SocketServerConfig,
+    sender_ui: tokio::sync::mpsc::Sender<Web2UiMessage>,
+    weylus_client_config: WeylusClientConfig,
+) -> std::io::Result<SocketServer> {
+    let shutdown = Arc::new(AtomicBool::new(false));
+    let mut threads = Vec::new();
+
+    if let Some(addr) = config.bind_addr {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        info!("Listening for native clients on {addr}.");
+        let shutdown = shutdown.clone();
+        let config = config.clone();
+        let sender_ui = sender_ui.clone();
+        threads.push(spawn(move || {
+            run_listener(listener, shutdown, config, sender_ui, weylus_client_config)
+        }));
+    }
+
+    #[cfg(unix)]
+    if let Some(path) = config.path.clone() {
+        // remove a stale socket from a previous run, binding fails otherwise
+        use std::os::unix::fs::FileTypeExt;
+        if std::fs::metadata(&path).is_ok_and(|m| m.file_type().is_socket()) {
+            std::fs::remove_file(&path)?;
+        }
+        let listener = UnixListener::bind(&path)?;
+        listener.set_nonblocking(true)?;
+        info!("Listening for native clients on {}.", path.display());
+        let shutdown = shutdown.clone();
+        let config = SocketServerConfig {
+            ip_filter: IpFilter::default(),
+            ..config
+        };
+        threads.push(spawn(move || {
+            run_listener(listener, shutdown, config, sender_ui, weylus_client_config);
+            std::fs::remove_file(&path).ok();
+        }));
+    }
+
+    Ok(SocketServer { shutdown, threads })
+}
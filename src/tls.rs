@@ -0,0 +1,131 @@
+//! TLS for the web server, browsers only provide several APIs to secure contexts and the access
+//! code should not be sent in plaintext.
+//!
+//! If the user does not supply a certificate, a self-signed one is generated once and kept in the
+//! config directory, so that browsers only have to be told to trust it once. It is valid for
+//! localhost and the addresses Weylus is reachable at when it is generated, delete it to generate
+//! a new one if these change.
+
+use std::error::Error;
+use std::fs;
+use std::io::Write;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use sha2::{Digest, Sha256};
+use tokio_rustls::rustls;
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tracing::info;
+
+const CERT_FILE: &str = "tls_cert.pem";
+const KEY_FILE: &str = "tls_key.pem";
+
+pub struct TlsIdentity {
+    pub server_config: Arc<rustls::ServerConfig>,
+    /// SHA-256 fingerprint of the certificate as colon separated hex digits.
+    pub fingerprint: String,
+}
+
+impl TlsIdentity {
+    /// Load certificate and key from the given PEM files or, if neither is given, use the
+    /// self-signed certificate from the config directory, generating it if necessary.
+    pub fn load(
+        cert: Option<&Path>,
+        key: Option<&Path>,
+        web_sock: &SocketAddr,
+    ) -> Result<Self, Box<dyn Error>> {
+        let (cert_path, key_path) = match (cert, key) {
+            (Some(cert), Some(key)) => (cert.to_path_buf(), key.to_path_buf()),
+            (None, None) => self_signed_paths(web_sock)?,
+            _ => {
+                return Err(
+                    "Either both or none of --tls-cert and --tls-key have to be set.".into(),
+                )
+            }
+        };
+
+        let certs = CertificateDer::pem_file_iter(&cert_path)
+            .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+            .map_err(|err| {
+                format!(
+                    "Failed to read TLS certificate from {}: {err}",
+                    cert_path.display()
+                )
+            })?;
+        let fingerprint = fingerprint(
+            certs
+                .first()
+                .ok_or_else(|| format!("No certificate found in {}.", cert_path.display()))?,
+        );
+        let key = PrivateKeyDer::from_pem_file(&key_path).map_err(|err| {
+            format!(
+                "Failed to read TLS private key from {}: {err}",
+                key_path.display()
+            )
+        })?;
+
+        let mut server_config = rustls::ServerConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?;
+        server_config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+        Ok(Self {
+            server_config: Arc::new(server_config),
+            fingerprint,
+        })
+    }
+}
+
+fn self_signed_paths(web_sock: &SocketAddr) -> Result<(PathBuf, PathBuf), Box<dyn Error>> {
+    let dir = crate::config::config_dir()
+        .ok_or("Failed to find configuration directory to store the TLS certificate in!")?;
+    let cert_path = dir.join(CERT_FILE);
+    let key_path = dir.join(KEY_FILE);
+    if !cert_path.exists() || !key_path.exists() {
+        info!(
+            "Generating self-signed TLS certificate in {}.",
+            dir.display()
+        );
+        let rcgen::CertifiedKey { cert, key_pair } =
+            rcgen::generate_simple_self_signed(subject_alt_names(web_sock))?;
+        fs::create_dir_all(&dir)?;
+        write_private(&key_path, &key_pair.serialize_pem())?;
+        fs::write(&cert_path, cert.pem())?;
+    }
+    Ok((cert_path, key_path))
+}
+
+/// Names the self-signed certificate is valid for, rcgen turns the addresses into IP SANs.
+fn subject_alt_names(web_sock: &SocketAddr) -> Vec<String> {
+    let mut ips: Vec<IpAddr> = Vec::new();
+    if web_sock.ip().is_unspecified() {
+        #[cfg(not(target_os = "windows"))]
+        ips.extend(crate::weylus::guess_ips(web_sock));
+    } else {
+        ips.push(web_sock.ip());
+    }
+    std::iter::once("localhost".to_string())
+        .chain(ips.iter().map(IpAddr::to_string))
+        .collect()
+}
+
+fn write_private(path: &Path, contents: &str) -> std::io::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)?.write_all(contents.as_bytes())
+}
+
+fn fingerprint(cert: &CertificateDer) -> String {
+    Sha256::digest(cert)
+        .iter()
+        .map(|b| format!("{b:02X}"))
+        .collect::<Vec<_>>()
+        .join(":")
+}
@@ -0,0 +1,248 @@
+//! Receive files uploaded by web clients and store them in the configured upload directory.
+//!
+//! The file is sent as the raw body of a POST request, its name is passed via the `name` query
+//! parameter.
+
+use std::convert::Infallible;
+use std::io::ErrorKind;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+use bytes::Bytes;
+use http_body_util::combinators::BoxBody;
+use http_body_util::BodyExt;
+use hyper::body::Incoming;
+use hyper::{Request, Response, StatusCode};
+use tokio::fs::{File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tracing::{info, warn};
+
+// longer names are truncated, most filesystems do not support more than 255 bytes
+const MAX_FILENAME_LENGTH: usize = 200;
+// give up on finding a free name after trying this many numbered ones
+const MAX_UNIQUE_ATTEMPTS: u32 = 1000;
+// device names Windows does not allow as file names, not even with an extension
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+fn response(status: StatusCode, body: String) -> Response<BoxBody<Bytes, Infallible>> {
+    Response::builder()
+        .status(status)
+        .header("content-type", "text/plain; charset=utf-8")
+        .body(body.boxed())
+        .unwrap()
+}
+
+/// Turn a filename supplied by the client into one that is safe to use in the upload directory:
+/// directories are stripped as well as characters that are invalid on common filesystems.
+pub fn sanitize_filename(name: &str) -> String {
+    let name = name.rsplit(['/', '\\']).next().unwrap_or_default();
+    let mut name: String = name
+        .chars()
+        .filter(|c| !c.is_control() && !matches!(c, '<' | '>' | ':' | '"' | '|' | '?' | '*'))
+        .collect();
+    if name.len() > MAX_FILENAME_LENGTH {
+        let mut end = MAX_FILENAME_LENGTH;
+        while !name.is_char_boundary(end) {
+            end -= 1;
+        }
+        name.truncate(end);
+    }
+    // no hidden files, "." or ".." and Windows does not allow trailing dots and spaces
+    let name = name.trim_matches(|c| c == '.' || c == ' ');
+    let stem = name.split('.').next().unwrap_or_default().trim_end();
+    if name.is_empty() {
+        "upload".to_string()
+    } else if RESERVED_NAMES
+        .iter()
+        .any(|reserved| stem.eq_ignore_ascii_case(reserved))
+    {
+        format!("_{name}")
+    } else {
+        name.to_string()
+    }
+}
+
+/// Create a new file in `dir`, appending a number to `name` if a file with that name exists
+/// already.
+async fn create_unique_file(dir: &Path, name: &str) -> std::io::Result<(File, PathBuf)> {
+    let (stem, extension) = match name.rfind('.') {
+        Some(i) if i > 0 => name.split_at(i),
+        _ => (name, ""),
+    };
+    for i in 0..MAX_UNIQUE_ATTEMPTS {
+        let path = if i == 0 {
+            dir.join(name)
+        } else {
+            dir.join(format!("{stem} ({i}){extension}"))
+        };
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .await
+        {
+            Ok(file) => return Ok((file, path)),
+            Err(err) if err.kind() == ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    Err(std::io::Error::new(
+        ErrorKind::AlreadyExists,
+        format!("{MAX_UNIQUE_ATTEMPTS} files named like {name} exist already"),
+    ))
+}
+
+pub async fn handle_upload(
+    addr: SocketAddr,
+    req: Request<Incoming>,
+    name: &str,
+    upload_dir: Option<&PathBuf>,
+    max_size: u64,
+) -> Response<BoxBody<Bytes, Infallible>> {
+    let upload_dir = match upload_dir {
+        Some(dir) => dir,
+        None => return response(StatusCode::FORBIDDEN, "Uploads are disabled.".into()),
+    };
+    let too_large = || {
+        response(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("Files may not be larger than {max_size} bytes."),
+        )
+    };
+    let content_length = req
+        .headers()
+        .get(hyper::header::CONTENT_LENGTH)
+        .and_then(|len| len.to_str().ok())
+        .and_then(|len| len.parse::<u64>().ok());
+    if content_length.is_some_and(|len| len > max_size) {
+        return too_large();
+    }
+
+    if let Err(err) = tokio::fs::create_dir_all(upload_dir).await {
+        warn!(
+            "Failed to create upload directory {}: {err}",
+            upload_dir.display()
+        );
+        return response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Failed to create upload directory.".into(),
+        );
+    }
+    let name = sanitize_filename(name);
+    let (mut file, path) = match create_unique_file(upload_dir, &name).await {
+        Ok(file) => file,
+        Err(err) => {
+            warn!("Failed to create file for upload {name}: {err}");
+            return response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to create file.".into(),
+            );
+        }
+    };
+
+    let mut body = req.into_body();
+    let mut size = 0u64;
+    let result = loop {
+        let frame = match body.frame().await {
+            Some(Ok(frame)) => frame,
+            Some(Err(err)) => break Err(response(StatusCode::BAD_REQUEST, err.to_string())),
+            None => break Ok(()),
+        };
+        if let Ok(data) = frame.into_data() {
+            size += data.len() as u64;
+            if size > max_size {
+                break Err(too_large());
+            }
+            if let Err(err) = file.write_all(&data).await {
+                warn!("Failed to write upload {}: {err}", path.display());
+                break Err(response(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Failed to write file.".into(),
+                ));
+            }
+        }
+    };
+    let result = match result {
+        Ok(()) => file.flush().await.map_err(|err| {
+            warn!("Failed to write upload {}: {err}", path.display());
+            response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to write file.".into(),
+            )
+        }),
+        err => err,
+    };
+    drop(file);
+
+    match result {
+        Ok(()) => {
+            info!(address = ?addr, "Received upload, saved {size} bytes to {}.", path.display());
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            response(StatusCode::OK, name.into_owned())
+        }
+        Err(response) => {
+            // do not leave incomplete files behind
+            if let Err(err) = tokio::fs::remove_file(&path).await {
+                warn!(
+                    "Failed to remove incomplete upload {}: {err}",
+                    path.display()
+                );
+            }
+            response
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_directories() {
+        assert_eq!(sanitize_filename("../../etc/passwd"), "passwd");
+        assert_eq!(sanitize_filename("/etc/passwd"), "passwd");
+        assert_eq!(sanitize_filename("..\\..\\Windows\\win.ini"), "win.ini");
+        assert_eq!(sanitize_filename("C:\\Users\\me\\notes.txt"), "notes.txt");
+        assert_eq!(sanitize_filename("dir/"), "upload");
+        assert_eq!(sanitize_filename(".."), "upload");
+        assert_eq!(sanitize_filename("a/.."), "upload");
+    }
+
+    #[test]
+    fn strips_invalid_characters() {
+        assert_eq!(sanitize_filename("evil\0.txt"), "evil.txt");
+        assert_eq!(sanitize_filename("a\nb\tc.txt"), "abc.txt");
+        assert_eq!(sanitize_filename("what?<*>|\":.txt"), "what.txt");
+        assert_eq!(sanitize_filename("\0"), "upload");
+    }
+
+    #[test]
+    fn empty_and_hidden_names() {
+        assert_eq!(sanitize_filename(""), "upload");
+        assert_eq!(sanitize_filename("   "), "upload");
+        assert_eq!(sanitize_filename(".bashrc"), "bashrc");
+        assert_eq!(sanitize_filename("name. . "), "name");
+    }
+
+    #[test]
+    fn reserved_names() {
+        assert_eq!(sanitize_filename("CON"), "_CON");
+        assert_eq!(sanitize_filename("nul.txt"), "_nul.txt");
+        assert_eq!(sanitize_filename("Com1.tar.gz"), "_Com1.tar.gz");
+        assert_eq!(sanitize_filename("lpt9 .txt"), "_lpt9 .txt");
+        assert_eq!(sanitize_filename("CONSOLE.txt"), "CONSOLE.txt");
+        assert_eq!(sanitize_filename("COM10"), "COM10");
+    }
+
+    #[test]
+    fn truncates_long_names() {
+        let name = sanitize_filename(&"ä".repeat(MAX_FILENAME_LENGTH));
+        assert!(name.len() <= MAX_FILENAME_LENGTH);
+        assert!(name.chars().all(|c| c == 'ä'));
+        let name = sanitize_filename(&format!("{}. .txt", "a".repeat(MAX_FILENAME_LENGTH - 2)));
+        assert_eq!(name, "a".repeat(MAX_FILENAME_LENGTH - 2));
+    }
+}
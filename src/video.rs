@@ -16,6 +16,7 @@ extern "C" {
         try_nvenc: c_int,
         try_videotoolbox: c_int,
         try_mediafoundation: c_int,
+        annexb: c_int,
     ) -> *mut c_void;
     fn open_video(handle: *mut c_void, err: *mut CError);
     fn destroy_video_encoder(handle: *mut c_void);
@@ -64,12 +65,42 @@ pub struct EncoderOptions {
     pub try_mediafoundation: bool,
 }
 
+impl EncoderOptions {
+    /// Names of the ffmpeg encoders that may be used, in the order they are tried by
+    /// lib/encode_video.c. libx264 is always available as fallback.
+    pub fn backends(&self) -> Vec<String> {
+        [
+            (self.try_vaapi, "h264_vaapi"),
+            (self.try_mediafoundation, "h264_mf"),
+            (self.try_nvenc, "h264_nvenc"),
+            (self.try_videotoolbox, "h264_videotoolbox"),
+            (true, "libx264"),
+        ]
+        .iter()
+        .filter(|(enabled, _)| *enabled)
+        .map(|(_, name)| name.to_string())
+        .collect()
+    }
+}
+
+/// Container the encoded video is written in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum VideoFormat {
+    /// Fragmented mp4 as consumed by Media Source Extensions in the browser.
+    #[default]
+    FragmentedMp4,
+    /// Raw H.264 elementary stream in Annex B format, one access unit per write, for transports
+    /// that do their own packetization like WebRTC.
+    H264AnnexB,
+}
+
 pub struct VideoEncoder {
     handle: *mut c_void,
     width_in: usize,
     height_in: usize,
     width_out: usize,
     height_out: usize,
+    format: VideoFormat,
     write_data: Box<dyn FnMut(&[u8])>,
     start_time: Instant,
 }
@@ -82,6 +113,7 @@ impl VideoEncoder {
         height_out: usize,
         mut write_data: impl FnMut(&[u8]) + 'static,
         options: EncoderOptions,
+        format: VideoFormat,
     ) -> Result<Box<Self>, CError> {
         let mut video_encoder = Box::new(Self {
             handle: std::ptr::null_mut(),
@@ -89,6 +121,7 @@ impl VideoEncoder {
             height_in,
             width_out,
             height_out,
+            format,
             write_data: Box::new(move |data| write_data(data)),
             start_time: Instant::now(),
         });
@@ -103,6 +136,7 @@ impl VideoEncoder {
                 options.try_nvenc.into(),
                 options.try_videotoolbox.into(),
                 options.try_mediafoundation.into(),
+                (format == VideoFormat::H264AnnexB).into(),
             )
         };
         video_encoder.handle = handle;
@@ -160,6 +194,10 @@ impl VideoEncoder {
             && (self.width_out == width_out)
             && (self.height_out == height_out)
     }
+
+    pub fn format(&self) -> VideoFormat {
+        self.format
+    }
 }
 
 impl Drop for VideoEncoder {
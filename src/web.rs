@@ -2,7 +2,7 @@ use bytes::Bytes;
 use fastwebsockets::upgrade;
 use handlebars::Handlebars;
 use http_body_util::combinators::BoxBody;
-use http_body_util::{BodyExt, Full};
+use http_body_util::{BodyExt, Full, Limited};
 use hyper::body::Incoming;
 use hyper::server::conn::http1;
 use hyper::service::service_fn;
@@ -16,11 +16,39 @@ use std::path::PathBuf;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::TcpListener;
 use tokio::sync::{mpsc, oneshot};
+use tokio_rustls::{rustls, TlsAcceptor};
 use tracing::{debug, error, info, warn};
 
-use crate::websocket::{weylus_websocket_channel, WeylusClientConfig, WeylusClientHandler};
+use crate::approval::{self, ApprovalRequest, PendingApprovals};
+use crate::auth::{check_access_code, AuthLimiter, LoginSessions, Permission};
+use crate::ip_filter::IpFilter;
+use crate::pairing::{self, Pairing};
+use crate::protocol::WireFormat;
+use crate::upload::handle_upload;
+use crate::websocket::{
+    weylus_websocket_channel, Sessions, WeylusClientConfig, WeylusClientHandler, WsWeylusReceiver,
+};
+
+#[cfg(feature = "webrtc")]
+type WebSender = crate::webrtc::WebRtcWeylusSender;
+#[cfg(not(feature = "webrtc"))]
+type WebSender = crate::websocket::WsWeylusSender;
+
+const LOGIN_COOKIE: &str = "weylus_login";
+const DEVICE_COOKIE: &str = "weylus_device";
+// paired devices stay paired until they are revoked
+const DEVICE_COOKIE_MAX_AGE: Duration = Duration::from_secs(10 * 365 * 24 * 60 * 60);
+
+// the login and pairing forms only contain the access code or the pairing token
+const MAX_LOGIN_SIZE: usize = 4096;
+
+// do not let clients that never finish the handshake occupy a connection forever
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+// how often clients whose login expired are disconnected
+const LOGIN_EXPIRY_INTERVAL: Duration = Duration::from_secs(60);
 
 #[derive(Debug)]
 pub enum WebStartUpMessage {
@@ -30,20 +58,30 @@ pub enum WebStartUpMessage {
 
 pub enum Web2UiMessage {
     UInputInaccessible,
+    ApprovalRequest(ApprovalRequest),
 }
 
 pub const INDEX_HTML: &str = std::include_str!("../www/templates/index.html");
+pub const PAIR_HTML: &str = std::include_str!("../www/templates/pair.html");
 pub const ACCESS_HTML: &str = std::include_str!("../www/static/access_code.html");
 pub const STYLE_CSS: &str = std::include_str!("../www/static/style.css");
 pub const LIB_JS: &str = std::include_str!("../www/static/lib.js");
 
 #[derive(Serialize)]
 struct IndexTemplateContext {
-    access_code: Option<String>,
+    login_required: bool,
+    view_only: bool,
     uinput_enabled: bool,
     capture_cursor_enabled: bool,
     log_level: String,
     enable_custom_input_areas: bool,
+    upload_enabled: bool,
+    upload_requires_approval: bool,
+}
+
+#[derive(Serialize)]
+struct PairTemplateContext<'a> {
+    token: &'a str,
 }
 
 fn response_from_str(s: &str, content_type: &str) -> Response<Full<Bytes>> {
@@ -62,6 +100,59 @@ fn response_not_found() -> Response<Full<Bytes>> {
         .unwrap()
 }
 
+fn response_too_many_attempts(wait: Duration) -> Response<Full<Bytes>> {
+    let secs = wait.as_secs_f64().ceil() as u64;
+    Response::builder()
+        .status(StatusCode::TOO_MANY_REQUESTS)
+        .header("content-type", "text/plain; charset=utf-8")
+        .header("retry-after", secs)
+        .body(format!("Too many wrong access codes, try again in {secs} seconds.").into())
+        .unwrap()
+}
+
+fn get_cookie<'a>(req: &'a Request<Incoming>, name: &str) -> Option<&'a str> {
+    req.headers()
+        .get_all(hyper::header::COOKIE)
+        .iter()
+        .filter_map(|cookies| cookies.to_str().ok())
+        .flat_map(|cookies| cookies.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find_map(|(key, value)| (key == name).then_some(value))
+}
+
+fn response_redirect_home() -> hyper::http::response::Builder {
+    Response::builder()
+        .status(StatusCode::SEE_OTHER)
+        .header("location", "/")
+}
+
+/// Value of a Set-Cookie header for a cookie that is only sent to Weylus.
+fn cookie(name: &str, value: &str, max_age: Duration, secure: bool) -> String {
+    format!(
+        "{name}={value}; Path=/; Max-Age={}; HttpOnly; SameSite=Lax{}",
+        max_age.as_secs(),
+        if secure { "; Secure" } else { "" }
+    )
+}
+
+/// Permission of the client and the login session token or device credential it has been
+/// granted with, paired devices have full control. `None` if the client is not logged in.
+fn logged_in_permission<'a>(
+    addr: SocketAddr,
+    req: &'a Request<Incoming>,
+    context: &Context,
+) -> Option<(Permission, &'a str)> {
+    if let Some(token) = get_cookie(req, LOGIN_COOKIE) {
+        if let Some(permission) = context.login_sessions.permission(token) {
+            return Some((permission, token));
+        }
+    }
+    let credential = get_cookie(req, DEVICE_COOKIE)?;
+    let device = pairing::verify_credential(credential)?;
+    debug!(address = ?addr, device = device.id, "Paired device authenticated.");
+    Some((Permission::Control, credential))
+}
+
 async fn response_from_path_or_default(
     path: Option<&PathBuf>,
     default: &str,
@@ -89,45 +180,60 @@ async fn serve(
     notify_disconnect: Arc<tokio::sync::Notify>,
 ) -> Result<Response<BoxBody<Bytes, Infallible>>, hyper::Error> {
     debug!("Got request: {:?}", req);
-    let mut authed = false;
-    if let Some(access_code) = &context.web_config.access_code {
-        if req.method() == Method::GET && (req.uri().path() == "/" || req.uri().path() == "/ws") {
-            use url::form_urlencoded;
-            if let Some(query) = req.uri().query() {
-                let params = form_urlencoded::parse(query.as_bytes())
-                    .into_owned()
-                    .collect::<HashMap<String, String>>();
-                if let Some(code) = params.get("access_code") {
-                    if code == access_code {
-                        authed = true;
-                        debug!(address = ?addr, "Web-Client authenticated.");
-                    }
-                }
-            }
+    let params = req.uri().query().map_or_else(HashMap::new, |query| {
+        url::form_urlencoded::parse(query.as_bytes())
+            .into_owned()
+            .collect::<HashMap<String, String>>()
+    });
+    let path = req.uri().path().to_string();
+    let allowed_method = match path.as_str() {
+        "/login" | "/logout" | "/upload" => req.method() == Method::POST,
+        // GET asks for confirmation, only POST pairs the device
+        "/pair" => matches!(*req.method(), Method::GET | Method::POST),
+        _ => req.method() == Method::GET,
+    };
+    if !allowed_method {
+        return Ok(response_not_found().map(|r| r.boxed()));
+    }
+    let secure = context.web_config.tls_config.is_some();
+    let login_required =
+        context.web_config.access_code.is_some() || context.web_config.view_access_code.is_some();
+    let (permission, login) = if login_required {
+        match logged_in_permission(addr, &req, &context) {
+            Some((permission, login)) => (Some(permission), Some(login.to_string())),
+            None => (None, None),
         }
     } else {
-        authed = true;
-    }
-    if req.method() != Method::GET {
-        return Ok(response_not_found().map(|r| r.boxed()));
+        (Some(Permission::Control), None)
+    };
+    let view_only = permission == Some(Permission::View);
+    // the login page needs the stylesheet
+    if permission.is_none() && !matches!(path.as_str(), "/login" | "/pair" | "/style.css") {
+        if path == "/" {
+            return Ok(response_from_path_or_default(
+                context.web_config.custom_access_html.as_ref(),
+                ACCESS_HTML,
+                "text/html; charset=utf-8",
+            )
+            .await
+            .map(|r| r.boxed()));
+        }
+        return Ok(Response::builder()
+            .status(StatusCode::UNAUTHORIZED)
+            .body("unauthorized".to_string().boxed())
+            .unwrap());
     }
-    match req.uri().path() {
+    match path.as_str() {
         "/" => {
-            if !authed {
-                return Ok(response_from_path_or_default(
-                    context.web_config.custom_access_html.as_ref(),
-                    ACCESS_HTML,
-                    "text/html; charset=utf-8",
-                )
-                .await
-                .map(|r| r.boxed()));
-            }
             let config = IndexTemplateContext {
-                access_code: context.web_config.access_code.clone(),
+                login_required,
+                view_only,
                 uinput_enabled: cfg!(target_os = "linux"),
                 capture_cursor_enabled: cfg!(not(target_os = "windows")),
                 log_level: crate::log::get_log_level().to_string(),
                 enable_custom_input_areas: context.web_config.enable_custom_input_areas,
+                upload_enabled: context.web_config.upload_dir.is_some() && !view_only,
+                upload_requires_approval: context.web_config.require_approval,
             };
 
             let html = if let Some(path) = context.web_config.custom_index_html.as_ref() {
@@ -153,22 +259,65 @@ async fn serve(
             }
         }
         "/ws" => {
-            if !authed {
-                return Ok(Response::builder()
-                    .status(StatusCode::UNAUTHORIZED)
-                    .body("unauthorized".to_string().boxed())
-                    .unwrap());
-            }
+            let wire_format = match params.get("wire_format") {
+                Some(s) => match WireFormat::from_query(s) {
+                    Some(wire_format) => wire_format,
+                    None => {
+                        return Ok(Response::builder()
+                            .status(StatusCode::BAD_REQUEST)
+                            .body(format!("unknown wire format: {s}").boxed())
+                            .unwrap());
+                    }
+                },
+                None => WireFormat::Json,
+            };
+            debug!(address = ?addr, "Using wire format {wire_format:?}.");
 
             let (response, fut) = upgrade::upgrade(&mut req).unwrap();
             num_clients.fetch_add(1, Ordering::Relaxed);
 
-            let config = context.weylus_client_config.clone();
+            let config = WeylusClientConfig {
+                view_only,
+                ..context.weylus_client_config
+            };
+            let idle_timeout = context.web_config.idle_timeout;
+            let sessions = context.sessions.clone();
+            // a view-only client must not take over a session with full control, which the same
+            // browser tab may have had before logging in again
+            let session_token = params.get("session").filter(|_| !view_only).cloned();
+            let approve = context.web_config.require_approval.then(|| {
+                approval::approver(
+                    sender_ui.clone(),
+                    context.pending_approvals.clone(),
+                    addr.to_string(),
+                    Some(addr.ip()),
+                )
+            });
             tokio::spawn(async move {
                 match fut.await {
                     Ok(ws) => {
-                        let (sender, receiver) =
-                            weylus_websocket_channel(ws, semaphore_websocket_shutdown);
+                        let (sender, receiver) = weylus_websocket_channel(
+                            ws,
+                            wire_format,
+                            idle_timeout,
+                            semaphore_websocket_shutdown,
+                        );
+                        #[cfg(feature = "webrtc")]
+                        let sender = crate::webrtc::WebRtcWeylusSender::new(sender);
+                        let (sender, receiver) = match session_token {
+                            Some(token) => {
+                                match sessions.resume(&token, login.as_deref(), sender, receiver) {
+                                    Ok(()) => {
+                                        debug!(address = ?addr, "Resumed session.");
+                                        // the client is still counted by its original session
+                                        num_clients.fetch_sub(1, Ordering::Relaxed);
+                                        return;
+                                    }
+                                    Err(connection) => connection,
+                                }
+                            }
+                            None => (sender, receiver),
+                        };
                         std::thread::spawn(move || {
                             let client = WeylusClientHandler::new(
                                 sender,
@@ -183,6 +332,9 @@ async fn serve(
                                     }
                                 },
                                 config,
+                                approve,
+                                Some(sessions),
+                                login,
                             );
                             client.run();
                             num_clients.fetch_sub(1, Ordering::Relaxed);
@@ -199,6 +351,207 @@ async fn serve(
 
             Ok(response.map(|r| r.boxed()))
         }
+        "/login" => {
+            if !login_required {
+                return Ok(response_redirect_home()
+                    .body(String::new().boxed())
+                    .unwrap());
+            }
+            let attempts = match context.web_config.auth_limiter.attempt(addr.ip()) {
+                Ok(attempts) => attempts,
+                Err(wait) => {
+                    info!(address = ?addr, "Refused login, too many failed attempts.");
+                    return Ok(response_too_many_attempts(wait).map(|r| r.boxed()));
+                }
+            };
+            let body = match Limited::new(req.into_body(), MAX_LOGIN_SIZE)
+                .collect()
+                .await
+            {
+                Ok(body) => body.to_bytes(),
+                Err(err) => {
+                    return Ok(Response::builder()
+                        .status(StatusCode::BAD_REQUEST)
+                        .body(err.to_string().boxed())
+                        .unwrap())
+                }
+            };
+            let code = url::form_urlencoded::parse(&body)
+                .find(|(key, _)| key == "access_code")
+                .map(|(_, code)| code)
+                .unwrap_or_default();
+            let permission = check_access_code(
+                code.as_bytes(),
+                context.web_config.access_code.as_deref(),
+                context.web_config.view_access_code.as_deref(),
+            );
+            let Some(permission) = permission else {
+                warn!(address = ?addr, attempts, "Web-Client sent wrong access code.");
+                let mut response = response_from_path_or_default(
+                    context.web_config.custom_access_html.as_ref(),
+                    ACCESS_HTML,
+                    "text/html; charset=utf-8",
+                )
+                .await;
+                *response.status_mut() = StatusCode::UNAUTHORIZED;
+                return Ok(response.map(|r| r.boxed()));
+            };
+            context.web_config.auth_limiter.record_success(addr.ip());
+            info!(address = ?addr, ?permission, "Web-Client logged in.");
+            let token = context.login_sessions.create(permission);
+            Ok(response_redirect_home()
+                .header(
+                    "set-cookie",
+                    cookie(
+                        LOGIN_COOKIE,
+                        &token,
+                        context.login_sessions.lifetime(),
+                        secure,
+                    ),
+                )
+                .body(String::new().boxed())
+                .unwrap())
+        }
+        "/logout" => {
+            if let Some(token) = get_cookie(&req, LOGIN_COOKIE) {
+                context.login_sessions.remove(token);
+                context.sessions.end(token);
+            }
+            // logging out a paired device unpairs it, it would be logged in again right away
+            // otherwise
+            if let Some(credential) = get_cookie(&req, DEVICE_COOKIE) {
+                context.sessions.end(credential);
+                match pairing::revoke_credential(credential) {
+                    Ok(true) => info!(address = ?addr, "Unpaired device on logout."),
+                    Ok(false) => (),
+                    Err(err) => error!("Failed to unpair device: {err}"),
+                }
+            }
+            debug!(address = ?addr, "Web-Client logged out.");
+            Ok(response_redirect_home()
+                .header(
+                    "set-cookie",
+                    cookie(LOGIN_COOKIE, "", Duration::ZERO, secure),
+                )
+                .header(
+                    "set-cookie",
+                    cookie(DEVICE_COOKIE, "", Duration::ZERO, secure),
+                )
+                .body(String::new().boxed())
+                .unwrap())
+        }
+        "/pair" if req.method() == Method::GET => {
+            // opening the link must not use up the token, link previews and browsers loading
+            // pages in advance would pair themselves otherwise
+            let token = params.get("token").map_or("", String::as_str);
+            match context
+                .templates
+                .render("pair", &PairTemplateContext { token })
+            {
+                Ok(html) => {
+                    Ok(response_from_str(&html, "text/html; charset=utf-8").map(|r| r.boxed()))
+                }
+                Err(err) => {
+                    error!("Failed to render pair template: {}", err);
+                    Ok(response_not_found().map(|r| r.boxed()))
+                }
+            }
+        }
+        "/pair" => {
+            let attempts = match context.web_config.auth_limiter.attempt(addr.ip()) {
+                Ok(attempts) => attempts,
+                Err(wait) => {
+                    info!(address = ?addr, "Refused pairing, too many failed attempts.");
+                    return Ok(response_too_many_attempts(wait).map(|r| r.boxed()));
+                }
+            };
+            let body = match Limited::new(req.body_mut(), MAX_LOGIN_SIZE).collect().await {
+                Ok(body) => body.to_bytes(),
+                Err(err) => {
+                    return Ok(Response::builder()
+                        .status(StatusCode::BAD_REQUEST)
+                        .body(err.to_string().boxed())
+                        .unwrap())
+                }
+            };
+            let token = url::form_urlencoded::parse(&body)
+                .find(|(key, _)| key == "token")
+                .map(|(_, token)| token)
+                .unwrap_or_default();
+            if !context.web_config.pairing.redeem(&token) {
+                warn!(address = ?addr, attempts, "Web-Client sent invalid pairing token.");
+                return Ok(Response::builder()
+                    .status(StatusCode::FORBIDDEN)
+                    .header("content-type", "text/plain; charset=utf-8")
+                    .body(
+                        "This pairing code is invalid or has expired, scan a new one."
+                            .to_string()
+                            .boxed(),
+                    )
+                    .unwrap());
+            }
+            context.web_config.auth_limiter.record_success(addr.ip());
+            let name = req
+                .headers()
+                .get(hyper::header::USER_AGENT)
+                .and_then(|agent| agent.to_str().ok())
+                .unwrap_or("Unknown device");
+            match pairing::add_device(name) {
+                Ok(credential) => {
+                    info!(address = ?addr, "Paired new device: {name}");
+                    Ok(response_redirect_home()
+                        .header(
+                            "set-cookie",
+                            cookie(DEVICE_COOKIE, &credential, DEVICE_COOKIE_MAX_AGE, secure),
+                        )
+                        .body(String::new().boxed())
+                        .unwrap())
+                }
+                Err(err) => {
+                    error!("Failed to store paired device: {err}");
+                    Ok(Response::builder()
+                        .status(StatusCode::INTERNAL_SERVER_ERROR)
+                        .body("Failed to pair device.".to_string().boxed())
+                        .unwrap())
+                }
+            }
+        }
+        "/upload" => {
+            if view_only {
+                return Ok(Response::builder()
+                    .status(StatusCode::FORBIDDEN)
+                    .body(
+                        "View-only clients may not upload files."
+                            .to_string()
+                            .boxed(),
+                    )
+                    .unwrap());
+            }
+            // clients only learn their session token once the host accepted them
+            if context.web_config.require_approval
+                && !params
+                    .get("session")
+                    .is_some_and(|token| context.sessions.contains(token, login.as_deref()))
+            {
+                return Ok(Response::builder()
+                    .status(StatusCode::FORBIDDEN)
+                    .body(
+                        "Files may only be uploaded once the host accepted this client."
+                            .to_string()
+                            .boxed(),
+                    )
+                    .unwrap());
+            }
+            let name = params.get("name").map_or("", String::as_str);
+            Ok(handle_upload(
+                addr,
+                req,
+                name,
+                context.web_config.upload_dir.as_ref(),
+                context.web_config.max_upload_size,
+            )
+            .await)
+        }
         "/style.css" => Ok(response_from_path_or_default(
             context.web_config.custom_style_css.as_ref(),
             STYLE_CSS,
@@ -221,17 +574,38 @@ async fn serve(
 pub struct WebServerConfig {
     pub bind_addr: SocketAddr,
     pub access_code: Option<String>,
+    pub view_access_code: Option<String>,
     pub custom_index_html: Option<PathBuf>,
     pub custom_access_html: Option<PathBuf>,
     pub custom_style_css: Option<PathBuf>,
     pub custom_lib_js: Option<PathBuf>,
     pub enable_custom_input_areas: bool,
+    pub idle_timeout: Option<Duration>,
+    pub upload_dir: Option<PathBuf>,
+    /// In bytes.
+    pub max_upload_size: u64,
+    /// Serve HTTPS instead of HTTP if set.
+    pub tls_config: Option<Arc<rustls::ServerConfig>>,
+    pub pairing: Arc<Pairing>,
+    /// Shared with the server for native clients.
+    pub auth_limiter: Arc<AuthLimiter>,
+    pub session_lifetime: Duration,
+    pub ip_filter: IpFilter,
+    pub require_approval: bool,
 }
 
+/// Plain TCP or TLS stream.
+trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
 struct Context<'a> {
     web_config: WebServerConfig,
     weylus_client_config: WeylusClientConfig,
     templates: Handlebars<'a>,
+    sessions: Arc<Sessions<WebSender, WsWeylusReceiver>>,
+    login_sessions: LoginSessions,
+    pending_approvals: Arc<PendingApprovals>,
 }
 
 pub fn run(
@@ -245,11 +619,17 @@ pub fn run(
     templates
         .register_template_string("index", INDEX_HTML)
         .unwrap();
+    templates
+        .register_template_string("pair", PAIR_HTML)
+        .unwrap();
 
     let context = Context {
+        login_sessions: LoginSessions::new(web_server_config.session_lifetime),
         web_config: web_server_config,
         weylus_client_config,
         templates,
+        sessions: Arc::new(Sessions::default()),
+        pending_approvals: Arc::new(PendingApprovals::default()),
     };
     std::thread::spawn(move || run_server(context, sender_ui, sender_startup, notify_shutdown))
 }
@@ -278,10 +658,14 @@ async fn run_server(
 
     let broadcast_shutdown = Arc::new(tokio::sync::Notify::new());
 
+    let tls_acceptor = context.web_config.tls_config.clone().map(TlsAcceptor::from);
+
     let num_clients = Arc::new(AtomicUsize::new(0));
     let notify_disconnect = Arc::new(tokio::sync::Notify::new());
     let semaphore_websocket_shutdown = Arc::new(tokio::sync::Semaphore::new(0));
 
+    let mut login_expiry = tokio::time::interval(LOGIN_EXPIRY_INTERVAL);
+
     loop {
         let (tcp, remote_address) = tokio::select! {
             res = listener.accept() => {
@@ -298,12 +682,22 @@ async fn run_server(
                 broadcast_shutdown.notify_waiters();
                 break;
             }
+            _ = login_expiry.tick() => {
+                for token in context.login_sessions.remove_expired() {
+                    context.sessions.end(&token);
+                }
+                continue;
+            }
         };
 
-        debug!(address = ?remote_address, "Client connected.");
+        if !context.web_config.ip_filter.is_allowed(remote_address.ip()) {
+            info!(address = ?remote_address, "Refused connection from address that is not allowed.");
+            continue;
+        }
 
-        let io = TokioIo::new(tcp);
+        debug!(address = ?remote_address, "Client connected.");
 
+        let tls_acceptor = tls_acceptor.clone();
         let sender_ui = sender_ui.clone();
         let broadcast_shutdown = broadcast_shutdown.clone();
         let context = context.clone();
@@ -312,6 +706,24 @@ async fn run_server(
         let notify_disconnect = notify_disconnect.clone();
 
         tokio::task::spawn(async move {
+            let stream: Box<dyn Stream> = match tls_acceptor {
+                Some(acceptor) => {
+                    match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(tcp)).await {
+                        Ok(Ok(stream)) => Box::new(stream),
+                        Ok(Err(err)) => {
+                            debug!("TLS handshake failed ({remote_address}): {err}.");
+                            return;
+                        }
+                        Err(_) => {
+                            debug!("TLS handshake timed out ({remote_address}).");
+                            return;
+                        }
+                    }
+                }
+                None => Box::new(tcp),
+            };
+            let io = TokioIo::new(stream);
+
             let conn = http1::Builder::new().serve_connection(
                 io,
                 service_fn({
@@ -349,6 +761,7 @@ async fn run_server(
         });
     }
 
+    context.sessions.shutdown();
     semaphore_websocket_shutdown.add_permits(num_clients.load(Ordering::Relaxed));
 
     loop {
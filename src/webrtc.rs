@@ -0,0 +1,356 @@
+//! WebRTC transport for video and input.
+//!
+//! The session is negotiated via [`WebRtcSignal`] messages relayed over the websocket: the client
+//! sends an offer with a receive only video transceiver and a data channel, the server answers and
+//! both sides trickle their ICE candidates. Once the peer connection is established video is
+//! encoded as raw H.264 and sent as RTP, input sent over the data channel is handled just like
+//! input received via the websocket. Until then, or if WebRTC fails, everything keeps going over
+//! the websocket.
+
+use std::error::Error;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use tokio::runtime::Handle;
+use tokio::sync::mpsc::error::SendError;
+use tokio::sync::mpsc::WeakSender;
+use tracing::{debug, info, warn};
+use webrtc::api::interceptor_registry::register_default_interceptors;
+use webrtc::api::media_engine::{MediaEngine, MIME_TYPE_H264};
+use webrtc::api::APIBuilder;
+use webrtc::data_channel::data_channel_message::DataChannelMessage;
+use webrtc::data_channel::RTCDataChannel;
+use webrtc::ice_transport::ice_candidate::{RTCIceCandidate, RTCIceCandidateInit};
+use webrtc::interceptor::registry::Registry;
+use webrtc::media::Sample;
+use webrtc::peer_connection::configuration::RTCConfiguration;
+use webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState;
+use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
+use webrtc::peer_connection::RTCPeerConnection;
+use webrtc::rtp_transceiver::rtp_codec::RTCRtpCodecCapability;
+use webrtc::track::track_local::track_local_static_sample::TrackLocalStaticSample;
+use webrtc::track::track_local::TrackLocal;
+
+use crate::protocol::{
+    ErrorCode, IceCandidate, MessageInbound, MessageOutbound, WebRtcSignal, WeylusError,
+    WeylusSender,
+};
+use crate::video::VideoFormat;
+use crate::websocket::{WsMessage, WsWeylusSender};
+
+#[derive(Debug)]
+pub enum WebRtcError {
+    WebSocket(SendError<WsMessage>),
+    WebRtc(webrtc::Error),
+}
+
+impl fmt::Display for WebRtcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WebRtcError::WebSocket(err) => write!(f, "{err}"),
+            WebRtcError::WebRtc(err) => write!(f, "WebRTC error: {err}"),
+        }
+    }
+}
+
+impl Error for WebRtcError {}
+
+impl From<SendError<WsMessage>> for WebRtcError {
+    fn from(err: SendError<WsMessage>) -> Self {
+        WebRtcError::WebSocket(err)
+    }
+}
+
+impl From<webrtc::Error> for WebRtcError {
+    fn from(err: webrtc::Error) -> Self {
+        WebRtcError::WebRtc(err)
+    }
+}
+
+struct Session {
+    runtime: Handle,
+    peer_connection: Arc<RTCPeerConnection>,
+    track: Arc<TrackLocalStaticSample>,
+    connected: Arc<AtomicBool>,
+    // format latched by the last call to video_format
+    format: VideoFormat,
+    last_sample: Option<Instant>,
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        let peer_connection = self.peer_connection.clone();
+        self.runtime.spawn(async move {
+            if let Err(err) = peer_connection.close().await {
+                warn!("Failed to close WebRTC peer connection: {err}");
+            }
+        });
+    }
+}
+
+/// Sends video via WebRTC once a session has been negotiated and falls back to the websocket
+/// otherwise. Messages are always sent via the websocket.
+#[derive(Clone)]
+pub struct WebRtcWeylusSender {
+    sender: WsWeylusSender,
+    runtime: Handle,
+    session: Arc<Mutex<Option<Session>>>,
+}
+
+impl WebRtcWeylusSender {
+    /// Must be called from within the tokio runtime.
+    pub fn new(sender: WsWeylusSender) -> Self {
+        Self {
+            sender,
+            runtime: Handle::current(),
+            session: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    async fn answer(&self, offer: String) -> Result<(), WebRtcError> {
+        // a new offer replaces the current session
+        let old_session = self.session.lock().unwrap().take();
+        drop(old_session);
+
+        let mut media_engine = MediaEngine::default();
+        media_engine.register_default_codecs()?;
+        let registry = register_default_interceptors(Registry::new(), &mut media_engine)?;
+        let api = APIBuilder::new()
+            .with_media_engine(media_engine)
+            .with_interceptor_registry(registry)
+            .build();
+        // no ICE servers: Weylus is meant to be used within the local network
+        let peer_connection = Arc::new(api.new_peer_connection(RTCConfiguration::default()).await?);
+
+        let track = Arc::new(TrackLocalStaticSample::new(
+            RTCRtpCodecCapability {
+                mime_type: MIME_TYPE_H264.to_owned(),
+                ..Default::default()
+            },
+            "video".to_owned(),
+            "weylus".to_owned(),
+        ));
+        let rtp_sender = peer_connection
+            .add_track(Arc::clone(&track) as Arc<dyn TrackLocal + Send + Sync>)
+            .await?;
+        // RTCP packets have to be read for interceptors like NACK to work
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; 1500];
+            while rtp_sender.read(&mut buf).await.is_ok() {}
+        });
+
+        let connected = Arc::new(AtomicBool::new(false));
+        {
+            let connected = connected.clone();
+            peer_connection.on_peer_connection_state_change(Box::new(
+                move |state: RTCPeerConnectionState| {
+                    info!("WebRTC connection state changed: {state}");
+                    connected.store(
+                        state == RTCPeerConnectionState::Connected,
+                        Ordering::Relaxed,
+                    );
+                    Box::pin(async {})
+                },
+            ));
+        }
+
+        {
+            let sender = self.sender.clone();
+            peer_connection.on_ice_candidate(Box::new(
+                move |candidate: Option<RTCIceCandidate>| {
+                    let sender = sender.clone();
+                    Box::pin(async move {
+                        if let Some(candidate) = candidate {
+                            let candidate = match candidate.to_json() {
+                                Ok(candidate) => candidate,
+                                Err(err) => {
+                                    warn!("Failed to serialize ICE candidate: {err}");
+                                    return;
+                                }
+                            };
+                            let msg = MessageOutbound::WebRtcSignal(WebRtcSignal::IceCandidate(
+                                IceCandidate {
+                                    candidate: candidate.candidate,
+                                    sdp_mid: candidate.sdp_mid,
+                                    sdp_mline_index: candidate.sdp_mline_index,
+                                },
+                            ));
+                            if let Err(err) = sender.send_message_async(msg).await {
+                                warn!("Failed to send ICE candidate: {err}");
+                            }
+                        }
+                    })
+                },
+            ));
+        }
+
+        {
+            let inbound = self.sender.inbound();
+            peer_connection.on_data_channel(Box::new(move |channel: Arc<RTCDataChannel>| {
+                debug!("WebRTC data channel opened: {}", channel.label());
+                let inbound = inbound.clone();
+                Box::pin(async move {
+                    channel.on_message(Box::new(move |msg: DataChannelMessage| {
+                        let inbound = inbound.clone();
+                        Box::pin(async move { forward_data_channel_message(msg, inbound).await })
+                    }));
+                })
+            }));
+        }
+
+        peer_connection
+            .set_remote_description(RTCSessionDescription::offer(offer)?)
+            .await?;
+        let answer = peer_connection.create_answer(None).await?;
+        peer_connection.set_local_description(answer).await?;
+        if let Some(description) = peer_connection.local_description().await {
+            self.sender
+                .send_message_async(MessageOutbound::WebRtcSignal(WebRtcSignal::Answer(
+                    description.sdp,
+                )))
+                .await?;
+        }
+
+        *self.session.lock().unwrap() = Some(Session {
+            runtime: self.runtime.clone(),
+            peer_connection,
+            track,
+            connected,
+            format: VideoFormat::FragmentedMp4,
+            last_sample: None,
+        });
+        Ok(())
+    }
+
+    async fn add_ice_candidate(&self, candidate: IceCandidate) -> Result<(), WebRtcError> {
+        let peer_connection = match self.session.lock().unwrap().as_ref() {
+            Some(session) => session.peer_connection.clone(),
+            None => {
+                warn!("Got ICE candidate before WebRTC offer, ignoring it.");
+                return Ok(());
+            }
+        };
+        peer_connection
+            .add_ice_candidate(RTCIceCandidateInit {
+                candidate: candidate.candidate,
+                sdp_mid: candidate.sdp_mid,
+                sdp_mline_index: candidate.sdp_mline_index,
+                username_fragment: None,
+            })
+            .await?;
+        Ok(())
+    }
+}
+
+async fn forward_data_channel_message(
+    msg: DataChannelMessage,
+    inbound: WeakSender<MessageInbound>,
+) {
+    let msg: MessageInbound = if msg.is_string {
+        match serde_json::from_slice(&msg.data) {
+            Ok(msg) => msg,
+            Err(err) => {
+                warn!("Failed to parse message from data channel: {err}");
+                return;
+            }
+        }
+    } else {
+        match rmp_serde::from_slice(&msg.data) {
+            Ok(msg) => msg,
+            Err(err) => {
+                warn!("Failed to parse binary message from data channel: {err}");
+                return;
+            }
+        }
+    };
+    if let Some(inbound) = inbound.upgrade() {
+        if let Err(err) = inbound.send(msg).await {
+            warn!("Failed to forward data channel message to WeylusClientHandler: {err}.");
+        }
+    }
+}
+
+impl WeylusSender for WebRtcWeylusSender {
+    type Error = WebRtcError;
+
+    fn send_message(&mut self, message: MessageOutbound) -> Result<(), Self::Error> {
+        Ok(self.sender.send_message(message)?)
+    }
+
+    fn send_video(&mut self, bytes: &[u8]) -> Result<(), Self::Error> {
+        let mut session = self.session.lock().unwrap();
+        match session.as_mut() {
+            Some(session) if session.format == VideoFormat::H264AnnexB => {
+                let now = Instant::now();
+                let duration = session
+                    .last_sample
+                    .map(|last_sample| now - last_sample)
+                    .unwrap_or(Duration::ZERO);
+                session.last_sample = Some(now);
+                self.runtime.block_on(session.track.write_sample(&Sample {
+                    data: Bytes::copy_from_slice(bytes),
+                    duration,
+                    ..Default::default()
+                }))?;
+                Ok(())
+            }
+            _ => Ok(self.sender.send_video(bytes)?),
+        }
+    }
+
+    fn send_video_message(&mut self, message: MessageOutbound) -> Result<(), Self::Error> {
+        Ok(self.sender.send_video_message(message)?)
+    }
+
+    fn send_audio(&mut self, bytes: &[u8]) -> Result<(), Self::Error> {
+        Ok(self.sender.send_audio(bytes)?)
+    }
+
+    fn video_format(&self) -> VideoFormat {
+        match self.session.lock().unwrap().as_mut() {
+            Some(session) => {
+                session.format = if session.connected.load(Ordering::Relaxed) {
+                    VideoFormat::H264AnnexB
+                } else {
+                    VideoFormat::FragmentedMp4
+                };
+                session.format
+            }
+            None => VideoFormat::FragmentedMp4,
+        }
+    }
+
+    fn is_congested(&self) -> bool {
+        // write_sample does not queue, only video sent via the websocket can pile up
+        match self.session.lock().unwrap().as_ref() {
+            Some(session) if session.format == VideoFormat::H264AnnexB => false,
+            _ => self.sender.is_congested(),
+        }
+    }
+
+    fn handle_webrtc_signal(&mut self, signal: WebRtcSignal) -> bool {
+        let res = match signal {
+            WebRtcSignal::Offer(offer) => self.runtime.block_on(self.answer(offer)),
+            WebRtcSignal::IceCandidate(candidate) => {
+                self.runtime.block_on(self.add_ice_candidate(candidate))
+            }
+            WebRtcSignal::Answer(_) => {
+                warn!("Got unexpected WebRTC answer from client, ignoring it.");
+                Ok(())
+            }
+        };
+        if let Err(err) = res {
+            warn!("WebRTC signalling failed: {err}");
+            if let Err(err) = self.send_message(MessageOutbound::Error(WeylusError::new(
+                ErrorCode::WebRtcFailed,
+                "Failed to establish WebRTC connection!",
+            ))) {
+                warn!("Failed to send message to client: {err}");
+            }
+        }
+        true
+    }
+}
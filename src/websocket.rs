@@ -1,19 +1,38 @@
 use fastwebsockets::{FragmentCollectorRead, Frame, OpCode, WebSocket, WebSocketError};
 use hyper::upgrade::Upgraded;
 use hyper_util::rt::TokioIo;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use std::collections::HashMap;
+#[cfg(target_os = "linux")]
+use std::collections::HashSet;
 use std::convert::Infallible;
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::mpsc::RecvTimeoutError;
-use std::sync::{mpsc, Arc};
+use std::sync::{mpsc, Arc, Mutex};
 use std::thread::{spawn, JoinHandle};
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tokio::sync::mpsc::channel;
-use tracing::{error, trace, warn};
+use tracing::{debug, error, info, trace, warn};
 
-use crate::capturable::{get_capturables, Capturable, Recorder};
-use crate::input::device::{InputDevice, InputDeviceType};
+#[cfg(target_os = "linux")]
+use crate::audio::AudioStream;
+#[cfg(target_os = "linux")]
+use crate::capturable::get_screen_casts;
+#[cfg(target_os = "linux")]
+use crate::capturable::Geometry;
+use crate::capturable::{capturable_watcher, get_capturables, Capturable, Recorder};
+#[cfg(target_os = "linux")]
+use crate::clipboard::{Clipboard, ClipboardWatcher};
+#[cfg(target_os = "linux")]
+use crate::cursor::CursorWatcher;
+use crate::input::device::{available_input_device_types, InputDevice, InputDeviceType};
 use crate::protocol::{
-    ClientConfiguration, KeyboardEvent, MessageInbound, MessageOutbound, PointerEvent,
-    WeylusReceiver, WeylusSender, WheelEvent,
+    CapturableInfo, ClientConfiguration, ClientHello, ClipboardContent, CursorPosition,
+    CursorShape, ErrorCode, FrameInfo, KeyboardEvent, LatencyReport, MessageInbound,
+    MessageOutbound, Ping, PointerEvent, Pong, ServerInfo, SessionInfo, WeylusError,
+    WeylusReceiver, WeylusSender, WheelEvent, WireFormat, PROTOCOL_VERSION,
 };
 
 use crate::cerror::CErrorCode;
@@ -27,11 +46,181 @@ struct VideoConfig {
     frame_rate: f64,
 }
 
-enum VideoCommands {
+enum VideoCommands<S> {
     Start(VideoConfig),
     Pause,
     Resume,
     Restart,
+    /// Continue sending video to a new connection after the session has been resumed.
+    Reattach(S),
+}
+
+/// Everything the WeylusClientHandler reacts to.
+enum ClientEvent {
+    // a message received via the connection with the given id
+    Message(u32, Result<MessageInbound, String>),
+    CapturablesChanged,
+    #[cfg(target_os = "linux")]
+    ClipboardChanged,
+    #[cfg(target_os = "linux")]
+    CursorMoved(CursorPosition),
+    #[cfg(target_os = "linux")]
+    CursorShapeChanged(CursorShape),
+    // the audio stream of the given generation dropped data and has to be restarted
+    #[cfg(target_os = "linux")]
+    RestartAudio(u32),
+    SendPing,
+    // the connection with the given id has been closed
+    Closed(u32),
+    // the client reconnected to resume its session
+    Resume,
+    // the client logged out or its login expired
+    End,
+}
+
+const PING_INTERVAL: Duration = Duration::from_secs(2);
+const SESSION_GRACE_PERIOD: Duration = Duration::from_secs(30);
+// messages a client sends before its hello are kept until the host accepted it, up to this many
+const MAX_MESSAGES_AWAITING_APPROVAL: usize = 64;
+#[cfg(target_os = "linux")]
+const CURSOR_POLL_INTERVAL: Duration = Duration::from_millis(8);
+#[cfg(target_os = "linux")]
+const CURSOR_GEOMETRY_INTERVAL: Duration = Duration::from_millis(500);
+
+struct Session<S, R> {
+    // credential the client authenticated with, see `Sessions::end`
+    login: Option<String>,
+    // new connections of the client resuming the session
+    connections: mpsc::Sender<(S, R)>,
+    // wakes up the handler of the session to switch to the new connection
+    events: mpsc::Sender<ClientEvent>,
+}
+
+/// Sessions of connected clients and of disconnected clients waiting to be resumed, keyed by
+/// session token.
+pub struct Sessions<S, R> {
+    // None once the server is shutting down
+    sessions: Mutex<Option<HashMap<String, Session<S, R>>>>,
+}
+
+impl<S, R> Default for Sessions<S, R> {
+    fn default() -> Self {
+        Self {
+            sessions: Mutex::new(Some(HashMap::new())),
+        }
+    }
+}
+
+impl<S, R> Sessions<S, R> {
+    /// Hand the connection over to the session with the given token. The connection is returned
+    /// if there is no such session or it has been started with another login.
+    ///
+    /// The session does not have to notice that its old connection is gone first: after a network
+    /// outage the old connection is usually only declared dead after the idle timeout, the new
+    /// connection replaces it right away.
+    pub fn resume(
+        &self,
+        token: &str,
+        login: Option<&str>,
+        sender: S,
+        receiver: R,
+    ) -> Result<(), (S, R)> {
+        let sessions = self.sessions.lock().unwrap();
+        let session = match sessions.as_ref().and_then(|sessions| sessions.get(token)) {
+            Some(session) if session.login.as_deref() == login => session,
+            _ => return Err((sender, receiver)),
+        };
+        session
+            .connections
+            .send((sender, receiver))
+            .map_err(|err| err.0)?;
+        // fails if the handler is waiting for the connection already and about to exit
+        session.events.send(ClientEvent::Resume).ok();
+        Ok(())
+    }
+
+    /// Whether there is a session with the given token that has been started with the given login.
+    pub fn contains(&self, token: &str, login: Option<&str>) -> bool {
+        self.sessions
+            .lock()
+            .unwrap()
+            .as_ref()
+            .and_then(|sessions| sessions.get(token))
+            .is_some_and(|session| session.login.as_deref() == login)
+    }
+
+    /// End all sessions started with the given login, for example because the client logged out.
+    pub fn end(&self, login: &str) {
+        if let Some(sessions) = self.sessions.lock().unwrap().as_mut() {
+            sessions.retain(|_, session| {
+                if session.login.as_deref() != Some(login) {
+                    return true;
+                }
+                // handlers waiting to be resumed stop once their session is dropped
+                session.events.send(ClientEvent::End).ok();
+                false
+            });
+        }
+    }
+
+    /// Drop all sessions and do not accept new ones, handlers waiting to be resumed stop.
+    pub fn shutdown(&self) {
+        *self.sessions.lock().unwrap() = None;
+    }
+
+    fn register(
+        &self,
+        token: String,
+        login: Option<String>,
+        events: mpsc::Sender<ClientEvent>,
+    ) -> Option<mpsc::Receiver<(S, R)>> {
+        self.sessions.lock().unwrap().as_mut().map(|sessions| {
+            let (connections, receiver) = mpsc::channel();
+            sessions.insert(
+                token,
+                Session {
+                    login,
+                    connections,
+                    events,
+                },
+            );
+            receiver
+        })
+    }
+
+    fn unregister(&self, token: &str) {
+        if let Some(sessions) = self.sessions.lock().unwrap().as_mut() {
+            sessions.remove(token);
+        }
+    }
+}
+
+/// Latencies in microseconds, shared with the video thread. Zero means not measured yet.
+#[derive(Default)]
+struct Latency {
+    rtt: AtomicU64,
+    video_latency: AtomicU64,
+}
+
+impl Latency {
+    fn set(value: &AtomicU64, millis: f64) {
+        value.store((millis * 1000.0).max(0.0) as u64, Ordering::Relaxed);
+    }
+
+    fn get(value: &AtomicU64) -> Option<Duration> {
+        match value.load(Ordering::Relaxed) {
+            0 => None,
+            micros => Some(Duration::from_micros(micros)),
+        }
+    }
+}
+
+fn unix_time_millis() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+        * 1000.0
 }
 
 fn send_message<S>(sender: &mut S, message: MessageOutbound)
@@ -43,18 +232,68 @@ where
     }
 }
 
+fn send_video_message<S>(sender: &mut S, message: MessageOutbound)
+where
+    S: WeylusSender,
+{
+    if let Err(err) = sender.send_video_message(message) {
+        warn!("Failed to send message to client: {err}");
+    }
+}
+
+/// Asks the host whether to accept a new client, gets the name the client sent with its hello.
+pub type ApproveClient = Box<dyn FnOnce(Option<String>) -> bool + Send>;
+
 pub struct WeylusClientHandler<S, R, FnUInput> {
     sender: S,
     receiver: Option<R>,
-    video_sender: mpsc::Sender<VideoCommands>,
+    video_sender: mpsc::Sender<VideoCommands<S>>,
     input_device: Option<Box<dyn InputDevice>>,
     capturables: Vec<Box<dyn Capturable>>,
+    #[cfg(target_os = "linux")]
+    screen_casts: Vec<Box<dyn Capturable>>,
+    capturables_requested: bool,
     on_uinput_inaccessible: FnUInput,
     config: WeylusClientConfig,
+    // set until the host accepted the client, other messages are kept until the hello is approved
+    approve: Option<ApproveClient>,
     #[cfg(target_os = "linux")]
     capture_cursor: bool,
     client_name: Option<String>,
     video_thread: JoinHandle<()>,
+    latency: Arc<Latency>,
+    ping_id: u32,
+    pending_ping: Option<(u32, Instant)>,
+    sessions: Option<Arc<Sessions<S, R>>>,
+    session_token: String,
+    // credential the client authenticated with, logging out ends the session
+    login: Option<String>,
+    // connections of the client resuming the session, set once the session is registered
+    connections: Option<mpsc::Receiver<(S, R)>>,
+    // increased whenever the client reconnects, to ignore the closing of replaced connections
+    connection_id: u32,
+    resumed: bool,
+    capturable_id: Option<String>,
+    #[cfg(target_os = "linux")]
+    clipboard: Option<Clipboard>,
+    // last clipboard content sent to or received from the client
+    #[cfg(target_os = "linux")]
+    last_clipboard: Option<ClipboardContent>,
+    #[cfg(target_os = "linux")]
+    stop_clipboard_watcher: Option<Arc<AtomicBool>>,
+    #[cfg(target_os = "linux")]
+    stop_cursor_watcher: Option<Arc<AtomicBool>>,
+    // hashes of the cursor images the client already has
+    #[cfg(target_os = "linux")]
+    cursor_hashes: HashSet<String>,
+    // shape last sent to the client, including its image
+    #[cfg(target_os = "linux")]
+    cursor_shape: Option<CursorShape>,
+    #[cfg(target_os = "linux")]
+    audio: Option<AudioStream>,
+    // incremented whenever the audio stream is replaced to ignore restarts of the previous one
+    #[cfg(target_os = "linux")]
+    audio_generation: u32,
 }
 
 #[derive(Clone, Copy)]
@@ -63,6 +302,8 @@ pub struct WeylusClientConfig {
     #[cfg(target_os = "linux")]
     pub wayland_support: bool,
     pub no_gui: bool,
+    /// Only send video, input from the client is ignored.
+    pub view_only: bool,
 }
 
 impl<S, R, FnUInput> WeylusClientHandler<S, R, FnUInput> {
@@ -71,17 +312,22 @@ impl<S, R, FnUInput> WeylusClientHandler<S, R, FnUInput> {
         receiver: R,
         on_uinput_inaccessible: FnUInput,
         config: WeylusClientConfig,
+        approve: Option<ApproveClient>,
+        sessions: Option<Arc<Sessions<S, R>>>,
+        login: Option<String>,
     ) -> Self
     where
         R: WeylusReceiver,
         S: WeylusSender + Clone + Send + Sync + 'static,
     {
-        let (video_sender, video_receiver) = mpsc::channel::<VideoCommands>();
+        let (video_sender, video_receiver) = mpsc::channel::<VideoCommands<S>>();
+        let latency = Arc::new(Latency::default());
         let video_thread = {
             let sender = sender.clone();
+            let latency = latency.clone();
             // offload creating the videostream to another thread to avoid blocking the thread that
             // is receiving messages from the websocket
-            spawn(move || handle_video(video_receiver, sender, config.encoder_options))
+            spawn(move || handle_video(video_receiver, sender, config.encoder_options, latency))
         };
 
         Self {
@@ -90,63 +336,189 @@ impl<S, R, FnUInput> WeylusClientHandler<S, R, FnUInput> {
             video_sender,
             input_device: None,
             capturables: vec![],
+            #[cfg(target_os = "linux")]
+            screen_casts: vec![],
+            capturables_requested: false,
             on_uinput_inaccessible,
             config,
+            approve,
             #[cfg(target_os = "linux")]
             capture_cursor: false,
             client_name: None,
             video_thread,
+            latency,
+            ping_id: 0,
+            pending_ping: None,
+            sessions,
+            session_token: rand::thread_rng()
+                .sample_iter(&Alphanumeric)
+                .take(32)
+                .map(char::from)
+                .collect(),
+            login,
+            connections: None,
+            connection_id: 0,
+            resumed: false,
+            capturable_id: None,
+            #[cfg(target_os = "linux")]
+            clipboard: None,
+            #[cfg(target_os = "linux")]
+            last_clipboard: None,
+            #[cfg(target_os = "linux")]
+            stop_clipboard_watcher: None,
+            #[cfg(target_os = "linux")]
+            stop_cursor_watcher: None,
+            #[cfg(target_os = "linux")]
+            cursor_hashes: HashSet::new(),
+            #[cfg(target_os = "linux")]
+            cursor_shape: None,
+            #[cfg(target_os = "linux")]
+            audio: None,
+            #[cfg(target_os = "linux")]
+            audio_generation: 0,
         }
     }
 
     pub fn run(mut self)
     where
-        R: WeylusReceiver,
+        R: WeylusReceiver + Send + 'static,
         S: WeylusSender + Clone + Send + Sync + 'static,
         FnUInput: Fn(),
     {
-        for message in self.receiver.take().unwrap() {
-            match message {
-                Ok(message) => {
+        let (event_sender, events) = mpsc::channel::<ClientEvent>();
+        spawn_reader(
+            self.receiver.take().unwrap(),
+            event_sender.clone(),
+            self.connection_id,
+        );
+        if let Some(sessions) = &self.sessions {
+            self.connections = sessions.register(
+                self.session_token.clone(),
+                self.login.clone(),
+                event_sender.clone(),
+            );
+        }
+        let stop_threads = Arc::new(AtomicBool::new(false));
+        {
+            let event_sender = event_sender.clone();
+            let stop_threads = stop_threads.clone();
+            spawn(move || watch_capturables(event_sender, stop_threads));
+        }
+        {
+            let event_sender = event_sender.clone();
+            let stop_threads = stop_threads.clone();
+            spawn(move || {
+                while !stop_threads.load(Ordering::Relaxed) {
+                    std::thread::sleep(PING_INTERVAL);
+                    if event_sender.send(ClientEvent::SendPing).is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+
+        let mut awaiting_approval = Vec::new();
+        for event in events {
+            match event {
+                // the connection has been replaced by the client resuming the session
+                ClientEvent::Message(id, _) if id != self.connection_id => {
+                    trace!("Dropping message from replaced connection.")
+                }
+                ClientEvent::Message(_, Ok(message)) => {
                     trace!("Received message: {message:?}");
                     match message {
-                        MessageInbound::PointerEvent(event) => self.process_pointer_event(&event),
-                        MessageInbound::WheelEvent(event) => self.process_wheel_event(&event),
-                        MessageInbound::KeyboardEvent(event) => self.process_keyboard_event(&event),
-                        MessageInbound::GetCapturableList => self.send_capturable_list(),
-                        MessageInbound::Config(config) => self.update_config(config),
-                        MessageInbound::PauseVideo => {
-                            self.video_sender.send(VideoCommands::Pause).unwrap()
-                        }
-                        MessageInbound::ResumeVideo => {
-                            self.video_sender.send(VideoCommands::Resume).unwrap()
-                        }
-                        MessageInbound::RestartVideo => {
-                            self.video_sender.send(VideoCommands::Restart).unwrap()
+                        MessageInbound::Hello(hello) if self.approve.is_some() => {
+                            if !self.approve(&hello) {
+                                break;
+                            }
+                            self.process_hello(hello);
+                            // messages sent before the hello are processed once the client has
+                            // been accepted
+                            for message in std::mem::take(&mut awaiting_approval) {
+                                self.process_message(message, &event_sender);
+                            }
                         }
-                        MessageInbound::ChooseCustomInputAreas => {
-                            let (sender, receiver) = std::sync::mpsc::channel();
-                            crate::gui::get_input_area(self.config.no_gui, sender);
-                            let mut sender = self.sender.clone();
-                            spawn(move || {
-                                while let Ok(areas) = receiver.recv() {
-                                    send_message(
-                                        &mut sender,
-                                        MessageOutbound::CustomInputAreas(areas),
-                                    );
-                                }
-                            });
+                        _ if self.approve.is_some() => {
+                            if awaiting_approval.len() < MAX_MESSAGES_AWAITING_APPROVAL {
+                                awaiting_approval.push(message);
+                            } else {
+                                trace!("Dropping message from client awaiting approval.")
+                            }
                         }
+                        message => self.process_message(message, &event_sender),
                     }
                 }
-                Err(err) => {
+                ClientEvent::Message(_, Err(err)) => {
                     warn!("Failed to read message {err}!");
-                    self.send_message(MessageOutbound::Error(
-                        "Failed to read message!".to_string(),
-                    ));
+                    self.send_message(MessageOutbound::Error(WeylusError::new(
+                        ErrorCode::InvalidMessage,
+                        "Failed to read message!",
+                    )));
+                }
+                ClientEvent::CapturablesChanged => {
+                    // only push updates to clients that are interested in the list
+                    if self.capturables_requested {
+                        self.send_capturable_list(false);
+                    }
+                }
+                #[cfg(target_os = "linux")]
+                ClientEvent::ClipboardChanged => self.send_clipboard(),
+                #[cfg(target_os = "linux")]
+                ClientEvent::CursorMoved(position) => {
+                    self.send_message(MessageOutbound::CursorPosition(position))
+                }
+                #[cfg(target_os = "linux")]
+                ClientEvent::CursorShapeChanged(shape) => self.send_cursor_shape(shape),
+                #[cfg(target_os = "linux")]
+                ClientEvent::RestartAudio(generation) => {
+                    self.restart_audio(generation, &event_sender)
+                }
+                ClientEvent::SendPing => self.send_ping(),
+                // closing a replaced connection does not end the session
+                ClientEvent::Closed(id) if id != self.connection_id => (),
+                ClientEvent::Closed(_) => {
+                    if !self.wait_for_resume(&event_sender) {
+                        break;
+                    }
+                }
+                ClientEvent::End => {
+                    info!("Client logged out or its login expired, ending session.");
+                    break;
+                }
+                ClientEvent::Resume => {
+                    // only the most recent connection is of interest
+                    let connection = self
+                        .connections
+                        .as_ref()
+                        .and_then(|connections| connections.try_iter().last());
+                    if let Some(connection) = connection {
+                        self.switch_connection(connection, &event_sender);
+                    }
                 }
             }
         }
+        if let Some(sessions) = &self.sessions {
+            sessions.unregister(&self.session_token);
+        }
+        stop_threads.store(true, Ordering::Relaxed);
+        #[cfg(target_os = "linux")]
+        for stop in [
+            self.stop_clipboard_watcher.take(),
+            self.stop_cursor_watcher.take(),
+        ]
+        .into_iter()
+        .flatten()
+        {
+            stop.store(true, Ordering::Relaxed);
+        }
+
+        #[cfg(target_os = "linux")]
+        {
+            self.audio = None;
+        }
+
+        // release input devices right away, stopping the video thread may take a moment
+        self.input_device = None;
 
         drop(self.video_sender);
         if let Err(err) = self.video_thread.join() {
@@ -161,6 +533,481 @@ impl<S, R, FnUInput> WeylusClientHandler<S, R, FnUInput> {
         send_message(&mut self.sender, message)
     }
 
+    /// Keep the session around for a while after the connection has been lost and wait for the
+    /// client to reconnect. Returns true if the session has been resumed.
+    fn wait_for_resume(&mut self, events: &mpsc::Sender<ClientEvent>) -> bool
+    where
+        R: WeylusReceiver + Send + 'static,
+        S: WeylusSender + Clone + Send + 'static,
+    {
+        let (sessions, connections) = match (&self.sessions, &self.connections) {
+            (Some(sessions), Some(connections)) => (sessions, connections),
+            _ => return false,
+        };
+        debug!(
+            "Connection lost, waiting {:?} for the client to resume the session.",
+            SESSION_GRACE_PERIOD
+        );
+        // nobody is watching, the client resumes the video once it is back
+        self.video_sender.send(VideoCommands::Pause).unwrap();
+        // nobody is listening either, the audio is restarted once the client is back
+        #[cfg(target_os = "linux")]
+        let audio = self.audio.take().is_some();
+        let connection = connections
+            .recv_timeout(SESSION_GRACE_PERIOD)
+            .ok()
+            .or_else(|| {
+                sessions.unregister(&self.session_token);
+                // the client may have resumed the session right before it was unregistered
+                connections.try_recv().ok()
+            });
+        match connection {
+            Some(connection) => {
+                self.switch_connection(connection, events);
+                #[cfg(target_os = "linux")]
+                if audio {
+                    self.stream_audio(true, events);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Continue the session with the connection of the client that resumed it, the previous
+    /// connection is dropped.
+    fn switch_connection(&mut self, (sender, receiver): (S, R), events: &mpsc::Sender<ClientEvent>)
+    where
+        R: WeylusReceiver + Send + 'static,
+        S: WeylusSender + Clone + Send + 'static,
+    {
+        info!("Client resumed session.");
+        self.sender = sender.clone();
+        self.video_sender
+            .send(VideoCommands::Reattach(sender))
+            .unwrap();
+        self.resumed = true;
+        self.pending_ping = None;
+        self.connection_id = self.connection_id.wrapping_add(1);
+        spawn_reader(receiver, events.clone(), self.connection_id);
+        // the client may have reloaded the page and lost the cursor images it had
+        #[cfg(target_os = "linux")]
+        {
+            self.cursor_hashes.clear();
+            if let Some(shape) = self.cursor_shape.clone() {
+                if self.stop_cursor_watcher.is_some() {
+                    self.send_cursor_shape(shape);
+                }
+            }
+        }
+        // like the video, the client needs a new stream after reconnecting
+        #[cfg(target_os = "linux")]
+        if self.audio.take().is_some() {
+            self.stream_audio(true, events);
+        }
+    }
+
+    fn send_ping(&mut self)
+    where
+        S: WeylusSender,
+    {
+        self.ping_id = self.ping_id.wrapping_add(1);
+        self.pending_ping = Some((self.ping_id, Instant::now()));
+        self.send_message(MessageOutbound::Ping(Ping {
+            id: self.ping_id,
+            timestamp: unix_time_millis(),
+        }));
+    }
+
+    fn process_pong(&mut self, pong: Pong) {
+        match self.pending_ping {
+            Some((id, sent)) if id == pong.id => {
+                let rtt = sent.elapsed();
+                trace!("RTT: {:.1} ms", rtt.as_secs_f64() * 1000.0);
+                Latency::set(&self.latency.rtt, rtt.as_secs_f64() * 1000.0);
+                self.pending_ping = None;
+            }
+            // answer to a ping that has been superseded by a newer one
+            _ => (),
+        }
+    }
+
+    fn process_latency_report(&mut self, report: LatencyReport) {
+        trace!(
+            "Client reported RTT: {:.1} ms, video latency: {:.1} ms",
+            report.rtt,
+            report.video_latency
+        );
+        Latency::set(&self.latency.video_latency, report.video_latency);
+    }
+
+    /// Ask the host to accept the client, returns false if it was rejected.
+    fn approve(&mut self, hello: &ClientHello) -> bool
+    where
+        S: WeylusSender,
+    {
+        let approve = match self.approve.take() {
+            Some(approve) => approve,
+            None => return true,
+        };
+        if approve(hello.client_name.clone()) {
+            return true;
+        }
+        self.send_message(MessageOutbound::Error(WeylusError::new(
+            ErrorCode::ConnectionRejected,
+            "The host rejected the connection.",
+        )));
+        false
+    }
+
+    /// Handle a message of a client the host accepted already.
+    fn process_message(&mut self, message: MessageInbound, event_sender: &mpsc::Sender<ClientEvent>)
+    where
+        S: WeylusSender + Clone + Send + Sync + 'static,
+        FnUInput: Fn(),
+    {
+        match message {
+            MessageInbound::PointerEvent(_)
+            | MessageInbound::PointerEvents(_)
+            | MessageInbound::WheelEvent(_)
+            | MessageInbound::KeyboardEvent(_)
+            | MessageInbound::TextInput(_)
+            | MessageInbound::ChooseCustomInputAreas
+            | MessageInbound::ClipboardSet(_)
+            | MessageInbound::SyncClipboard(_)
+                if self.config.view_only =>
+            {
+                trace!("Dropping message from view-only client.")
+            }
+            MessageInbound::Hello(hello) => self.process_hello(hello),
+            MessageInbound::PointerEvent(event) => self.process_pointer_event(&event),
+            MessageInbound::PointerEvents(events) => self.process_pointer_events(&events),
+            MessageInbound::WheelEvent(event) => self.process_wheel_event(&event),
+            MessageInbound::KeyboardEvent(event) => self.process_keyboard_event(&event),
+            MessageInbound::TextInput(text) => self.process_text_input(&text),
+            MessageInbound::GetCapturableList => self.send_capturable_list(true),
+            MessageInbound::Config(config) => self.update_config(config, event_sender),
+            MessageInbound::PauseVideo => self.video_sender.send(VideoCommands::Pause).unwrap(),
+            MessageInbound::ResumeVideo => self.video_sender.send(VideoCommands::Resume).unwrap(),
+            MessageInbound::RestartVideo => self.video_sender.send(VideoCommands::Restart).unwrap(),
+            MessageInbound::ChooseCustomInputAreas => {
+                let (sender, receiver) = std::sync::mpsc::channel();
+                crate::gui::get_input_area(self.config.no_gui, sender);
+                let mut sender = self.sender.clone();
+                spawn(move || {
+                    while let Ok(areas) = receiver.recv() {
+                        send_message(&mut sender, MessageOutbound::CustomInputAreas(areas));
+                    }
+                });
+            }
+            MessageInbound::Ping(ping) => self.send_message(MessageOutbound::Pong(Pong {
+                id: ping.id,
+                timestamp: ping.timestamp,
+                peer_timestamp: unix_time_millis(),
+            })),
+            MessageInbound::Pong(pong) => self.process_pong(pong),
+            MessageInbound::LatencyReport(report) => self.process_latency_report(report),
+            MessageInbound::ClipboardSet(content) => self.set_clipboard(content),
+            MessageInbound::SyncClipboard(enable) => self.sync_clipboard(enable, event_sender),
+            MessageInbound::WebRtcSignal(signal) => {
+                if !self.sender.handle_webrtc_signal(signal) {
+                    self.send_message(MessageOutbound::Error(WeylusError::new(
+                        ErrorCode::WebRtcNotSupported,
+                        "WebRTC is not supported by this connection!",
+                    )));
+                }
+            }
+        }
+    }
+
+    fn process_hello(&mut self, hello: ClientHello)
+    where
+        S: WeylusSender,
+    {
+        #[cfg(target_os = "linux")]
+        let wayland_support = self.config.wayland_support;
+        #[cfg(not(target_os = "linux"))]
+        let wayland_support = false;
+        let view_only = self.config.view_only;
+        let features = [
+            ("msgpack", true),
+            ("capture_cursor", cfg!(not(target_os = "windows"))),
+            ("wayland", wayland_support),
+            ("custom_input_areas", wayland_support && !view_only),
+            ("webrtc", cfg!(feature = "webrtc")),
+            ("clipboard", cfg!(target_os = "linux") && !view_only),
+            ("stream_cursor", cfg!(target_os = "linux")),
+            ("audio", cfg!(target_os = "linux")),
+        ]
+        .iter()
+        .filter(|(_, enabled)| *enabled)
+        .map(|(name, _)| name.to_string())
+        .collect();
+        self.send_message(MessageOutbound::ServerInfo(ServerInfo {
+            protocol_version: PROTOCOL_VERSION,
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            platform: std::env::consts::OS.to_string(),
+            input_backends: available_input_device_types(),
+            encoders: self.config.encoder_options.backends(),
+            features,
+            view_only,
+            session: self.sessions.as_ref().map(|_| SessionInfo {
+                token: self.session_token.clone(),
+                resumed: self.resumed,
+                capturable_id: self.capturable_id.clone(),
+            }),
+        }));
+        if self.resumed {
+            // the client already knows the capturable in use, there is no need to refresh the
+            // list which might ask the user to pick a screen cast again
+            self.resumed = false;
+            let capturables = self.capturable_infos();
+            self.send_message(MessageOutbound::CapturableList(capturables));
+        }
+        if hello.protocol_version != PROTOCOL_VERSION {
+            warn!(
+                "Client uses protocol version {}, but this server speaks version {}!",
+                hello.protocol_version, PROTOCOL_VERSION
+            );
+            self.send_message(MessageOutbound::ConfigError(WeylusError::new(
+                ErrorCode::ProtocolVersionMismatch,
+                format!(
+                    "Protocol version mismatch: client uses version {}, server uses version {}. \
+                    Please reload the page or update your client.",
+                    hello.protocol_version, PROTOCOL_VERSION
+                ),
+            )));
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn clipboard(&mut self) -> Option<&mut Clipboard>
+    where
+        S: WeylusSender,
+    {
+        if self.clipboard.is_none() {
+            match Clipboard::new() {
+                Ok(clipboard) => self.clipboard = Some(clipboard),
+                Err(err) => {
+                    warn!("Failed to access clipboard: {err}");
+                    self.send_message(MessageOutbound::Error(WeylusError::new(
+                        ErrorCode::ClipboardFailed,
+                        format!("Failed to access the clipboard: {err}"),
+                    )));
+                }
+            }
+        }
+        self.clipboard.as_mut()
+    }
+
+    #[cfg_attr(not(target_os = "linux"), allow(unused_variables))]
+    fn set_clipboard(&mut self, content: ClipboardContent)
+    where
+        S: WeylusSender,
+    {
+        #[cfg(target_os = "linux")]
+        {
+            let clipboard = match self.clipboard() {
+                Some(clipboard) => clipboard,
+                None => return,
+            };
+            // remember what the clipboard holds now so that the watcher does not send it back,
+            // images are reencoded and thus differ from what has been received
+            match clipboard.set(&content).and_then(|()| clipboard.get()) {
+                Ok(content) => self.last_clipboard = content,
+                Err(err) => {
+                    warn!("Failed to set clipboard: {err}");
+                    self.send_message(MessageOutbound::Error(WeylusError::new(
+                        ErrorCode::ClipboardFailed,
+                        format!("Failed to set the clipboard: {err}"),
+                    )));
+                }
+            }
+        }
+        #[cfg(not(target_os = "linux"))]
+        self.send_message(MessageOutbound::Error(WeylusError::new(
+            ErrorCode::ClipboardFailed,
+            "The clipboard is not supported on this platform!",
+        )));
+    }
+
+    /// Start or stop pushing changes of the clipboard to the client.
+    #[cfg_attr(not(target_os = "linux"), allow(unused_variables))]
+    fn sync_clipboard(&mut self, enable: bool, events: &mpsc::Sender<ClientEvent>)
+    where
+        S: WeylusSender,
+    {
+        #[cfg(target_os = "linux")]
+        {
+            if let Some(stop) = self.stop_clipboard_watcher.take() {
+                stop.store(true, Ordering::Relaxed);
+            }
+            if !enable {
+                return;
+            }
+            let watcher = match ClipboardWatcher::new() {
+                Ok(watcher) => watcher,
+                Err(err) => {
+                    warn!("Failed to watch clipboard: {err}");
+                    self.send_message(MessageOutbound::Error(WeylusError::from_cerror(
+                        &err,
+                        ErrorCode::ClipboardFailed,
+                    )));
+                    return;
+                }
+            };
+            let stop = Arc::new(AtomicBool::new(false));
+            self.stop_clipboard_watcher = Some(stop.clone());
+            let events = events.clone();
+            spawn(move || watch_clipboard(watcher, events, stop));
+            // send what has been copied before syncing was enabled
+            self.last_clipboard = None;
+            self.send_clipboard();
+        }
+        #[cfg(not(target_os = "linux"))]
+        if enable {
+            self.send_message(MessageOutbound::Error(WeylusError::new(
+                ErrorCode::ClipboardFailed,
+                "The clipboard is not supported on this platform!",
+            )));
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn send_clipboard(&mut self)
+    where
+        S: WeylusSender,
+    {
+        let content = match self.clipboard().map(Clipboard::get) {
+            Some(Ok(Some(content))) => content,
+            Some(Ok(None)) | None => return,
+            Some(Err(err)) => {
+                warn!("Failed to read clipboard: {err}");
+                return;
+            }
+        };
+        if self.last_clipboard.as_ref() == Some(&content) {
+            return;
+        }
+        self.last_clipboard = Some(content.clone());
+        self.send_message(MessageOutbound::ClipboardChanged(content));
+    }
+
+    /// Start or stop sending the cursor of `capturable` to the client, returns true if the cursor
+    /// is streamed and thus should not be drawn into the video.
+    #[cfg(target_os = "linux")]
+    fn stream_cursor(
+        &mut self,
+        enable: bool,
+        capturable: &dyn Capturable,
+        events: &mpsc::Sender<ClientEvent>,
+    ) -> bool
+    where
+        S: WeylusSender,
+    {
+        if let Some(stop) = self.stop_cursor_watcher.take() {
+            stop.store(true, Ordering::Relaxed);
+        }
+        if !enable {
+            return false;
+        }
+        // XFixes only knows about the cursor of the X server, screen casts have to draw it
+        if self.screen_casts.iter().any(|c| c.id() == capturable.id()) {
+            debug!("Can not stream the cursor of a screen cast, drawing it into the video.");
+            return false;
+        }
+        let watcher = match CursorWatcher::new() {
+            Ok(watcher) => watcher,
+            Err(err) => {
+                warn!("Failed to watch cursor: {err}");
+                self.send_message(MessageOutbound::Error(WeylusError::from_cerror(
+                    &err,
+                    ErrorCode::CursorStreamFailed,
+                )));
+                return false;
+            }
+        };
+        let stop = Arc::new(AtomicBool::new(false));
+        self.stop_cursor_watcher = Some(stop.clone());
+        let events = events.clone();
+        let capturable = capturable.box_clone();
+        spawn(move || watch_cursor(watcher, capturable, events, stop));
+        true
+    }
+
+    #[cfg(target_os = "linux")]
+    fn send_cursor_shape(&mut self, mut shape: CursorShape)
+    where
+        S: WeylusSender,
+    {
+        self.cursor_shape = Some(shape.clone());
+        if !self.cursor_hashes.insert(shape.hash.clone()) {
+            shape.png = None;
+        }
+        self.send_message(MessageOutbound::CursorShape(shape));
+    }
+
+    /// Start or stop streaming the host's audio to the client.
+    #[cfg(target_os = "linux")]
+    fn stream_audio(&mut self, enable: bool, events: &mpsc::Sender<ClientEvent>)
+    where
+        S: WeylusSender + Clone + Send + 'static,
+    {
+        if enable == self.audio.is_some() {
+            return;
+        }
+        self.audio = None;
+        self.audio_generation = self.audio_generation.wrapping_add(1);
+        if !enable {
+            return;
+        }
+        let generation = self.audio_generation;
+        let events = events.clone();
+        let mut sender = self.sender.clone();
+        let mut started = false;
+        let mut dropped = false;
+        let audio = AudioStream::new(move |data| {
+            // audio is queued along with the video, so announcing it has to be queued there too
+            if !started {
+                send_video_message(&mut sender, MessageOutbound::NewAudio);
+                started = true;
+            }
+            // like video, audio is dropped while the connection can not keep up, the MP4 stream
+            // is broken after that and has to be restarted once the congestion is gone
+            if sender.is_congested() {
+                dropped = true;
+            } else if dropped {
+                // the handler may be gone already, in which case the stream is about to be stopped
+                let _ = events.send(ClientEvent::RestartAudio(generation));
+            } else if let Err(err) = sender.send_audio(data) {
+                warn!("Failed to send audio to client: {err}");
+            }
+        });
+        match audio {
+            Ok(audio) => self.audio = Some(audio),
+            Err(err) => {
+                warn!("Failed to stream audio: {err}");
+                self.send_message(MessageOutbound::Error(WeylusError::new(
+                    ErrorCode::AudioFailed,
+                    format!("Failed to stream audio: {err}"),
+                )));
+            }
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn restart_audio(&mut self, generation: u32, events: &mpsc::Sender<ClientEvent>)
+    where
+        S: WeylusSender + Clone + Send + 'static,
+    {
+        // restarts requested by a previous stream are outdated
+        if generation == self.audio_generation && self.audio.is_some() {
+            debug!("Restarting audio stream after dropping data.");
+            self.stream_audio(false, events);
+            self.stream_audio(true, events);
+        }
+    }
+
     fn process_wheel_event(&mut self, event: &WheelEvent) {
         match &mut self.input_device {
             Some(i) => i.send_wheel_event(event),
@@ -179,6 +1026,13 @@ impl<S, R, FnUInput> WeylusClientHandler<S, R, FnUInput> {
         }
     }
 
+    fn process_pointer_events(&mut self, events: &[PointerEvent]) {
+        match &mut self.input_device {
+            Some(i) => i.send_pointer_events(events),
+            None => warn!("Input device is not initalized, can not process PointerEvents!"),
+        }
+    }
+
     fn process_keyboard_event(&mut self, event: &KeyboardEvent) {
         if self.input_device.is_some() {
             self.input_device
@@ -190,26 +1044,86 @@ impl<S, R, FnUInput> WeylusClientHandler<S, R, FnUInput> {
         }
     }
 
-    fn send_capturable_list(&mut self)
+    fn process_text_input(&mut self, text: &str) {
+        match &mut self.input_device {
+            Some(i) => i.send_text(text),
+            None => warn!("Input device is not initalized, can not process TextInput!"),
+        }
+    }
+
+    /// Refresh the list of capturables. Screen casts are only requested again if
+    /// `request_screen_casts` is set, as this may bother the user with a dialog.
+    #[cfg_attr(not(target_os = "linux"), allow(unused_variables))]
+    fn refresh_capturables(&mut self, request_screen_casts: bool) -> Vec<Box<dyn Error>> {
+        #[cfg_attr(not(target_os = "linux"), allow(unused_mut))]
+        let (capturables, mut errors) = get_capturables();
+        #[cfg(target_os = "linux")]
+        if request_screen_casts && self.config.wayland_support {
+            match get_screen_casts(self.capture_cursor) {
+                Ok(screen_casts) => self.screen_casts = screen_casts,
+                Err(err) => {
+                    warn!(
+                        "Failed to get list of capturables via dbus/pipewire: {}",
+                        err
+                    );
+                    self.screen_casts = vec![];
+                    errors.insert(0, err);
+                }
+            }
+        }
+        #[cfg(target_os = "linux")]
+        {
+            self.capturables = self
+                .screen_casts
+                .iter()
+                .cloned()
+                .chain(capturables)
+                .collect();
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            self.capturables = capturables;
+        }
+        errors
+    }
+
+    /// Refresh the list of capturables and send it to the client. Errors are only reported if the
+    /// client asked for the list, to avoid bothering the user with every pushed update.
+    fn send_capturable_list(&mut self, requested_by_client: bool)
     where
         S: WeylusSender,
     {
-        let mut windows = Vec::<String>::new();
-        self.capturables = get_capturables(
-            #[cfg(target_os = "linux")]
-            self.config.wayland_support,
-            #[cfg(target_os = "linux")]
-            self.capture_cursor,
-        );
-        self.capturables.iter().for_each(|c| {
-            windows.push(c.name());
-        });
-        self.send_message(MessageOutbound::CapturableList(windows));
+        let errors = self.refresh_capturables(requested_by_client);
+        self.capturables_requested |= requested_by_client;
+        if requested_by_client {
+            for err in errors {
+                self.send_message(MessageOutbound::Error(WeylusError::from_capture_error(
+                    err.as_ref(),
+                )));
+            }
+        }
+        let capturables = self.capturable_infos();
+        self.send_message(MessageOutbound::CapturableList(capturables));
     }
 
-    fn update_config(&mut self, config: ClientConfiguration)
+    fn capturable_infos(&self) -> Vec<CapturableInfo> {
+        self.capturables
+            .iter()
+            .map(|c| CapturableInfo {
+                id: c.id(),
+                name: c.name(),
+            })
+            .collect()
+    }
+
+    fn find_capturable(&self, id: &str) -> Option<Box<dyn Capturable>> {
+        self.capturables.iter().find(|c| c.id() == id).cloned()
+    }
+
+    #[cfg_attr(not(target_os = "linux"), allow(unused_variables))]
+    fn update_config(&mut self, config: ClientConfiguration, events: &mpsc::Sender<ClientEvent>)
     where
-        S: WeylusSender,
+        S: WeylusSender + Clone + Send + 'static,
         FnUInput: Fn(),
     {
         let client_name_changed = if self.client_name != config.client_name {
@@ -218,74 +1132,90 @@ impl<S, R, FnUInput> WeylusClientHandler<S, R, FnUInput> {
         } else {
             false
         };
-        if config.capturable_id < self.capturables.len() {
-            let capturable = self.capturables[config.capturable_id].clone();
-
+        let mut capturable = self.find_capturable(&config.capturable_id);
+        if capturable.is_none() {
+            // the capturable may be new and not yet in the list known to this handler
+            self.refresh_capturables(false);
+            capturable = self.find_capturable(&config.capturable_id);
+        }
+        if let Some(capturable) = capturable {
             #[cfg(target_os = "linux")]
             {
                 self.capture_cursor = config.capture_cursor;
             }
-
             #[cfg(target_os = "linux")]
-            if config.uinput_support {
-                if self.input_device.as_ref().map_or(true, |d| {
-                    client_name_changed || d.device_type() != InputDeviceType::UInputDevice
-                }) {
-                    let device = crate::input::uinput_device::UInputDevice::new(
-                        capturable.clone(),
-                        &self.client_name,
-                    );
-                    match device {
-                        Ok(d) => self.input_device = Some(Box::new(d)),
-                        Err(e) => {
-                            error!("Failed to create uinput device: {}", e);
-                            if let CErrorCode::UInputNotAccessible = e.to_enum() {
-                                (self.on_uinput_inaccessible)();
+            let capture_cursor =
+                !self.stream_cursor(config.stream_cursor, capturable.as_ref(), events)
+                    && config.capture_cursor;
+            #[cfg(not(target_os = "linux"))]
+            let capture_cursor = config.capture_cursor;
+            #[cfg(target_os = "linux")]
+            self.stream_audio(config.audio, events);
+
+            // view-only clients must not control the computer, so they do not get an input device
+            if !self.config.view_only {
+                #[cfg(target_os = "linux")]
+                if config.uinput_support {
+                    if self.input_device.as_ref().map_or(true, |d| {
+                        client_name_changed || d.device_type() != InputDeviceType::UInputDevice
+                    }) {
+                        let device = crate::input::uinput_device::UInputDevice::new(
+                            capturable.clone(),
+                            &self.client_name,
+                        );
+                        match device {
+                            Ok(d) => self.input_device = Some(Box::new(d)),
+                            Err(e) => {
+                                error!("Failed to create uinput device: {}", e);
+                                if let CErrorCode::UInputNotAccessible = e.to_enum() {
+                                    (self.on_uinput_inaccessible)();
+                                }
+                                self.send_message(MessageOutbound::ConfigError(
+                                    WeylusError::from_cerror(&e, ErrorCode::InputDeviceFailed),
+                                ));
+                                return;
                             }
-                            self.send_message(MessageOutbound::ConfigError(
-                                "Failed to create uinput device!".to_string(),
-                            ));
-                            return;
                         }
+                    } else if let Some(d) = self.input_device.as_mut() {
+                        d.set_capturable(capturable.clone());
                     }
+                } else if self.input_device.as_ref().map_or(true, |d| {
+                    d.device_type() != InputDeviceType::AutoPilotDevice
+                }) {
+                    self.input_device = Some(Box::new(
+                        crate::input::autopilot_device::AutoPilotDevice::new(capturable.clone()),
+                    ));
                 } else if let Some(d) = self.input_device.as_mut() {
                     d.set_capturable(capturable.clone());
                 }
-            } else if self.input_device.as_ref().map_or(true, |d| {
-                d.device_type() != InputDeviceType::AutoPilotDevice
-            }) {
-                self.input_device = Some(Box::new(
-                    crate::input::autopilot_device::AutoPilotDevice::new(capturable.clone()),
-                ));
-            } else if let Some(d) = self.input_device.as_mut() {
-                d.set_capturable(capturable.clone());
-            }
-
-            #[cfg(target_os = "macos")]
-            if self.input_device.is_none() {
-                self.input_device = Some(Box::new(
-                    crate::input::autopilot_device::AutoPilotDevice::new(capturable.clone()),
-                ));
-            } else {
-                self.input_device
-                    .as_mut()
-                    .map(|d| d.set_capturable(capturable.clone()));
-            }
-            #[cfg(target_os = "windows")]
-            if self.input_device.is_none() {
-                self.input_device = Some(Box::new(
-                    crate::input::autopilot_device_win::WindowsInput::new(capturable.clone()),
-                ));
-            } else {
-                self.input_device
-                    .as_mut()
-                    .map(|d| d.set_capturable(capturable.clone()));
+
+                #[cfg(target_os = "macos")]
+                if self.input_device.is_none() {
+                    self.input_device = Some(Box::new(
+                        crate::input::autopilot_device::AutoPilotDevice::new(capturable.clone()),
+                    ));
+                } else {
+                    self.input_device
+                        .as_mut()
+                        .map(|d| d.set_capturable(capturable.clone()));
+                }
+                #[cfg(target_os = "windows")]
+                if self.input_device.is_none() {
+                    self.input_device = Some(Box::new(
+                        crate::input::autopilot_device_win::WindowsInput::new(capturable.clone()),
+                    ));
+                } else {
+                    self.input_device
+                        .as_mut()
+                        .map(|d| d.set_capturable(capturable.clone()));
+                }
             }
 
+            self.capturable_id = Some(config.capturable_id);
             self.video_sender
                 .send(VideoCommands::Start(VideoConfig {
                     capturable,
-                    capture_cursor: config.capture_cursor,
+                    capture_cursor,
                     max_width: config.max_width,
                     max_height: config.max_height,
                     frame_rate: config.frame_rate,
@@ -293,28 +1223,138 @@ impl<S, R, FnUInput> WeylusClientHandler<S, R, FnUInput> {
                 .unwrap();
         } else {
             error!("Got invalid id for capturable: {}", config.capturable_id);
-            self.send_message(MessageOutbound::ConfigError(
-                "Invalid id for capturable!".to_string(),
-            ));
+            self.send_message(MessageOutbound::ConfigError(WeylusError::new(
+                ErrorCode::CapturableGone,
+                "The selected capturable is not available anymore!",
+            )));
+        }
+    }
+}
+
+/// Read messages in another thread so that the WeylusClientHandler can also handle events from
+/// other sources, like the capturable watcher.
+fn spawn_reader<R>(receiver: R, events: mpsc::Sender<ClientEvent>, connection_id: u32)
+where
+    R: WeylusReceiver + Send + 'static,
+{
+    spawn(move || {
+        for message in receiver {
+            let message = message.map_err(|err| err.to_string());
+            if events
+                .send(ClientEvent::Message(connection_id, message))
+                .is_err()
+            {
+                return;
+            }
+        }
+        // the handler may be gone already, in which case there is nothing left to do
+        let _ = events.send(ClientEvent::Closed(connection_id));
+    });
+}
+
+fn watch_capturables(events: mpsc::Sender<ClientEvent>, stop: Arc<AtomicBool>) {
+    let mut watcher = match capturable_watcher() {
+        Some(watcher) => watcher,
+        None => return,
+    };
+    while !stop.load(Ordering::Relaxed) {
+        if watcher.wait_for_change(Duration::from_millis(500)) {
+            // opening or renaming a window usually causes a burst of changes, only send a single
+            // update for all of them
+            while watcher.wait_for_change(Duration::from_millis(100)) {}
+            if events.send(ClientEvent::CapturablesChanged).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn watch_clipboard(
+    mut watcher: ClipboardWatcher,
+    events: mpsc::Sender<ClientEvent>,
+    stop: Arc<AtomicBool>,
+) {
+    while !stop.load(Ordering::Relaxed) {
+        if watcher.wait_for_change(Duration::from_millis(500))
+            && events.send(ClientEvent::ClipboardChanged).is_err()
+        {
+            return;
+        }
+    }
+}
+
+/// Poll the position of the cursor and report it relative to `capturable` along with changes of
+/// its shape.
+#[cfg(target_os = "linux")]
+fn watch_cursor(
+    mut watcher: CursorWatcher,
+    capturable: Box<dyn Capturable>,
+    events: mpsc::Sender<ClientEvent>,
+    stop: Arc<AtomicBool>,
+) {
+    let mut geometry = None;
+    let mut last_geometry_update: Option<Instant> = None;
+    let mut last_position = None;
+    while !stop.load(Ordering::Relaxed) {
+        if watcher.shape_changed() {
+            match watcher.shape() {
+                Ok(shape) => {
+                    if events.send(ClientEvent::CursorShapeChanged(shape)).is_err() {
+                        return;
+                    }
+                }
+                Err(err) => warn!("Failed to get cursor image: {err}"),
+            }
         }
+        // windows can be moved without the cursor moving, so the geometry is refreshed regularly
+        if last_geometry_update.is_none_or(|t| t.elapsed() > CURSOR_GEOMETRY_INTERVAL) {
+            geometry = match capturable.geometry() {
+                Ok(Geometry::Relative(x, y, width, height)) => Some((x, y, width, height)),
+                Err(err) => {
+                    warn!("Failed to get geometry of capturable: {err}");
+                    None
+                }
+            };
+            last_geometry_update = Some(Instant::now());
+        }
+        if let (Some((x, y)), Some((x0, y0, width, height))) = (watcher.position(), geometry) {
+            let position = CursorPosition {
+                x: (x - x0) / width,
+                y: (y - y0) / height,
+            };
+            if last_position != Some(position) {
+                last_position = Some(position);
+                if events.send(ClientEvent::CursorMoved(position)).is_err() {
+                    return;
+                }
+            }
+        }
+        std::thread::sleep(CURSOR_POLL_INTERVAL);
     }
 }
 
 fn handle_video<S: WeylusSender + Clone + 'static>(
-    receiver: mpsc::Receiver<VideoCommands>,
+    receiver: mpsc::Receiver<VideoCommands<S>>,
     mut sender: S,
     encoder_options: EncoderOptions,
+    latency: Arc<Latency>,
 ) {
     const EFFECTIVE_INIFINITY: Duration = Duration::from_secs(3600 * 24 * 365 * 200);
+    const LATENCY_LOG_INTERVAL: Duration = Duration::from_secs(10);
 
     let mut recorder: Option<Box<dyn Recorder>> = None;
     let mut video_encoder: Option<Box<VideoEncoder>> = None;
+    let mut encoder_failed = false;
 
     let mut max_width = 1920;
     let mut max_height = 1080;
     let mut frame_duration = EFFECTIVE_INIFINITY;
     let mut last_frame = Instant::now();
     let mut paused = false;
+    let mut frame_sequence: u64 = 0;
+    let mut skipped_frames: u64 = 0;
+    let mut last_latency_log = Instant::now();
 
     loop {
         let now = Instant::now();
@@ -353,7 +1393,7 @@ fn handle_video<S: WeylusSender + Clone + 'static>(
                         warn!("Failed to init screen cast: {}!", err);
                         send_message(
                             &mut sender,
-                            MessageOutbound::Error("Failed to init screen cast!".into()),
+                            MessageOutbound::Error(WeylusError::from_capture_error(err.as_ref())),
                         )
                     }
                 }
@@ -379,11 +1419,22 @@ fn handle_video<S: WeylusSender + Clone + 'static>(
             Ok(VideoCommands::Restart) => {
                 video_encoder = None;
             }
+            Ok(VideoCommands::Reattach(new_sender)) => {
+                sender = new_sender;
+                // the encoder still sends to the old connection and the client needs a new stream
+                video_encoder = None;
+            }
             Err(RecvTimeoutError::Timeout) => {
                 if recorder.is_none() {
                     warn!("Screen capture not initalized, can not send video frame!");
                     continue;
                 }
+                if sender.is_congested() {
+                    skipped_frames += 1;
+                    trace!("Connection congested, skipping frame.");
+                    continue;
+                }
+                let capture_timestamp = unix_time_millis();
                 let pixel_data = recorder.as_mut().unwrap().capture();
                 if let Err(err) = pixel_data {
                     warn!("Error capturing screen: {}", err);
@@ -402,37 +1453,78 @@ fn handle_video<S: WeylusSender + Clone + 'static>(
                     width_out = (width_out as f64 * scale) as usize;
                     height_out = (height_out as f64 * scale) as usize;
                 }
-                // video encoder is not setup or setup for encoding the wrong size: restart it
-                if video_encoder.is_none()
-                    || !video_encoder
-                        .as_ref()
-                        .unwrap()
-                        .check_size(width_in, height_in, width_out, height_out)
-                {
-                    send_message(&mut sender, MessageOutbound::NewVideo);
-                    let mut sender = sender.clone();
+                let format = sender.video_format();
+                // video encoder is not setup or setup for encoding the wrong size or format:
+                // restart it
+                if video_encoder.as_ref().is_none_or(|encoder| {
+                    !encoder.check_size(width_in, height_in, width_out, height_out)
+                        || encoder.format() != format
+                }) {
+                    send_video_message(&mut sender, MessageOutbound::NewVideo);
+                    let mut video_sender = sender.clone();
                     let res = VideoEncoder::new(
                         width_in,
                         height_in,
                         width_out,
                         height_out,
                         move |data| {
-                            if let Err(err) = sender.send_video(data) {
+                            if let Err(err) = video_sender.send_video(data) {
                                 warn!("Failed to send video frame: {err}!");
                             }
                         },
                         encoder_options,
+                        format,
                     );
                     match res {
-                        Ok(r) => video_encoder = Some(r),
+                        Ok(r) => {
+                            video_encoder = Some(r);
+                            encoder_failed = false;
+                        }
                         Err(e) => {
                             warn!("{}", e);
+                            // this is retried on every frame, only tell the client once
+                            if !encoder_failed {
+                                send_message(
+                                    &mut sender,
+                                    MessageOutbound::Error(WeylusError::from_cerror(
+                                        &e,
+                                        ErrorCode::EncoderInitFailed,
+                                    )),
+                                );
+                                encoder_failed = true;
+                            }
                             continue;
                         }
                     };
                 }
                 let video_encoder = video_encoder.as_mut().unwrap();
+                frame_sequence += 1;
+                send_video_message(
+                    &mut sender,
+                    MessageOutbound::FrameInfo(FrameInfo {
+                        sequence: frame_sequence,
+                        capture_timestamp,
+                        skipped_frames,
+                    }),
+                );
                 video_encoder.encode(pixel_data);
+
+                if last_latency_log.elapsed() > LATENCY_LOG_INTERVAL {
+                    last_latency_log = Instant::now();
+                    if skipped_frames > 0 {
+                        debug!("Skipped {skipped_frames} frame(s) due to congestion so far.");
+                    }
+                    if let Some(rtt) = Latency::get(&latency.rtt) {
+                        debug!(
+                            "RTT: {:.1} ms, video latency: {}",
+                            rtt.as_secs_f64() * 1000.0,
+                            Latency::get(&latency.video_latency).map_or(
+                                "unknown".to_string(),
+                                |l| format!("{:.1} ms", l.as_secs_f64() * 1000.0)
+                            )
+                        );
+                    }
+                }
             }
             // stop thread once the channel is closed
             Err(RecvTimeoutError::Disconnected) => return,
@@ -459,14 +1551,45 @@ impl WeylusReceiver for WsWeylusReceiver {
 pub enum WsMessage {
     Frame(Frame<'static>),
     Video(Vec<u8>),
+    // sent as AudioData followed by the binary data
+    Audio(Vec<u8>),
     MessageOutbound(MessageOutbound),
 }
 
 unsafe impl Send for WsMessage {}
 
+/// Chunks of video that may be queued up before the connection is considered congested, each frame
+/// is preceded by its FrameInfo in the queue.
+const VIDEO_QUEUE_CONGESTED: usize = 4;
+const VIDEO_QUEUE_SIZE: usize = 32;
+
 #[derive(Clone)]
 pub struct WsWeylusSender {
     sender: tokio::sync::mpsc::Sender<WsMessage>,
+    // video is sent via a separate queue so that messages can skip ahead of it
+    video: tokio::sync::mpsc::Sender<WsMessage>,
+    congested: Arc<AtomicBool>,
+    #[cfg(feature = "webrtc")]
+    inbound: tokio::sync::mpsc::WeakSender<MessageInbound>,
+}
+
+impl WsWeylusSender {
+    /// Send a message from within the async runtime, `send_message` must not be used there as it
+    /// blocks.
+    #[cfg(feature = "webrtc")]
+    pub async fn send_message_async(
+        &self,
+        message: MessageOutbound,
+    ) -> Result<(), tokio::sync::mpsc::error::SendError<WsMessage>> {
+        self.sender.send(WsMessage::MessageOutbound(message)).await
+    }
+
+    /// Handle to inject inbound messages received via side channels like a WebRTC data channel.
+    /// The handle is weak so that the connection still closes once the websocket is gone.
+    #[cfg(feature = "webrtc")]
+    pub fn inbound(&self) -> tokio::sync::mpsc::WeakSender<MessageInbound> {
+        self.inbound.clone()
+    }
 }
 
 impl WeylusSender for WsWeylusSender {
@@ -478,12 +1601,38 @@ impl WeylusSender for WsWeylusSender {
     }
 
     fn send_video(&mut self, bytes: &[u8]) -> Result<(), Self::Error> {
-        self.sender.blocking_send(WsMessage::Video(bytes.to_vec()))
+        self.video.blocking_send(WsMessage::Video(bytes.to_vec()))
+    }
+
+    fn send_audio(&mut self, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.video.blocking_send(WsMessage::Audio(bytes.to_vec()))
+    }
+
+    fn send_video_message(&mut self, message: MessageOutbound) -> Result<(), Self::Error> {
+        self.video
+            .blocking_send(WsMessage::MessageOutbound(message))
+    }
+
+    fn is_congested(&self) -> bool {
+        // once congested wait for the queue to drain completely to avoid toggling on every frame
+        let queued = self.video.max_capacity() - self.video.capacity();
+        if queued >= VIDEO_QUEUE_CONGESTED {
+            self.congested.store(true, Ordering::Relaxed);
+        } else if queued == 0 {
+            self.congested.store(false, Ordering::Relaxed);
+        }
+        self.congested.load(Ordering::Relaxed)
     }
 }
 
+/// Websocket to WeylusSender/WeylusReceiver adapter.
+///
+/// If `idle_timeout` is set, the client is pinged regularly and the connection is closed if
+/// nothing, not even a pong, has been received within the timeout.
 pub fn weylus_websocket_channel(
     websocket: WebSocket<TokioIo<Upgraded>>,
+    wire_format: WireFormat,
+    idle_timeout: Option<Duration>,
     semaphore_shutdown: Arc<tokio::sync::Semaphore>,
 ) -> (WsWeylusSender, WsWeylusReceiver) {
     let (rx, mut tx) = websocket.split(|ws| tokio::io::split(ws));
@@ -491,10 +1640,35 @@ pub fn weylus_websocket_channel(
     let mut rx = FragmentCollectorRead::new(rx);
 
     let (sender_inbound, receiver_inbound) = channel::<MessageInbound>(32);
+    #[cfg(feature = "webrtc")]
+    let inbound = sender_inbound.downgrade();
     let (sender_outbound, mut receiver_outbound) = channel::<WsMessage>(32);
+    let (sender_video, mut receiver_video) = channel::<WsMessage>(VIDEO_QUEUE_SIZE);
+    // the connection is dead, stop writing as this may block forever
+    let notify_dead = Arc::new(tokio::sync::Notify::new());
+
+    if let Some(idle_timeout) = idle_timeout {
+        let sender_outbound = sender_outbound.downgrade();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(idle_timeout / 3);
+            loop {
+                interval.tick().await;
+                // stop pinging once the connection is gone
+                let sender_outbound = match sender_outbound.upgrade() {
+                    Some(sender_outbound) => sender_outbound,
+                    None => break,
+                };
+                let ping = Frame::new(true, OpCode::Ping, None, Vec::new().into());
+                if sender_outbound.send(WsMessage::Frame(ping)).await.is_err() {
+                    break;
+                }
+            }
+        });
+    }
 
     {
         let sender_outbound = sender_outbound.clone();
+        let notify_dead = notify_dead.clone();
         tokio::spawn(async move {
             let mut send_fn = |frame| async {
                 if let Err(err) = sender_outbound.send(WsMessage::Frame(frame)).await {
@@ -505,75 +1679,131 @@ pub fn weylus_websocket_channel(
 
             loop {
                 let fut = rx.read_frame::<_, WebSocketError>(&mut send_fn);
+                let fut = async {
+                    match idle_timeout {
+                        Some(idle_timeout) => tokio::time::timeout(idle_timeout, fut).await,
+                        None => Ok(fut.await),
+                    }
+                };
 
                 let frame = tokio::select! {
                     _ = semaphore_shutdown.acquire() => break,
                     frame = fut => match frame {
-                        Ok(frame) => frame,
-                        Err(err) => {
+                        Ok(Ok(frame)) => frame,
+                        Ok(Err(err)) => {
                             warn!("Invalid websocket frame: {err}.");
                             break;
                         },
+                        Err(_) => {
+                            warn!(
+                                "Client did not respond for {:?}, closing connection.",
+                                idle_timeout.unwrap()
+                            );
+                            notify_dead.notify_one();
+                            break;
+                        }
                     },
                 };
-                match frame.opcode {
+                let msg = match frame.opcode {
                     OpCode::Close => break,
                     OpCode::Text => match serde_json::from_slice(&frame.payload) {
-                        Ok(msg) => {
-                            if let Err(err) = sender_inbound.send(msg).await {
-                                warn!("Failed to forward inbound message to WeylusClientHandler: {err}.");
+                        Ok(msg) => msg,
+                        Err(err) => {
+                            warn!("Failed to parse message: {err}");
+                            continue;
+                        }
+                    },
+                    OpCode::Binary => match wire_format {
+                        WireFormat::MessagePack => match rmp_serde::from_slice(&frame.payload) {
+                            Ok(msg) => msg,
+                            Err(err) => {
+                                warn!("Failed to parse binary message: {err}");
+                                continue;
                             }
+                        },
+                        WireFormat::Json => {
+                            warn!("Got binary message but no binary wire format was negotiated.");
+                            continue;
                         }
-                        Err(err) => warn!("Failed to parse message: {err}"),
                     },
-                    _ => {}
+                    _ => continue,
+                };
+                if let Err(err) = sender_inbound.send(msg).await {
+                    warn!("Failed to forward inbound message to WeylusClientHandler: {err}.");
                 }
             }
         });
     }
 
     tokio::spawn(async move {
-        loop {
-            let msg = if let Some(msg) = receiver_outbound.recv().await {
-                msg
-            } else {
-                break;
-            };
+        let write_loop = async {
+            loop {
+                // prefer messages over video so that they do not have to wait for queued up frames,
+                // messages describing the video are queued along with it to keep them in order
+                let msg = tokio::select! {
+                    biased;
+                    msg = receiver_outbound.recv() => msg,
+                    msg = receiver_video.recv() => msg,
+                };
+                let msg = if let Some(msg) = msg { msg } else { break };
 
-            match msg {
-                WsMessage::Frame(frame) => {
-                    if let Err(err) = tx.write_frame(frame).await {
-                        if let WebSocketError::ConnectionClosed = err {
-                            break;
+                match msg {
+                    WsMessage::Frame(frame) => {
+                        if let Err(err) = tx.write_frame(frame).await {
+                            if let WebSocketError::ConnectionClosed = err {
+                                break;
+                            }
+                            warn!("Failed to send frame: {err}");
                         }
-                        warn!("Failed to send frame: {err}");
                     }
-                }
-                WsMessage::Video(data) => {
-                    if let Err(err) = tx.write_frame(Frame::binary(data.into())).await {
-                        if let WebSocketError::ConnectionClosed = err {
-                            break;
+                    WsMessage::Video(data) => {
+                        if let Err(err) = tx.write_frame(Frame::binary(data.into())).await {
+                            if let WebSocketError::ConnectionClosed = err {
+                                break;
+                            }
+                            warn!("Failed to send video frame: {err}");
                         }
-                        warn!("Failed to send video frame: {err}");
                     }
-                }
-                WsMessage::MessageOutbound(msg) => {
-                    let json_string = serde_json::to_string(&msg).unwrap();
-                    let data = json_string.as_bytes();
-                    if let Err(err) = tx.write_frame(Frame::text(data.into())).await {
-                        if let WebSocketError::ConnectionClosed = err {
-                            break;
+                    WsMessage::Audio(data) => {
+                        let announce = serde_json::to_vec(&MessageOutbound::AudioData).unwrap();
+                        let mut result = tx.write_frame(Frame::text(announce.into())).await;
+                        if result.is_ok() {
+                            result = tx.write_frame(Frame::binary(data.into())).await;
+                        }
+                        if let Err(err) = result {
+                            if let WebSocketError::ConnectionClosed = err {
+                                break;
+                            }
+                            warn!("Failed to send audio frame: {err}");
+                        }
+                    }
+                    WsMessage::MessageOutbound(msg) => {
+                        let json_string = serde_json::to_string(&msg).unwrap();
+                        let data = json_string.as_bytes();
+                        if let Err(err) = tx.write_frame(Frame::text(data.into())).await {
+                            if let WebSocketError::ConnectionClosed = err {
+                                break;
+                            }
+                            warn!("Failed to send outbound message: {err}");
                         }
-                        warn!("Failed to send outbound message: {err}");
                     }
                 }
             }
+        };
+        // a write may block forever if the client is gone, so also stop while writing
+        tokio::select! {
+            _ = notify_dead.notified() => (),
+            _ = write_loop => (),
         }
     });
 
     (
         WsWeylusSender {
             sender: sender_outbound,
+            video: sender_video,
+            congested: Arc::new(AtomicBool::new(false)),
+            #[cfg(feature = "webrtc")]
+            inbound,
         },
         WsWeylusReceiver {
             recv: receiver_inbound,
@@ -1,15 +1,27 @@
 use std::net::SocketAddr;
 use std::sync::Arc;
-use tracing::error;
+use std::time::Duration;
+use tracing::{error, info};
 
+use crate::auth::AuthLimiter;
 use crate::config::Config;
+use crate::ip_filter::IpFilter;
+use crate::pairing::Pairing;
+use crate::socket::{SocketServer, SocketServerConfig};
+use crate::tls::TlsIdentity;
 use crate::video::EncoderOptions;
 use crate::web::{Web2UiMessage, WebServerConfig, WebStartUpMessage};
 use crate::websocket::WeylusClientConfig;
 
+#[cfg(not(target_os = "windows"))]
+use pnet_datalink as datalink;
+
 pub struct Weylus {
     notify_shutdown: Arc<tokio::sync::Notify>,
     web_thread: Option<std::thread::JoinHandle<()>>,
+    socket_server: Option<SocketServer>,
+    tls_fingerprint: Option<String>,
+    pairing: Arc<Pairing>,
 }
 
 impl Weylus {
@@ -17,6 +29,9 @@ impl Weylus {
         Self {
             notify_shutdown: Arc::new(tokio::sync::Notify::new()),
             web_thread: None,
+            socket_server: None,
+            tls_fingerprint: None,
+            pairing: Arc::new(Pairing::default()),
         }
     }
 
@@ -47,9 +62,67 @@ impl Weylus {
             try_mediafoundation: false,
         };
 
+        let tls = if config.tls {
+            match TlsIdentity::load(
+                config.tls_cert.as_deref(),
+                config.tls_key.as_deref(),
+                &SocketAddr::new(config.bind_address, config.web_port),
+            ) {
+                Ok(tls) => {
+                    info!("TLS certificate fingerprint (SHA-256): {}", tls.fingerprint);
+                    Some(tls)
+                }
+                Err(err) => {
+                    error!("Failed to set up TLS: {err}");
+                    return false;
+                }
+            }
+        } else {
+            None
+        };
+
         let (sender_ui, mut receiver_ui) = tokio::sync::mpsc::channel(100);
         let (sender_startup, receiver_startup) = tokio::sync::oneshot::channel();
 
+        let weylus_client_config = WeylusClientConfig {
+            encoder_options,
+            #[cfg(target_os = "linux")]
+            wayland_support: config.wayland_support,
+            no_gui: config.no_gui,
+            view_only: false,
+        };
+
+        let ip_filter = IpFilter {
+            allow: config.allow.clone(),
+            deny: config.deny.clone(),
+        };
+
+        let auth_limiter = Arc::new(AuthLimiter::default());
+
+        let socket_server = crate::socket::run(
+            SocketServerConfig {
+                bind_addr: config
+                    .socket_port
+                    .map(|port| SocketAddr::new(config.bind_address, port)),
+                #[cfg(unix)]
+                path: config.socket_path.clone(),
+                access_code: config.access_code.clone(),
+                view_access_code: config.view_access_code.clone(),
+                ip_filter: ip_filter.clone(),
+                auth_limiter: auth_limiter.clone(),
+                require_approval: config.require_approval,
+            },
+            sender_ui.clone(),
+            weylus_client_config,
+        );
+        let socket_server = match socket_server {
+            Ok(s) => s,
+            Err(err) => {
+                error!("Failed to start server for native clients: {err}.");
+                return false;
+            }
+        };
+
         let web_thread = crate::web::run(
             sender_ui,
             sender_startup,
@@ -57,21 +130,31 @@ impl Weylus {
             WebServerConfig {
                 bind_addr: SocketAddr::new(config.bind_address, config.web_port),
                 access_code: config.access_code.clone(),
+                view_access_code: config.view_access_code.clone(),
                 custom_index_html: config.custom_index_html.clone(),
                 custom_access_html: config.custom_access_html.clone(),
                 custom_style_css: config.custom_style_css.clone(),
                 custom_lib_js: config.custom_lib_js.clone(),
+                idle_timeout: match config.idle_timeout {
+                    0 => None,
+                    secs => Some(Duration::from_secs(secs)),
+                },
+                upload_dir: config.upload_dir.clone(),
+                max_upload_size: config.max_upload_size.saturating_mul(1024 * 1024),
+                tls_config: tls.as_ref().map(|tls| tls.server_config.clone()),
+                pairing: self.pairing.clone(),
+                auth_limiter,
+                ip_filter,
+                require_approval: config.require_approval,
+                session_lifetime: Duration::from_secs(
+                    config.session_lifetime.saturating_mul(60 * 60),
+                ),
                 #[cfg(target_os = "linux")]
                 enable_custom_input_areas: config.wayland_support,
                 #[cfg(not(target_os = "linux"))]
                 enable_custom_input_areas: false,
             },
-            WeylusClientConfig {
-                encoder_options,
-                #[cfg(target_os = "linux")]
-                wayland_support: config.wayland_support,
-                no_gui: config.no_gui,
-            },
+            weylus_client_config,
         );
 
         match receiver_startup.blocking_recv() {
@@ -91,6 +174,8 @@ impl Weylus {
             }
         }
         self.web_thread = Some(web_thread);
+        self.socket_server = Some(socket_server);
+        self.tls_fingerprint = tls.map(|tls| tls.fingerprint);
         std::thread::spawn(move || {
             while let Some(msg) = receiver_ui.blocking_recv() {
                 on_web_message(msg);
@@ -99,12 +184,25 @@ impl Weylus {
         true
     }
 
+    /// Fingerprint of the certificate used by the web server, `None` if TLS is disabled.
+    pub fn tls_fingerprint(&self) -> Option<&str> {
+        self.tls_fingerprint.as_deref()
+    }
+
+    /// Tokens to pair devices with, see [`crate::pairing`].
+    pub fn pairing(&self) -> Arc<Pairing> {
+        self.pairing.clone()
+    }
+
     pub fn stop(&mut self) {
         self.notify_shutdown.notify_one();
         self.wait();
     }
 
     fn wait(&mut self) {
+        if let Some(mut s) = self.socket_server.take() {
+            s.stop();
+        }
         if let Some(t) = self.web_thread.take() {
             if t.join().is_err() {
                 error!("Web thread panicked.");
@@ -118,3 +216,25 @@ impl Drop for Weylus {
         self.stop();
     }
 }
+
+/// Addresses of the network interfaces browsers may use to connect to `web_sock`, which is bound
+/// to all interfaces.
+#[cfg(not(target_os = "windows"))]
+pub fn guess_ips(web_sock: &SocketAddr) -> Vec<std::net::IpAddr> {
+    let mut ips = Vec::new();
+    for iface in datalink::interfaces()
+        .iter()
+        .filter(|iface| iface.is_up() && !iface.is_loopback())
+    {
+        for ipnetw in &iface.ips {
+            if (ipnetw.is_ipv4() && web_sock.ip().is_ipv4())
+                || (ipnetw.is_ipv6() && web_sock.ip().is_ipv6())
+            {
+                // filtering ipv6 unicast requires nightly or more fiddling,
+                // lets wait for nightlies to stabilize...
+                ips.push(ipnetw.ip())
+            }
+        }
+    }
+    ips
+}
@@ -94,23 +94,291 @@ function fresh_canvas() {
     return canvas;
 }
 
-class Rect {
-    x: number;
-    y: number;
-    w: number;
-    h: number;
+// The type is generated from protocol.rs, tsc fails if the value does not match it.
+const PROTOCOL_VERSION: typeof Protocol.PROTOCOL_VERSION = 2;
+
+// Types of the messages exchanged with the server are declared in the generated protocol.d.ts.
+
+let server_info: Protocol.ServerInfo;
+
+function describe_error(err: Protocol.WeylusError): string {
+    switch (err.code) {
+        case "UInputNotAccessible":
+            // detail contains instructions on how to make /dev/uinput accessible
+            return "Weylus can not access /dev/uinput on the host.\n\n" + err.detail;
+        case "ScreenCastDenied":
+            return "Screen sharing was declined on the host, press refresh to ask again.";
+        case "CapturableGone":
+            return "The selected window or screen is no longer available, please select another one.";
+        case "EncoderInitFailed":
+            return "Failed to start the video encoder on the host: " + err.detail;
+        default:
+            return err.detail ? err.detail : err.code;
+    }
+}
+
+/* Minimal MessagePack encoder used to send input events as binary websocket frames.
+ *
+ * Only the subset of MessagePack required for MessageInbound is implemented. Structs are encoded
+ * as arrays holding their fields in the order they are declared in protocol.rs, which the server
+ * accepts just like maps but saves sending the field names with every event.
+ */
+class MsgPackEncoder {
+    buf: Uint8Array;
+    view: DataView;
+    pos: number;
+
+    constructor() {
+        this.buf = new Uint8Array(256);
+        this.view = new DataView(this.buf.buffer);
+        this.pos = 0;
+    }
+
+    reserve(n: number) {
+        if (this.pos + n <= this.buf.length)
+            return;
+        let buf = new Uint8Array(Math.max(2 * this.buf.length, this.pos + n));
+        buf.set(this.buf);
+        this.buf = buf;
+        this.view = new DataView(this.buf.buffer);
+    }
+
+    u8(v: number) {
+        this.reserve(1);
+        this.view.setUint8(this.pos, v);
+        this.pos += 1;
+    }
+
+    u16(v: number) {
+        this.reserve(2);
+        this.view.setUint16(this.pos, v);
+        this.pos += 2;
+    }
+
+    u32(v: number) {
+        this.reserve(4);
+        this.view.setUint32(this.pos, v);
+        this.pos += 4;
+    }
+
+    number(v: number) {
+        if (Number.isSafeInteger(v)) {
+            if (v >= 0) {
+                if (v < 0x80) {
+                    this.u8(v);
+                } else if (v < 0x100) {
+                    this.u8(0xcc);
+                    this.u8(v);
+                } else if (v < 0x10000) {
+                    this.u8(0xcd);
+                    this.u16(v);
+                } else if (v < 0x100000000) {
+                    this.u8(0xce);
+                    this.u32(v);
+                } else {
+                    this.u8(0xcf);
+                    this.u32(Math.floor(v / 0x100000000));
+                    this.u32(v >>> 0);
+                }
+            } else {
+                if (v >= -0x20) {
+                    this.u8(v & 0xff);
+                } else if (v >= -0x80) {
+                    this.u8(0xd0);
+                    this.u8(v & 0xff);
+                } else if (v >= -0x8000) {
+                    this.u8(0xd1);
+                    this.u16(v & 0xffff);
+                } else if (v >= -0x80000000) {
+                    this.u8(0xd2);
+                    this.u32(v >>> 0);
+                } else {
+                    this.u8(0xcb);
+                    this.reserve(8);
+                    this.view.setFloat64(this.pos, v);
+                    this.pos += 8;
+                }
+            }
+        } else {
+            this.u8(0xcb);
+            this.reserve(8);
+            this.view.setFloat64(this.pos, v);
+            this.pos += 8;
+        }
+    }
+
+    string(v: string) {
+        let bytes = new TextEncoder().encode(v);
+        let n = bytes.length;
+        if (n < 0x20) {
+            this.u8(0xa0 | n);
+        } else if (n < 0x100) {
+            this.u8(0xd9);
+            this.u8(n);
+        } else if (n < 0x10000) {
+            this.u8(0xda);
+            this.u16(n);
+        } else {
+            this.u8(0xdb);
+            this.u32(n);
+        }
+        this.reserve(n);
+        this.buf.set(bytes, this.pos);
+        this.pos += n;
+    }
+
+    length(n: number, fix: number, l16: number, l32: number) {
+        if (n < 0x10) {
+            this.u8(fix | n);
+        } else if (n < 0x10000) {
+            this.u8(l16);
+            this.u16(n);
+        } else {
+            this.u8(l32);
+            this.u32(n);
+        }
+    }
+
+    encode(v: any) {
+        if (v === null || v === undefined) {
+            this.u8(0xc0);
+        } else if (typeof v === "boolean") {
+            this.u8(v ? 0xc3 : 0xc2);
+        } else if (typeof v === "number") {
+            this.number(v);
+        } else if (typeof v === "string") {
+            this.string(v);
+        } else if (Array.isArray(v)) {
+            this.length(v.length, 0x90, 0xdc, 0xdd);
+            for (let e of v)
+                this.encode(e);
+        } else {
+            let fields: string[] = v.constructor && v.constructor.fields;
+            if (fields) {
+                this.length(fields.length, 0x90, 0xdc, 0xdd);
+                for (let f of fields)
+                    this.encode(v[f]);
+            } else {
+                let entries = Object.entries(v);
+                this.length(entries.length, 0x80, 0xde, 0xdf);
+                for (let [key, value] of entries) {
+                    this.string(key);
+                    this.encode(value);
+                }
+            }
+        }
+    }
+
+    finish(): Uint8Array {
+        return this.buf.subarray(0, this.pos);
+    }
 }
 
-class CustomInputAreas {
-    mouse: Rect;
-    touch: Rect;
-    pen: Rect;
+// Wire format requested from the server, inbound messages are sent as binary frames if this is
+// anything but "json".
+const wire_format = "msgpack";
+
+function send_input(webSocket: WebSocket, msg: Protocol.MessageInbound) {
+    // prefer the WebRTC data channel once it is open, it does not stall on packet loss
+    let channel = webrtc && webrtc.channel.readyState == "open" ? webrtc.channel : null;
+    if (wire_format == "msgpack") {
+        let encoder = new MsgPackEncoder();
+        encoder.encode(msg);
+        if (channel)
+            channel.send(encoder.finish());
+        else
+            webSocket.send(encoder.finish());
+    } else {
+        if (channel)
+            channel.send(JSON.stringify(msg));
+        else
+            webSocket.send(JSON.stringify(msg));
+    }
 }
 
+/* Optional WebRTC transport: video is received as RTP and input is sent over a data channel.
+ * Signalling goes over the websocket, until the peer connection is established video keeps
+ * arriving as fragmented mp4 over the websocket.
+ */
+class WebRtcClient {
+    webSocket: WebSocket;
+    pc: RTCPeerConnection;
+    channel: RTCDataChannel;
+    // candidates received before the answer can not be added yet
+    pending_candidates: RTCIceCandidateInit[];
+    has_answer: boolean;
+
+    constructor(webSocket: WebSocket, video: HTMLVideoElement) {
+        this.webSocket = webSocket;
+        this.pending_candidates = [];
+        this.has_answer = false;
+        this.pc = new RTCPeerConnection();
+        this.pc.addTransceiver("video", { direction: "recvonly" });
+        this.channel = this.pc.createDataChannel("input", { ordered: true });
+        this.channel.binaryType = "arraybuffer";
+        let stream = new MediaStream();
+        this.pc.ontrack = (e) => stream.addTrack(e.track);
+        this.pc.onconnectionstatechange = () => {
+            log(LogLevel.INFO, "WebRTC connection state: " + this.pc.connectionState);
+            // srcObject takes precedence over the MSE source set in handle_messages
+            if (this.connected())
+                video.srcObject = stream;
+        };
+        this.pc.onicecandidate = (e) => {
+            if (!e.candidate)
+                return;
+            this.send_signal({
+                "IceCandidate": {
+                    candidate: e.candidate.candidate,
+                    sdp_mid: e.candidate.sdpMid,
+                    sdp_mline_index: e.candidate.sdpMLineIndex,
+                }
+            });
+        };
+        this.pc.createOffer().then((offer) => this.pc.setLocalDescription(offer)).then(
+            () => this.send_signal({ "Offer": this.pc.localDescription.sdp })
+        ).catch((err) => log(LogLevel.ERROR, "Failed to create WebRTC offer: " + err));
+    }
+
+    send_signal(signal: Protocol.WebRtcSignal) {
+        this.webSocket.send(JSON.stringify({ "WebRtcSignal": signal }));
+    }
+
+    onSignal(signal: Protocol.WebRtcSignal) {
+        if ("Answer" in signal) {
+            this.pc.setRemoteDescription({ type: "answer", sdp: signal["Answer"] }).then(() => {
+                this.has_answer = true;
+                for (let candidate of this.pending_candidates)
+                    this.pc.addIceCandidate(candidate);
+                this.pending_candidates = [];
+            }).catch((err) => log(LogLevel.ERROR, "Failed to set WebRTC answer: " + err));
+        } else if ("IceCandidate" in signal) {
+            let c = signal["IceCandidate"];
+            let candidate = { candidate: c.candidate, sdpMid: c.sdp_mid, sdpMLineIndex: c.sdp_mline_index };
+            if (this.has_answer)
+                this.pc.addIceCandidate(candidate);
+            else
+                this.pending_candidates.push(candidate);
+        }
+    }
+
+    connected(): boolean {
+        return this.pc.connectionState == "connected";
+    }
+
+    close() {
+        this.channel.close();
+        this.pc.close();
+    }
+}
+
+let webrtc: WebRtcClient = null;
+
 class Settings {
     webSocket: WebSocket;
     checks: Map<string, HTMLInputElement>;
     capturable_select: HTMLSelectElement;
+    capturable_list_received: boolean;
     frame_rate_input: HTMLInputElement;
     frame_rate_output: HTMLOutputElement;
     scale_video_input: HTMLInputElement;
@@ -119,7 +387,7 @@ class Settings {
     check_aggressive_seek: HTMLInputElement;
     client_name_input: HTMLInputElement;
     visible: boolean;
-    custom_input_areas: CustomInputAreas;
+    custom_input_areas: Protocol.CustomInputAreas;
     settings: HTMLElement;
 
     constructor(webSocket: WebSocket) {
@@ -191,6 +459,16 @@ class Settings {
             this.save_settings();
         };
 
+        this.checks.get("webrtc").onchange = (e) => {
+            this.save_settings();
+            this.toggle_webrtc((e.target as HTMLInputElement).checked);
+        };
+
+        this.checks.get("clipboard_sync").onchange = (e) => {
+            this.save_settings();
+            clipboard_sync.enable((e.target as HTMLInputElement).checked);
+        };
+
         this.checks.get("enable_video").onchange = (e) => {
             let enabled = (e.target as HTMLInputElement).checked;
             document.getElementById("video").classList.toggle("vanish", !enabled);
@@ -227,6 +505,15 @@ class Settings {
         let upd_server_config = () => { this.save_settings(); this.send_server_config() };
         this.checks.get("uinput_support").onchange = upd_server_config;
         this.checks.get("capture_cursor").onchange = upd_server_config;
+        this.checks.get("enable_audio").onchange = (e) => {
+            if (!(e.target as HTMLInputElement).checked)
+                audio_player.stop();
+            upd_server_config();
+        };
+        this.checks.get("stream_cursor").onchange = (e) => {
+            cursor_overlay.enable((e.target as HTMLInputElement).checked);
+            upd_server_config();
+        };
         this.scale_video_input.onchange = upd_server_config;
         this.client_name_input.onchange = upd_server_config;
         this.frame_rate_input.onchange = upd_server_config;
@@ -239,18 +526,22 @@ class Settings {
     }
 
     send_server_config() {
-        let config = new Object(null);
-        config["capturable_id"] = Number(this.capturable_select.value);
-        for (const key of [
-            "uinput_support",
-            "capture_cursor"])
-            config[key] = this.checks.get(key).checked;
+        // nothing to configure until a capturable has been selected from the list sent by the server
+        if (this.capturable_select.value === "")
+            return;
         let [w, h] = calc_max_video_resolution(this.scale_video_input.valueAsNumber);
-        config["max_width"] = w;
-        config["max_height"] = h;
-        config["frame_rate"] = frame_rate_scale(this.frame_rate_input.valueAsNumber);
+        let config: Protocol.ClientConfiguration = {
+            capturable_id: this.capturable_select.value,
+            uinput_support: this.checks.get("uinput_support").checked,
+            capture_cursor: this.checks.get("capture_cursor").checked,
+            stream_cursor: this.checks.get("stream_cursor").checked,
+            audio: this.checks.get("enable_audio").checked,
+            max_width: w,
+            max_height: h,
+            frame_rate: frame_rate_scale(this.frame_rate_input.valueAsNumber),
+        };
         if (this.client_name_input.value)
-            config["client_name"] = this.client_name_input.value;
+            config.client_name = this.client_name_input.value;
         this.webSocket.send(JSON.stringify({ "Config": config }));
     }
 
@@ -355,25 +646,106 @@ class Settings {
         this.visible = !this.visible;
     }
 
-    onCapturableList(window_names: string[]) {
+    onCapturableList(capturables: Protocol.CapturableInfo[]) {
         let current_selection = undefined;
         if (this.capturable_select.selectedOptions[0])
-            current_selection = this.capturable_select.selectedOptions[0].textContent;
-        let new_index: number;
+            current_selection = this.capturable_select.value;
+        let found = false;
         this.capturable_select.innerText = "";
-        window_names.forEach((name, i) => {
+        capturables.forEach((capturable) => {
             let option = document.createElement("option");
-            option.value = String(i);
-            option.innerText = name;
+            option.value = capturable.id;
+            option.innerText = capturable.name;
             this.capturable_select.appendChild(option);
-            if (name === current_selection)
-                new_index = i;
+            if (capturable.id === current_selection)
+                found = true;
         });
-        if (new_index !== undefined)
-            this.capturable_select.value = String(new_index);
+        if (found)
+            this.capturable_select.value = current_selection;
         else if (current_selection)
             // Can't find the window, so don't select anything
             this.capturable_select.value = "";
+        else if (!this.capturable_list_received && capturables.length > 0) {
+            // first list received, start with the first capturable
+            this.capturable_select.value = capturables[0].id;
+            this.send_server_config();
+        }
+        this.capturable_list_received = true;
+    }
+
+    onServerInfo(info: Protocol.ServerInfo) {
+        server_info = info;
+        log(LogLevel.INFO, "Connected to Weylus " + info.version + " on " + info.platform
+            + " (protocol version " + info.protocol_version + "), encoders: "
+            + info.encoders.join(", ") + ", features: " + info.features.join(", "));
+        if (info.view_only)
+            log(LogLevel.INFO, "This client is view-only, input is disabled.");
+        let uinput = this.checks.get("uinput_support");
+        if (!info.input_backends.includes("UInputDevice")) {
+            uinput.checked = false;
+            uinput.disabled = true;
+        }
+        if (!info.features.includes("custom_input_areas")) {
+            document.getElementById("custom_input_areas").classList.add("hide");
+            this.checks.get("enable_custom_input_areas").checked = false;
+        }
+        let check_webrtc = this.checks.get("webrtc");
+        if (!info.features.includes("webrtc") || !("RTCPeerConnection" in window)) {
+            check_webrtc.checked = false;
+            check_webrtc.disabled = true;
+        }
+        this.toggle_webrtc(check_webrtc.checked);
+        if (info.features.includes("audio"))
+            document.getElementById("audio_option").classList.remove("hide");
+        else
+            this.checks.get("enable_audio").checked = false;
+        let check_stream_cursor = this.checks.get("stream_cursor");
+        if (info.features.includes("stream_cursor"))
+            document.getElementById("stream_cursor_option").classList.remove("hide");
+        else
+            check_stream_cursor.checked = false;
+        cursor_overlay.enable(check_stream_cursor.checked);
+        if (info.features.includes("clipboard")) {
+            document.getElementById("clipboard_section").classList.remove("hide");
+            if (this.checks.get("clipboard_sync").checked)
+                clipboard_sync.enable(true);
+        }
+
+        if (info.session) {
+            // kept for the lifetime of the tab, so reloading the page resumes the session
+            sessionStorage.setItem("session_token", info.session.token);
+            // uploads are disabled until the host accepted the client if approval is required
+            (document.getElementById("upload_files") as HTMLInputElement).disabled = false;
+            if (info.session.resumed) {
+                this.onSessionResumed(info.session);
+                return;
+            }
+        }
+        this.webSocket.send('"GetCapturableList"');
+    }
+
+    onSessionResumed(session: Protocol.SessionInfo) {
+        log(LogLevel.INFO, "Resumed previous session.");
+        // the server sends the list of capturables on its own, make sure the capturable still in
+        // use stays selected and no new configuration is sent
+        if (session.capturable_id) {
+            let option = document.createElement("option");
+            option.value = session.capturable_id;
+            this.capturable_select.appendChild(option);
+            this.capturable_select.value = session.capturable_id;
+        }
+        this.capturable_list_received = true;
+        if (this.video_enabled() && !document.hidden)
+            this.webSocket.send('"ResumeVideo"');
+    }
+
+    toggle_webrtc(enabled: boolean) {
+        if (webrtc) {
+            webrtc.close();
+            webrtc = null;
+        }
+        if (enabled)
+            webrtc = new WebRtcClient(this.webSocket, document.getElementById("video") as HTMLVideoElement);
     }
 
     toggle_energysaving(energysaving: boolean) {
@@ -403,12 +775,12 @@ let settings: Settings;
 let debug_overlay: HTMLElement;
 let last_pointer_data: Object;
 
-class PEvent {
-    event_type: string;
+class PEvent implements Protocol.PointerEvent {
+    event_type: Protocol.PointerEventType;
     pointer_id: number;
     timestamp: number;
     is_primary: boolean;
-    pointer_type: string;
+    pointer_type: Protocol.PointerType;
     button: number;
     buttons: number;
     x: number;
@@ -422,13 +794,34 @@ class PEvent {
     width: number;
     height: number;
 
+    // field order of PointerEvent in protocol.rs
+    static fields = [
+        "event_type",
+        "pointer_id",
+        "timestamp",
+        "is_primary",
+        "pointer_type",
+        "button",
+        "buttons",
+        "x",
+        "y",
+        "movement_x",
+        "movement_y",
+        "pressure",
+        "tilt_x",
+        "tilt_y",
+        "twist",
+        "width",
+        "height",
+    ];
+
     constructor(eventType: string, event: PointerEvent, targetRect: DOMRect) {
         let diag_len = Math.sqrt(targetRect.width * targetRect.width + targetRect.height * targetRect.height)
-        this.event_type = eventType.toString();
+        this.event_type = eventType as Protocol.PointerEventType;
         this.pointer_id = event.pointerId;
         this.timestamp = Math.round(event.timeStamp * 1000);
         this.is_primary = event.isPrimary;
-        this.pointer_type = event.pointerType;
+        this.pointer_type = event.pointerType as Protocol.PointerType;
         let btn = event.button;
         // for some reason the secondary and auxiliary buttons are ordered differently for
         // the button and buttons properties
@@ -443,7 +836,7 @@ class PEvent {
         let x_scale = 1;
         let y_scale = 1;
         if (settings.checks.get("enable_custom_input_areas").checked) {
-            let custom_input_area: Rect = null;
+            let custom_input_area: Protocol.Rect = null;
             if (event.pointerType == "mouse") {
                 custom_input_area = settings.custom_input_areas.mouse;
             } else if (event.pointerType == "touch") {
@@ -471,11 +864,14 @@ class PEvent {
     }
 }
 
-class WEvent {
+class WEvent implements Protocol.WheelEvent {
     dx: number;
     dy: number;
     timestamp: number;
 
+    // field order of WheelEvent in protocol.rs
+    static fields = ["dx", "dy", "timestamp"];
+
     constructor(event: WheelEvent) {
         /* The WheelEvent can have different scrolling modes that affect how much scrolling
          * should be done. Unfortunately there is not always a way to accurately convert the scroll
@@ -691,6 +1087,9 @@ class PointerHandler {
     pointerTypes: string[];
 
     constructor(webSocket: WebSocket) {
+        // the server ignores input from view-only clients
+        if (server_info && server_info.view_only)
+            return;
         let video = document.getElementById("video");
         let canvas = document.getElementById("canvas");
         this.webSocket = webSocket;
@@ -737,7 +1136,7 @@ class PointerHandler {
 
         for (let elem of [video, canvas]) {
             elem.onwheel = (e) => {
-                this.webSocket.send(JSON.stringify({ "WheelEvent": new WEvent(e) }));
+                send_input(this.webSocket, { "WheelEvent": new WEvent(e) });
             }
         }
     }
@@ -805,17 +1204,23 @@ class PointerHandler {
         if (this.pointerTypes.includes(event.pointerType)) {
             let rect = (event.target as HTMLElement).getBoundingClientRect();
             const events = event_type === "pointermove" && typeof event.getCoalescedEvents === 'function' ? event.getCoalescedEvents() : [event];
-            for (let event of events) {
-                this.webSocket.send(
-                    JSON.stringify(
-                        {
-                            "PointerEvent": new PEvent(
-                                event_type,
-                                event,
-                                rect
-                            )
-                        }
-                    )
+            if (event.pointerType != "touch" && events.length > 0)
+                cursor_overlay.predict(new PEvent(event_type, events[events.length - 1], rect));
+            if (events.length > 1) {
+                send_input(
+                    this.webSocket,
+                    { "PointerEvents": events.map((e) => new PEvent(event_type, e, rect)) }
+                );
+            } else if (events.length == 1) {
+                send_input(
+                    this.webSocket,
+                    {
+                        "PointerEvent": new PEvent(
+                            event_type,
+                            events[0],
+                            rect
+                        )
+                    }
                 );
             }
             if (settings.visible) {
@@ -825,8 +1230,8 @@ class PointerHandler {
     }
 }
 
-class KEvent {
-    event_type: string;
+class KEvent implements Protocol.KeyboardEvent {
+    event_type: Protocol.KeyboardEventType;
     code: string;
     key: string;
     location: number;
@@ -835,7 +1240,10 @@ class KEvent {
     shift: boolean;
     meta: boolean;
 
-    constructor(event_type: string, event: KeyboardEvent) {
+    // field order of KeyboardEvent in protocol.rs
+    static fields = ["event_type", "code", "key", "location", "alt", "ctrl", "shift", "meta"];
+
+    constructor(event_type: Protocol.KeyboardEventType, event: KeyboardEvent) {
         this.event_type = event_type;
         this.code = event.code;
         this.key = event.key;
@@ -851,6 +1259,8 @@ class KeyboardHandler {
     webSocket: WebSocket;
 
     constructor(webSocket: WebSocket) {
+        if (server_info && server_info.view_only)
+            return;
         this.webSocket = webSocket;
 
         let d = document;
@@ -884,16 +1294,387 @@ class KeyboardHandler {
             e.stopPropagation();
             return false;
         };
+
+        // Text entered here is sent as is instead of as key presses, so on-screen keyboards,
+        // IMEs and dictation work regardless of the keyboard layout of the host.
+        let text_input = document.getElementById("text_input") as HTMLInputElement;
+        let send_text = (text: string) => {
+            if (text)
+                send_input(this.webSocket, { "TextInput": text });
+            text_input.value = "";
+        };
+        text_input.addEventListener("input", (e: InputEvent) => {
+            // composed text is sent once the composition ends
+            if (e.isComposing || e.inputType != "insertText")
+                return;
+            send_text(e.data);
+        });
+        text_input.addEventListener("compositionend", (e: CompositionEvent) => send_text(e.data));
+        text_input.onkeydown = (e) => {
+            // the field is empty unless text is being composed, so forward editing keys
+            if (text_input.value == "" && (e.key == "Backspace" || e.key == "Enter")) {
+                this.onEvent(e, "down");
+                this.onEvent(new KeyboardEvent("keyup", e), "up");
+                return false;
+            }
+            return true;
+        };
     }
 
-    onEvent(event: KeyboardEvent, event_type: string) {
-        this.webSocket.send(JSON.stringify({ "KeyboardEvent": new KEvent(event_type, event) }));
+    onEvent(event: KeyboardEvent, event_type: Protocol.KeyboardEventType) {
+        send_input(this.webSocket, { "KeyboardEvent": new KEvent(event_type, event) });
         event.preventDefault();
         event.stopPropagation();
         return false;
     }
 }
 
+/* Measures the round-trip time via Ping/Pong and estimates the time between capturing a frame on
+ * the host and displaying it, both are shown in the stats and reported to the server.
+ */
+class LatencyMonitor {
+    webSocket: WebSocket;
+    rtt_out: HTMLOutputElement;
+    video_latency_out: HTMLOutputElement;
+    ping_id: number;
+    rtt: number;
+    // add this to the local clock to get the server's clock
+    clock_offset: number;
+    last_frame_info: Protocol.FrameInfo;
+    video_latency: number;
+
+    constructor(webSocket: WebSocket, video: HTMLVideoElement) {
+        this.webSocket = webSocket;
+        this.rtt_out = document.getElementById("rtt") as HTMLOutputElement;
+        this.video_latency_out = document.getElementById("video_latency") as HTMLOutputElement;
+        this.ping_id = 0;
+        this.rtt = 0;
+        this.clock_offset = 0;
+        this.last_frame_info = null;
+        this.video_latency = 0;
+        if ("requestVideoFrameCallback" in video) {
+            let on_frame = () => {
+                this.on_video_frame();
+                (video as any).requestVideoFrameCallback(on_frame);
+            };
+            (video as any).requestVideoFrameCallback(on_frame);
+        }
+        setInterval(() => this.ping(), 2000);
+    }
+
+    ping() {
+        if (this.webSocket.readyState != WebSocket.OPEN)
+            return;
+        this.ping_id += 1;
+        this.webSocket.send(JSON.stringify({ "Ping": { id: this.ping_id, timestamp: Date.now() } }));
+    }
+
+    onPing(ping: Protocol.Ping) {
+        this.webSocket.send(JSON.stringify({
+            "Pong": { id: ping.id, timestamp: ping.timestamp, peer_timestamp: Date.now() }
+        }));
+    }
+
+    onPong(pong: Protocol.Pong) {
+        // ignore answers to older pings
+        if (pong.id != this.ping_id)
+            return;
+        let now = Date.now();
+        this.rtt = now - pong.timestamp;
+        this.clock_offset = pong.peer_timestamp + this.rtt / 2 - now;
+        this.rtt_out.value = Math.round(this.rtt) + " ms";
+        this.webSocket.send(JSON.stringify({
+            "LatencyReport": { rtt: this.rtt, video_latency: this.video_latency }
+        }));
+    }
+
+    onFrameInfo(info: Protocol.FrameInfo) {
+        this.last_frame_info = info;
+    }
+
+    on_video_frame() {
+        // This is only an estimate as the frame shown is assumed to be the last one announced.
+        if (!this.last_frame_info)
+            return;
+        this.video_latency = Date.now() + this.clock_offset - this.last_frame_info.capture_timestamp;
+        this.video_latency_out.value = Math.round(this.video_latency) + " ms";
+    }
+}
+
+let latency_monitor: LatencyMonitor;
+
+/* Keeps the clipboard in sync with the host. Whatever is copied on the host is shown in the
+ * settings and, if the browser permits it, copied to the local clipboard. Text entered or images
+ * pasted into the settings are sent to the host.
+ */
+class ClipboardSync {
+    webSocket: WebSocket;
+    text: HTMLTextAreaElement;
+    image: HTMLImageElement;
+
+    constructor(webSocket: WebSocket) {
+        this.webSocket = webSocket;
+        this.text = document.getElementById("clipboard_text") as HTMLTextAreaElement;
+        this.image = document.getElementById("clipboard_image") as HTMLImageElement;
+        this.text.onpaste = (e) => this.onPaste(e);
+        document.getElementById("clipboard_send").onclick = () => this.send({ Text: this.text.value });
+    }
+
+    enable(enabled: boolean) {
+        this.webSocket.send(JSON.stringify({ "SyncClipboard": enabled }));
+    }
+
+    send(content: Protocol.ClipboardContent) {
+        this.show(content);
+        this.webSocket.send(JSON.stringify({ "ClipboardSet": content }));
+    }
+
+    onPaste(e: ClipboardEvent) {
+        // text is simply pasted into the textarea, images are sent right away
+        let items = e.clipboardData.items;
+        for (let i = 0; i < items.length; ++i) {
+            if (items[i].type != "image/png")
+                continue;
+            e.preventDefault();
+            let reader = new FileReader();
+            reader.onload = () => {
+                // strip "data:image/png;base64,"
+                let png = (reader.result as string).split(",")[1];
+                this.send({ Png: png });
+            };
+            reader.readAsDataURL(items[i].getAsFile());
+            return;
+        }
+    }
+
+    onClipboardChanged(content: Protocol.ClipboardContent) {
+        this.show(content);
+        // requires a secure context and fails if the page is not focused
+        if (!navigator.clipboard)
+            return;
+        let copy: Promise<void>;
+        if ("Text" in content)
+            copy = navigator.clipboard.writeText(content.Text);
+        else if ("ClipboardItem" in window)
+            copy = fetch(this.image.src)
+                .then((response) => response.blob())
+                .then((blob) => navigator.clipboard.write([new ClipboardItem({ "image/png": blob })]));
+        if (copy)
+            copy.catch((err) => log(LogLevel.DEBUG, "Failed to copy to local clipboard: " + err));
+    }
+
+    show(content: Protocol.ClipboardContent) {
+        if ("Text" in content) {
+            this.text.value = content.Text;
+            this.image.classList.add("hide");
+            this.image.removeAttribute("src");
+        } else {
+            this.image.src = "data:image/png;base64," + content.Png;
+            this.image.classList.remove("hide");
+        }
+    }
+}
+
+let clipboard_sync: ClipboardSync;
+
+/* Draws the cursor streamed by the host on top of the video, so that it moves smoothly even if
+ * the video has a low frame rate. Images are cached by their hash as the host sends each image only
+ * once.
+ */
+class CursorOverlay {
+    img: HTMLImageElement;
+    video: HTMLVideoElement;
+    enabled = false;
+    images = new Map<string, string>();
+    shape: Protocol.CursorShape = null;
+    position: Protocol.CursorPosition = null;
+    last_prediction = 0;
+
+    constructor(video: HTMLVideoElement) {
+        this.img = document.getElementById("cursor") as HTMLImageElement;
+        this.video = video;
+        window.addEventListener("resize", () => this.update());
+    }
+
+    enable(enabled: boolean) {
+        this.enabled = enabled;
+        this.update();
+    }
+
+    onCursorShape(shape: Protocol.CursorShape) {
+        if (shape.png)
+            this.images.set(shape.hash, "data:image/png;base64," + shape.png);
+        let src = this.images.get(shape.hash);
+        if (!src) {
+            log(LogLevel.WARN, "Got unknown cursor image: " + shape.hash);
+            return;
+        }
+        this.shape = shape;
+        this.img.src = src;
+        this.img.style.width = shape.width + "px";
+        this.img.style.height = shape.height + "px";
+        this.update();
+    }
+
+    onCursorPosition(position: Protocol.CursorPosition) {
+        // positions reported by the host lag behind local input and would make the cursor jump back
+        if (performance.now() - this.last_prediction < 200)
+            return;
+        this.position = position;
+        this.update();
+    }
+
+    // move the cursor right away instead of waiting for the host to report the new position
+    predict(event: PEvent) {
+        this.last_prediction = performance.now();
+        this.position = { x: event.x, y: event.y };
+        this.update();
+    }
+
+    update() {
+        let p = this.position;
+        let visible = this.enabled && this.shape && p && !this.video.classList.contains("vanish")
+            && p.x >= 0 && p.x <= 1 && p.y >= 0 && p.y <= 1;
+        this.img.classList.toggle("hide", !visible);
+        if (!visible)
+            return;
+        let rect = this.video.getBoundingClientRect();
+        this.img.style.left = (rect.left + p.x * rect.width - this.shape.hotspot_x) + "px";
+        this.img.style.top = (rect.top + p.y * rect.height - this.shape.hotspot_y) + "px";
+    }
+}
+
+let cursor_overlay: CursorOverlay;
+
+/* Plays the host's audio, which is streamed as fragmented MP4 alongside the video but kept in a
+ * separate media element so that either can be enabled on its own.
+ */
+class AudioPlayer {
+    audio: HTMLAudioElement;
+    mediaSource: MediaSource = null;
+    sourceBuffer: SourceBuffer = null;
+    queue: Uint8Array[] = [];
+
+    // in seconds
+    static MAX_DELAY = 0.5;
+    static MAX_BUFFER_LENGTH = 10;
+
+    constructor() {
+        this.audio = document.getElementById("audio") as HTMLAudioElement;
+        this.audio.disableRemotePlayback = true;
+        // browsers only allow playing audio once the user interacted with the page
+        document.addEventListener("pointerdown", () => this.play());
+    }
+
+    onNewAudio() {
+        let MS = window.ManagedMediaSource ? window.ManagedMediaSource : window.MediaSource;
+        let mediaSource = new MS();
+        this.mediaSource = mediaSource;
+        this.sourceBuffer = null;
+        this.queue = [];
+        this.audio.src = URL.createObjectURL(mediaSource);
+        mediaSource.addEventListener("sourceopen", (_) => {
+            let mimeType = 'audio/mp4; codecs="mp4a.40.2"';
+            if (!MS.isTypeSupported(mimeType)) {
+                log(LogLevel.WARN, "This browser can not play audio of type: " + mimeType);
+                return;
+            }
+            this.sourceBuffer = mediaSource.addSourceBuffer(mimeType);
+            this.sourceBuffer.addEventListener("updateend", () => this.append());
+            this.append();
+        });
+        this.play();
+    }
+
+    onAudioData(data: Uint8Array) {
+        if (!this.mediaSource)
+            return;
+        this.queue.push(data);
+        this.append();
+    }
+
+    append() {
+        let sourceBuffer = this.sourceBuffer;
+        if (!sourceBuffer || sourceBuffer.updating || this.queue.length == 0
+            || this.mediaSource.readyState != "open")
+            return;
+        let buffered = sourceBuffer.buffered;
+        if (buffered.length) {
+            let end = buffered.end(buffered.length - 1);
+            // skip ahead if playback fell behind, for example after the tab was in the background
+            if (end - this.audio.currentTime > AudioPlayer.MAX_DELAY)
+                this.audio.currentTime = end - AudioPlayer.MAX_DELAY / 2;
+            if (end - buffered.start(0) > AudioPlayer.MAX_BUFFER_LENGTH) {
+                // this triggers updateend once finished, which appends the queued data
+                sourceBuffer.remove(0, end - AudioPlayer.MAX_BUFFER_LENGTH / 2);
+                return;
+            }
+        }
+        try {
+            sourceBuffer.appendBuffer(this.queue.shift());
+        } catch (err) {
+            log(LogLevel.DEBUG, "Error appending audio to sourceBuffer: " + err);
+        }
+    }
+
+    play() {
+        if (this.mediaSource && this.audio.paused)
+            this.audio.play().catch((err) => log(LogLevel.DEBUG, "Failed to play audio: " + err));
+    }
+
+    stop() {
+        this.mediaSource = null;
+        this.sourceBuffer = null;
+        this.queue = [];
+        this.audio.removeAttribute("src");
+        this.audio.load();
+    }
+}
+
+let audio_player: AudioPlayer;
+
+/* Uploads files picked in the settings to the host, which stores them in its upload directory. */
+class Uploader {
+    input: HTMLInputElement;
+    status: HTMLOutputElement;
+
+    constructor() {
+        this.input = document.getElementById("upload_files") as HTMLInputElement;
+        this.status = document.getElementById("upload_status") as HTMLOutputElement;
+        this.input.onchange = () => {
+            let files = Array.from(this.input.files);
+            // allows picking the same file again
+            this.input.value = "";
+            this.upload(files);
+        };
+    }
+
+    async upload(files: File[]) {
+        for (let i = 0; i < files.length; ++i) {
+            let file = files[i];
+            this.status.value = "Uploading " + file.name + " (" + (i + 1) + "/" + files.length + ")...";
+            let params = new URLSearchParams();
+            params.set("name", file.name);
+            // proves that the host accepted this client
+            let session_token = sessionStorage.getItem("session_token");
+            if (session_token)
+                params.set("session", session_token);
+            try {
+                let response = await fetch("/upload?" + params.toString(), { method: "POST", body: file });
+                let text = await response.text();
+                if (!response.ok)
+                    throw text;
+                this.status.value = "Uploaded " + text;
+                log(LogLevel.INFO, "Uploaded " + file.name + " as " + text);
+            } catch (err) {
+                this.status.value = "Failed to upload " + file.name + ": " + err;
+                log(LogLevel.ERROR, "Failed to upload " + file.name + ": " + err);
+                return;
+            }
+        }
+    }
+}
+
 function frame_rate_stats() {
     let t = performance.now();
     let fps = Math.round(frame_count / (t - last_fps_calc) * 10000) / 10;
@@ -913,6 +1694,8 @@ function handle_messages(
     let mediaSource: MediaSource = null;
     let sourceBuffer: SourceBuffer = null;
     let queue = [];
+    // set by AudioData, which announces that the next binary message is audio
+    let next_binary_is_audio = false;
     const MAX_BUFFER_LENGTH = 20;  // In seconds
     function upd_buf() {
         if (sourceBuffer == null)
@@ -944,9 +1727,13 @@ function handle_messages(
             let msg = JSON.parse(event.data);
             if (typeof msg == "string") {
                 if (msg == "NewVideo") {
+                    // video is received via WebRTC
+                    if (webrtc && webrtc.connected())
+                        return;
                     let MS = window.ManagedMediaSource ? window.ManagedMediaSource : window.MediaSource;
                     mediaSource = new MS();
                     sourceBuffer = null;
+                    video.srcObject = null;
                     video.src = URL.createObjectURL(mediaSource);
                     mediaSource.addEventListener("sourceopen", (_) => {
                         let mimeType = 'video/mp4; codecs="avc1.4D403D"';
@@ -958,16 +1745,51 @@ function handle_messages(
                         if (sourceBuffer.onerror)
                             sourceBuffer.onerror = () => settings.send_server_config();
                     })
+                } else if (msg == "NewAudio") {
+                    audio_player.onNewAudio();
+                } else if (msg == "AudioData") {
+                    next_binary_is_audio = true;
                 } else if (msg == "ConfigOk") {
                     onConfigOk();
                 }
             } else if (typeof msg == "object") {
-                if ("CapturableList" in msg)
+                if ("ServerInfo" in msg)
+                    settings.onServerInfo(msg["ServerInfo"]);
+                else if ("CapturableList" in msg)
                     onCapturableList(msg["CapturableList"]);
-                else if ("Error" in msg)
-                    alert(msg["Error"]);
+                else if ("Error" in msg) {
+                    let err = msg["Error"] as Protocol.WeylusError;
+                    log(LogLevel.ERROR, err.code + ": " + err.detail);
+                    // do not try again with every config update
+                    if (err.code == "AudioFailed") {
+                        settings.checks.get("enable_audio").checked = false;
+                        settings.save_settings();
+                    }
+                    alert(describe_error(err));
+                }
                 else if ("ConfigError" in msg) {
-                    onConfigError(msg["ConfigError"]);
+                    let err = msg["ConfigError"] as Protocol.WeylusError;
+                    if (err.code == "CapturableGone")
+                        webSocket.send('"GetCapturableList"');
+                    onConfigError(describe_error(err));
+                } else if ("FrameInfo" in msg) {
+                    let info = msg["FrameInfo"] as Protocol.FrameInfo;
+                    latency_monitor.onFrameInfo(info);
+                    (document.getElementById("skipped_frames") as HTMLOutputElement).value =
+                        info.skipped_frames.toString();
+                } else if ("Ping" in msg)
+                    latency_monitor.onPing(msg["Ping"]);
+                else if ("Pong" in msg)
+                    latency_monitor.onPong(msg["Pong"]);
+                else if ("ClipboardChanged" in msg)
+                    clipboard_sync.onClipboardChanged(msg["ClipboardChanged"]);
+                else if ("CursorPosition" in msg)
+                    cursor_overlay.onCursorPosition(msg["CursorPosition"]);
+                else if ("CursorShape" in msg)
+                    cursor_overlay.onCursorShape(msg["CursorShape"]);
+                else if ("WebRtcSignal" in msg) {
+                    if (webrtc)
+                        webrtc.onSignal(msg["WebRtcSignal"]);
                 } else if ("CustomInputAreas" in msg) {
                     settings.custom_input_areas = msg["CustomInputAreas"];
                     settings.checks.get("enable_custom_input_areas").checked = true;
@@ -978,6 +1800,12 @@ function handle_messages(
             return;
         }
 
+        if (next_binary_is_audio) {
+            next_binary_is_audio = false;
+            audio_player.onAudioData(new Uint8Array(event.data));
+            return;
+        }
+
         // not a string -> got a video frame
         queue.push(event.data);
         upd_buf();
@@ -1026,9 +1854,14 @@ function init() {
     check_apis();
 
     let protocol = document.location.protocol == "https:" ? "wss://" : "ws://";
+    let params = new URLSearchParams(window.location.search);
+    params.set("wire_format", wire_format);
+    let session_token = sessionStorage.getItem("session_token");
+    if (session_token)
+        params.set("session", session_token);
     let webSocket = new WebSocket(
         protocol + window.location.hostname + ":" +
-        window.location.port + "/ws" + window.location.search
+        window.location.port + "/ws?" + params.toString()
     );
     webSocket.binaryType = "arraybuffer";
 
@@ -1036,6 +1869,11 @@ function init() {
     settings = new Settings(webSocket);
 
     let video = document.getElementById("video") as HTMLVideoElement;
+    latency_monitor = new LatencyMonitor(webSocket, video);
+    clipboard_sync = new ClipboardSync(webSocket);
+    cursor_overlay = new CursorOverlay(video);
+    audio_player = new AudioPlayer();
+    new Uploader();
     let canvas = document.getElementById("canvas") as HTMLCanvasElement;
 
     video.oncontextmenu = function(event) {
@@ -1094,16 +1932,20 @@ function init() {
         }
     },
         (err) => alert(err),
-        (window_names) => settings.onCapturableList(window_names)
+        (capturables) => settings.onCapturableList(capturables)
     );
     window.onunload = () => { webSocket.close(); }
     webSocket.onopen = function(event) {
-        webSocket.send('"GetCapturableList"');
+        // the list of capturables is requested once the ServerInfo has been received, unless the
+        // session has been resumed
+        let hello: Protocol.ClientHello = { protocol_version: PROTOCOL_VERSION };
+        // shown to the host if it has to accept new clients
+        if (settings.client_name_input.value)
+            hello.client_name = settings.client_name_input.value;
+        webSocket.send(JSON.stringify({ "Hello": hello }));
         if (!settings.video_enabled())
             webSocket.send('"PauseVideo"');
 
-        settings.send_server_config();
-
         document.onvisibilitychange = () => {
             if (document.hidden) {
                 webSocket.send('"PauseVideo"');
@@ -1125,4 +1967,6 @@ function stretch_video() {
         let scale = Math.min(document.body.clientWidth / video.clientWidth, document.body.clientHeight / video.clientHeight);
         video.style.transform = "scale(" + scale + ")";
     }
+    if (cursor_overlay)
+        cursor_overlay.update();
 }
@@ -0,0 +1,267 @@
+// Generated from src/protocol.rs, do not edit! See src/protocol_ts.rs on how to update.
+
+declare namespace Protocol {
+    export type MessageInbound =
+        | "GetCapturableList"
+        | "PauseVideo"
+        | "ResumeVideo"
+        | "RestartVideo"
+        | "ChooseCustomInputAreas"
+        | { Hello: ClientHello }
+        | { PointerEvent: PointerEvent }
+        | { PointerEvents: Array<PointerEvent> }
+        | { WheelEvent: WheelEvent }
+        | { KeyboardEvent: KeyboardEvent }
+        | { TextInput: string }
+        | { Config: ClientConfiguration }
+        | { WebRtcSignal: WebRtcSignal }
+        | { Ping: Ping }
+        | { Pong: Pong }
+        | { LatencyReport: LatencyReport }
+        | { ClipboardSet: ClipboardContent }
+        | { SyncClipboard: boolean };
+
+    export type ClientHello = {
+        protocol_version: number;
+        /** Shown to the host if it has to approve new clients. */
+        client_name?: string | null;
+    };
+
+    export type PointerEvent = {
+        event_type: PointerEventType;
+        pointer_id: number;
+        timestamp: number;
+        is_primary: boolean;
+        pointer_type: PointerType;
+        button: number;
+        buttons: number;
+        x: number;
+        y: number;
+        movement_x: number;
+        movement_y: number;
+        pressure: number;
+        tilt_x: number;
+        tilt_y: number;
+        twist: number;
+        width: number;
+        height: number;
+    };
+
+    export type PointerEventType =
+        | "pointerdown"
+        | "pointerup"
+        | "pointercancel"
+        | "pointermove"
+        | "pointerover"
+        | "pointerenter"
+        | "pointerleave"
+        | "pointerout";
+
+    export type PointerType =
+        | ""
+        | "mouse"
+        | "pen"
+        | "touch";
+
+    export type WheelEvent = {
+        dx: number;
+        dy: number;
+        timestamp: number;
+    };
+
+    export type KeyboardEvent = {
+        event_type: KeyboardEventType;
+        code: string;
+        key: string;
+        location: number;
+        alt: boolean;
+        ctrl: boolean;
+        shift: boolean;
+        meta: boolean;
+    };
+
+    export type KeyboardEventType =
+        | "down"
+        | "up"
+        | "repeat";
+
+    export type ClientConfiguration = {
+        uinput_support: boolean;
+        /** Stable identifier of the capturable as sent in `CapturableList`. */
+        capturable_id: string;
+        capture_cursor: boolean;
+        /** Send the cursor as `CursorPosition` and `CursorShape` instead of drawing it into the video. */
+        stream_cursor: boolean;
+        /** Stream the host's audio via `NewAudio` and `AudioData`. */
+        audio: boolean;
+        max_width: number;
+        max_height: number;
+        client_name?: string | null;
+        frame_rate: number;
+    };
+
+    /** Signalling used to negotiate a WebRTC session, relayed over the regular connection. */
+    export type WebRtcSignal =
+        | { Offer: string }
+        | { Answer: string }
+        | { IceCandidate: IceCandidate };
+
+    export type IceCandidate = {
+        candidate: string;
+        sdp_mid?: string | null;
+        sdp_mline_index?: number | null;
+    };
+
+    /** Timestamps are milliseconds since the UNIX epoch on the clock of the side that took them. */
+    export type Ping = {
+        id: number;
+        timestamp: number;
+    };
+
+    /**
+     * Answer to a Ping, `timestamp` is copied from the Ping, `peer_timestamp` is taken by the side
+     * answering and allows to estimate the offset between both clocks.
+     */
+    export type Pong = {
+        id: number;
+        timestamp: number;
+        peer_timestamp: number;
+    };
+
+    /** Latencies measured by the client in milliseconds. */
+    export type LatencyReport = {
+        rtt: number;
+        /** Time between capturing a frame and displaying it. */
+        video_latency: number;
+    };
+
+    /** Content of a clipboard, only text and images are synchronized. */
+    export type ClipboardContent =
+        | { Text: string }
+        | { Png: string };
+
+    export type MessageOutbound =
+        | "NewVideo"
+        | "ConfigOk"
+        | { ServerInfo: ServerInfo }
+        | { CapturableList: Array<CapturableInfo> }
+        | { CustomInputAreas: CustomInputAreas }
+        | { ConfigError: WeylusError }
+        | { Error: WeylusError }
+        | { WebRtcSignal: WebRtcSignal }
+        | { Ping: Ping }
+        | { Pong: Pong }
+        | { FrameInfo: FrameInfo }
+        | { ClipboardChanged: ClipboardContent }
+        | { CursorPosition: CursorPosition }
+        | { CursorShape: CursorShape }
+        | "NewAudio"
+        | "AudioData";
+
+    export type ServerInfo = {
+        protocol_version: number;
+        version: string;
+        platform: string;
+        input_backends: Array<InputDeviceType>;
+        encoders: Array<string>;
+        features: Array<string>;
+        /** Input sent by view-only clients is ignored. */
+        view_only: boolean;
+        /** Only set if the connection supports resuming the session after reconnecting. */
+        session?: SessionInfo | null;
+    };
+
+    export type InputDeviceType =
+        | "AutoPilotDevice"
+        | "UInputDevice";
+
+    /**
+     * A session can be resumed within a grace period after the connection is lost by reconnecting
+     * with its token, input devices and screen capture are kept alive in the meantime.
+     */
+    export type SessionInfo = {
+        token: string;
+        /** True if this connection resumed an existing session. */
+        resumed: boolean;
+        /** Id of the capturable currently in use by the resumed session. */
+        capturable_id?: string | null;
+    };
+
+    export type CapturableInfo = {
+        id: string;
+        name: string;
+    };
+
+    export type CustomInputAreas = {
+        mouse?: Rect | null;
+        touch?: Rect | null;
+        pen?: Rect | null;
+    };
+
+    export type Rect = {
+        x: number;
+        y: number;
+        w: number;
+        h: number;
+    };
+
+    export type WeylusError = {
+        code: ErrorCode;
+        /** Human readable description of the error. */
+        detail?: string | null;
+    };
+
+    /**
+     * Stable error codes that allow clients to react to errors, for example by showing instructions
+     * on how to fix them. Clients match on the variant names, so do not rename them.
+     */
+    export type ErrorCode =
+        | "Generic"
+        | "InvalidMessage"
+        | "ProtocolVersionMismatch"
+        | "UInputNotAccessible"
+        | "InputDeviceFailed"
+        | "CapturableGone"
+        | "ScreenCastDenied"
+        | "ScreenCastFailed"
+        | "EncoderInitFailed"
+        | "WebRtcNotSupported"
+        | "WebRtcFailed"
+        | "ClipboardFailed"
+        | "CursorStreamFailed"
+        | "AudioFailed"
+        | "ConnectionRejected";
+
+    /** Sent right before the video data of a frame. */
+    export type FrameInfo = {
+        sequence: number;
+        /** Time the frame was captured in milliseconds since the UNIX epoch. */
+        capture_timestamp: number;
+        /** Number of frames not sent so far because the connection was congested. */
+        skipped_frames: number;
+    };
+
+    /**
+     * Position of the cursor relative to the capturable, values outside of [0, 1] mean the cursor is
+     * outside of the capturable.
+     */
+    export type CursorPosition = {
+        x: number;
+        y: number;
+    };
+
+    /** Image of the cursor, sent whenever the cursor's shape changes. */
+    export type CursorShape = {
+        /** Hash of the image, identifies images the client has already received. */
+        hash: string;
+        width: number;
+        height: number;
+        hotspot_x: number;
+        hotspot_y: number;
+        /** Base64 encoded PNG image, omitted if an image with the same hash has been sent before. */
+        png?: string | null;
+    };
+
+    /** Version of the protocol spoken by the server. */
+    export const PROTOCOL_VERSION: 2;
+}